@@ -0,0 +1,34 @@
+#![no_main]
+
+use bytes::BytesMut;
+use extfg_sigma::codec::{ResyncStrategy, SigmaClientProtocol};
+use libfuzzer_sys::fuzz_target;
+
+// The first byte selects the resync strategy (bit 0) and the size of chunks the rest of the
+// input is delivered in (bits 1-7), the way a socket delivers a stream.
+fuzz_target!(|data: &[u8]| {
+    let (mode, stream) = match data.split_first() {
+        Some(v) => v,
+        None => return,
+    };
+    let resync = match mode & 1 {
+        0 => ResyncStrategy::Fail,
+        _ => ResyncStrategy::SkipToNextHeader,
+    };
+    let mut codec = SigmaClientProtocol::new().with_resync(resync);
+    let mut buf = BytesMut::new();
+
+    for chunk in stream.chunks((mode >> 1) as usize + 1) {
+        buf.extend_from_slice(chunk);
+        loop {
+            match codec.decode_response(&mut buf) {
+                Ok(Some(resp)) => {
+                    // Decoded leniently, so it may have grown beyond what encodes
+                    let _ = resp.encode();
+                }
+                Ok(None) => break,
+                Err(_) => return,
+            }
+        }
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use extfg_sigma::FeeData;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(fee) = FeeData::from_slice(data) {
+        fee.encode().expect("decoded fee data must encode");
+    }
+});
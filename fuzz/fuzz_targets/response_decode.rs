@@ -0,0 +1,16 @@
+#![no_main]
+
+use bytes::Bytes;
+use extfg_sigma::{DecodeOptions, SigmaResponse};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let data = Bytes::copy_from_slice(data);
+    // Lossy decoding of non-ASCII data may grow the message beyond what encodes
+    if let Ok((resp, _)) = SigmaResponse::decode_with(data.clone(), &DecodeOptions::lenient()) {
+        let _ = resp.encode();
+    }
+    if let Ok((resp, _)) = SigmaResponse::decode_with(data, &DecodeOptions::strict()) {
+        resp.encode().expect("strictly decoded response must encode");
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use bytes::{Bytes, BytesMut};
+use extfg_sigma::Tag;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(tag) = Tag::decode(Bytes::copy_from_slice(data)) {
+        tag.encode_to_buf(&mut BytesMut::new())
+            .expect("decoded tag must encode");
+    }
+});
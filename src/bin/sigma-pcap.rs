@@ -0,0 +1,41 @@
+//! Prints Sigma exchanges of a pcap or pcapng capture as JSON, PAN and track 2 data masked.
+//!
+//! Usage: `sigma-pcap <capture>`
+//!
+//! Problems with the capture data are reported to stderr.
+
+use std::process::exit;
+
+use extfg_sigma::pcap::Capture;
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(v) if !v.starts_with('-') => v,
+        _ => {
+            eprintln!("Usage: sigma-pcap <capture>");
+            exit(2);
+        }
+    };
+
+    let capture = Capture::read_path(&path).unwrap_or_else(|err| {
+        eprintln!("Failed to read {}: {}", path, err);
+        exit(2);
+    });
+    for err in capture.errors.iter() {
+        eprintln!("{}", err);
+    }
+    for resp in capture.unmatched_responses.iter() {
+        eprintln!(
+            "{} -> {}: response {} without request",
+            resp.src, resp.dst, resp.message.auth_serno
+        );
+    }
+
+    match serde_json::to_string_pretty(&capture.to_json_value()) {
+        Ok(v) => println!("{}", v),
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(2);
+        }
+    }
+}
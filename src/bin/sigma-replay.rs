@@ -0,0 +1,122 @@
+//! Replays a traffic recording against a host and reports responses differing from recorded ones.
+//!
+//! Usage: `sigma-replay <recording> <host:port> [--fast | --speed <factor>] [--timeout <seconds>]`
+//!
+//! Exits with status 1 if any response differs, 2 on incorrect arguments or failures.
+
+use std::convert::TryFrom;
+use std::process::exit;
+use std::time::Duration;
+
+use extfg_sigma::client::{ClientConfig, SigmaClient};
+use extfg_sigma::record::read_records_from_path;
+use extfg_sigma::replay::{exchanges, replay, Timing};
+
+const USAGE: &str =
+    "Usage: sigma-replay <recording> <host:port> [--fast | --speed <factor>] [--timeout <seconds>]";
+
+struct Args {
+    recording: String,
+    address: String,
+    timing: Timing,
+    timeout: Duration,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut positional = Vec::new();
+    let mut timing = Timing::original();
+    let mut timeout = ClientConfig::default().request_timeout;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fast" => timing = Timing::AsFastAsPossible,
+            "--speed" => {
+                let factor = args
+                    .next()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .filter(|v| *v > 0.0)
+                    .ok_or("--speed requires a positive factor")?;
+                timing = Timing::Scaled(factor);
+            }
+            "--timeout" => {
+                let seconds = args
+                    .next()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .ok_or("--timeout requires a number of seconds")?;
+                timeout = Duration::from_secs(seconds);
+            }
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+            _ => positional.push(arg),
+        }
+    }
+
+    match <[String; 2]>::try_from(positional) {
+        Ok([recording, address]) => Ok(Args {
+            recording,
+            address,
+            timing,
+            timeout,
+        }),
+        Err(_) => Err(USAGE.into()),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        exit(2);
+    });
+
+    let records = read_records_from_path(&args.recording).unwrap_or_else(|err| {
+        eprintln!("Failed to read {}: {}", args.recording, err);
+        exit(2);
+    });
+    let exchanges = exchanges(&records).unwrap_or_else(|err| {
+        eprintln!("Failed to decode {}: {}", args.recording, err);
+        exit(2);
+    });
+
+    let config = ClientConfig {
+        request_timeout: args.timeout,
+        ..ClientConfig::default()
+    };
+    let client = SigmaClient::connect(&args.address, config)
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to connect to {}: {}", args.address, err);
+            exit(2);
+        });
+
+    let results = replay(&client, exchanges, args.timing).await;
+    let mut mismatches = 0;
+    for result in results.iter() {
+        let req = &result.exchange.request;
+        let status = match (&result.exchange.response, &result.replayed) {
+            _ if result.matches() => "OK",
+            (None, Ok(_)) => "UNRECORDED",
+            (_, Err(_)) => "FAILED",
+            _ => "DIFF",
+        };
+        println!("{} {} {}", req.mti(), req.auth_serno, status);
+        if let Err(err) = &result.replayed {
+            println!("    {}", err);
+        }
+        for difference in result.differences.iter() {
+            println!(
+                "    {}: {} -> {}",
+                difference.field, difference.recorded, difference.replayed
+            );
+        }
+        if !result.matches() {
+            mismatches += 1;
+        }
+    }
+
+    println!("{} replayed, {} differ", results.len(), mismatches);
+    if mismatches > 0 {
+        exit(1);
+    }
+}
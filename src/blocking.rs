@@ -0,0 +1,362 @@
+//! Blocking framing and client over [`std::io`], for consumers without an async runtime.
+//!
+//! Frames are passed with their 5-digit length header, as produced by
+//! [`SigmaRequest::encode`] and expected by [`SigmaResponse::decode`].
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+
+use crate::{Error, SigmaRequest, SigmaResponse};
+
+const LENGTH_BYTES_COUNT: usize = 5;
+
+/// Errors of blocking framing and [`SigmaClient`] requests.
+#[derive(Debug, thiserror::Error)]
+pub enum BlockingError {
+    #[error(transparent)]
+    ExtfgSigma(#[from] Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Request timed out")]
+    Timeout,
+    #[error("Connection closed")]
+    Closed,
+}
+
+fn frame_length(header: &[u8]) -> Result<usize, Error> {
+    parse_ascii_bytes_lossy!(
+        header,
+        usize,
+        Error::incorrect_field_data("message length", "5 digits")
+    )
+}
+
+/// Reads a single frame, `None` if the reader ends before it starts.
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Bytes>, BlockingError> {
+    let mut header = [0u8; LENGTH_BYTES_COUNT];
+    let mut read = 0;
+    while read < header.len() {
+        match reader.read(&mut header[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+            Ok(n) => read += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    let length = frame_length(&header)?;
+    let mut frame = vec![0u8; LENGTH_BYTES_COUNT + length];
+    frame[..LENGTH_BYTES_COUNT].copy_from_slice(&header);
+    reader.read_exact(&mut frame[LENGTH_BYTES_COUNT..])?;
+    Ok(Some(frame.into()))
+}
+
+/// Writes a single frame and flushes the writer. The length header of the frame must match
+/// its length.
+pub fn write_frame<W: Write>(writer: &mut W, frame: &[u8]) -> Result<(), BlockingError> {
+    let length = frame_length(frame.get(..LENGTH_BYTES_COUNT).unwrap_or_default())?;
+    if frame.len() != LENGTH_BYTES_COUNT + length {
+        return Err(Error::IncorrectData(format!(
+            "Length header {} does not match message length {}",
+            length,
+            frame.len() - LENGTH_BYTES_COUNT
+        ))
+        .into());
+    }
+    writer.write_all(frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads and decodes a single response, `None` if the reader ends before it starts.
+pub fn read_response<R: Read>(reader: &mut R) -> Result<Option<SigmaResponse>, BlockingError> {
+    match read_frame(reader)? {
+        Some(frame) => Ok(Some(SigmaResponse::decode(frame)?)),
+        None => Ok(None),
+    }
+}
+
+pub fn write_request<W: Write>(writer: &mut W, req: &SigmaRequest) -> Result<(), BlockingError> {
+    write_frame(writer, &req.encode()?)
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Time to wait for the connection to be established, per resolved address.
+    pub connect_timeout: Duration,
+    /// Time to wait for the request to be written and the response to arrive.
+    pub request_timeout: Duration,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Synchronous client sending one request at a time over TCP.
+///
+/// Responses with authorization serno other than the one of the current request, e.g. late
+/// responses to timed out requests, are skipped, even if they can not be decoded. Partially
+/// received frames are kept after a timeout, so the connection stays usable; received data is
+/// dropped after an incorrect length header, as frames can not be told apart anymore.
+#[derive(Debug)]
+pub struct SigmaClient {
+    stream: TcpStream,
+    config: ClientConfig,
+    buffer: BytesMut,
+}
+
+impl SigmaClient {
+    pub fn new(stream: TcpStream, config: ClientConfig) -> Self {
+        Self {
+            stream,
+            config,
+            buffer: BytesMut::new(),
+        }
+    }
+
+    /// Connects to the first reachable of given addresses.
+    pub fn connect<A: ToSocketAddrs>(addr: A, config: ClientConfig) -> Result<Self, BlockingError> {
+        let mut last_err = io::Error::new(ErrorKind::InvalidInput, "No addresses to connect to");
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, config.connect_timeout) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    return Ok(Self::new(stream, config));
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err.into())
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Sends request and waits for the response with the same authorization serno.
+    pub fn send(&mut self, req: &SigmaRequest) -> Result<SigmaResponse, BlockingError> {
+        let deadline = Instant::now() + self.config.request_timeout;
+        self.stream
+            .set_write_timeout(Some(self.config.request_timeout))?;
+        write_request(&mut self.stream, req).map_err(timeout)?;
+
+        loop {
+            let frame = match self.next_frame()? {
+                Some(v) => v,
+                None => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(BlockingError::Timeout);
+                    }
+                    self.stream.set_read_timeout(Some(remaining))?;
+                    self.fill_buffer().map_err(timeout)?;
+                    continue;
+                }
+            };
+            let resp = match SigmaResponse::decode(frame.clone()) {
+                Ok(v) => v,
+                Err(err) if frame_serno(&frame) == Some(req.auth_serno) => return Err(err.into()),
+                Err(_) => continue,
+            };
+            if resp.auth_serno == req.auth_serno {
+                return Ok(resp);
+            }
+        }
+    }
+
+    /// Splits a complete frame off the buffer, the buffer is cleared on incorrect length header.
+    fn next_frame(&mut self) -> Result<Option<Bytes>, Error> {
+        if self.buffer.len() < LENGTH_BYTES_COUNT {
+            return Ok(None);
+        }
+        let length = match frame_length(&self.buffer[..LENGTH_BYTES_COUNT]) {
+            Ok(v) => LENGTH_BYTES_COUNT + v,
+            Err(err) => {
+                self.buffer.clear();
+                return Err(err);
+            }
+        };
+        if self.buffer.len() < length {
+            return Ok(None);
+        }
+        Ok(Some(self.buffer.split_to(length).freeze()))
+    }
+
+    fn fill_buffer(&mut self) -> Result<(), BlockingError> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(BlockingError::Closed),
+                Ok(n) => {
+                    self.buffer.extend_from_slice(&chunk[..n]);
+                    return Ok(());
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Authorization serno of a response frame which may not be decodable otherwise.
+fn frame_serno(frame: &[u8]) -> Option<u64> {
+    let serno = frame.get(LENGTH_BYTES_COUNT + 4..LENGTH_BYTES_COUNT + 14)?;
+    std::str::from_utf8(serno).ok()?.trim().parse().ok()
+}
+
+/// Converts timeouts of socket operations into [`BlockingError::Timeout`].
+fn timeout(err: BlockingError) -> BlockingError {
+    match err {
+        BlockingError::Io(err)
+            if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+        {
+            BlockingError::Timeout
+        }
+        err => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn frames() {
+        let req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        let mut data = Vec::new();
+        write_request(&mut data, &req).unwrap();
+        write_frame(&mut data, b"0002401104007040978T\x00\x31\x00\x00\x048100").unwrap();
+
+        let mut reader = Cursor::new(&data);
+        let frame = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(SigmaRequest::decode(frame).unwrap().auth_serno, 1);
+        let resp = read_response(&mut reader).unwrap().unwrap();
+        assert_eq!(resp.reason, 8100);
+        assert!(read_frame(&mut reader).unwrap().is_none());
+
+        assert!(matches!(
+            read_frame(&mut Cursor::new(&b"0x024"[..])),
+            Err(BlockingError::ExtfgSigma(_))
+        ));
+        assert!(matches!(
+            read_frame(&mut Cursor::new(&data[..15])),
+            Err(BlockingError::Io(err)) if err.kind() == ErrorKind::UnexpectedEof
+        ));
+        assert!(matches!(
+            read_frame(&mut Cursor::new(&b"000"[..])),
+            Err(BlockingError::Io(err)) if err.kind() == ErrorKind::UnexpectedEof
+        ));
+        assert!(matches!(
+            write_frame(&mut Vec::new(), b"00005NM01"),
+            Err(BlockingError::ExtfgSigma(Error::IncorrectData(_)))
+        ));
+    }
+
+    #[test]
+    fn client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut io, _) = listener.accept().unwrap();
+            let respond = |io: &mut TcpStream, serno, reason| {
+                let resp = SigmaResponse::new("0110", serno, reason).unwrap();
+                io.write_all(&resp.encode().unwrap()).unwrap();
+            };
+            let read_serno = |io: &mut TcpStream| {
+                let frame = read_frame(io).unwrap().unwrap();
+                SigmaRequest::decode(frame).unwrap().auth_serno
+            };
+
+            // Stale response, then the response split in two writes
+            let serno = read_serno(&mut io);
+            respond(&mut io, 99, 8100);
+            let resp = SigmaResponse::new("0110", serno, 8495).unwrap();
+            let data = resp.encode().unwrap();
+            io.write_all(&data[..7]).unwrap();
+            thread::sleep(Duration::from_millis(20));
+            io.write_all(&data[7..]).unwrap();
+
+            // No response until the next request
+            let late = read_serno(&mut io);
+            let serno = read_serno(&mut io);
+            respond(&mut io, late, 8100);
+            respond(&mut io, serno, 8100);
+        });
+
+        let config = ClientConfig {
+            request_timeout: Duration::from_millis(200),
+            ..ClientConfig::default()
+        };
+        let mut client = SigmaClient::connect(addr, config).unwrap();
+        let req = |serno| SigmaRequest::new("N", "M", "0100", serno).unwrap();
+
+        assert_eq!(client.send(&req(1)).unwrap().reason, 8495);
+        assert!(matches!(client.send(&req(2)), Err(BlockingError::Timeout)));
+        let resp = client.send(&req(3)).unwrap();
+        assert_eq!((resp.auth_serno, resp.reason), (3, 8100));
+
+        server.join().unwrap();
+        assert!(matches!(client.send(&req(4)), Err(BlockingError::Closed)));
+    }
+
+    #[test]
+    fn client_incorrect_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut io, _) = listener.accept().unwrap();
+            let undecodable = |serno: u64| {
+                format!("000240110{:010}X\x00\x31\x00\x00\x048100", serno).into_bytes()
+            };
+            let respond = |io: &mut TcpStream, serno| {
+                let resp = SigmaResponse::new("0110", serno, 8100).unwrap();
+                io.write_all(&resp.encode().unwrap()).unwrap();
+            };
+
+            // Undecodable stale response, then the response
+            read_frame(&mut io).unwrap();
+            io.write_all(&undecodable(99)).unwrap();
+            respond(&mut io, 1);
+            // Undecodable response to the request
+            read_frame(&mut io).unwrap();
+            io.write_all(&undecodable(2)).unwrap();
+            // Incorrect length header
+            read_frame(&mut io).unwrap();
+            io.write_all(b"0x024").unwrap();
+            read_frame(&mut io).unwrap();
+            respond(&mut io, 4);
+        });
+
+        let mut client = SigmaClient::connect(addr, ClientConfig::default()).unwrap();
+        let req = |serno| SigmaRequest::new("N", "M", "0100", serno).unwrap();
+
+        assert_eq!(client.send(&req(1)).unwrap().auth_serno, 1);
+        assert!(matches!(
+            client.send(&req(2)),
+            Err(BlockingError::ExtfgSigma(Error::IncorrectTag(_)))
+        ));
+        assert!(matches!(
+            client.send(&req(3)),
+            Err(BlockingError::ExtfgSigma(Error::IncorrectFieldData { .. }))
+        ));
+        // Data following the incorrect header is dropped, the next request is served
+        assert_eq!(client.send(&req(4)).unwrap().auth_serno, 4);
+
+        server.join().unwrap();
+    }
+}
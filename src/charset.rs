@@ -0,0 +1,232 @@
+//! Single-byte character sets of tag and field text.
+//!
+//! Plain [`SigmaRequest::decode`] reads tags as UTF-8 and ISO fields as raw bytes, so text in
+//! CP1251 or CP866 turns into mojibake. [`CharsetConfig`] names the character set of each tag
+//! and field, it may be applied per message or per connection with
+//! [`SigmaClientProtocol::with_charset`](crate::codec::SigmaClientProtocol::with_charset).
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use encoding_rs::{Encoding, IBM866, WINDOWS_1251};
+
+use crate::raw::{RawField, RawMessage};
+use crate::{Error, IsoFieldData, SigmaRequest, SigmaResponse, Tag};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Charset {
+    /// Invalid sequences are replaced with U+FFFD on decoding, as plain decoding does.
+    #[default]
+    Utf8,
+    /// Windows-1251.
+    Cp1251,
+    /// DOS Cyrillic.
+    Cp866,
+}
+
+impl Charset {
+    fn encoding(&self) -> Option<&'static Encoding> {
+        match self {
+            Self::Utf8 => None,
+            Self::Cp1251 => Some(WINDOWS_1251),
+            Self::Cp866 => Some(IBM866),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "UTF-8",
+            Self::Cp1251 => "CP1251",
+            Self::Cp866 => "CP866",
+        }
+    }
+
+    pub fn decode<'a>(&self, data: &'a [u8]) -> Cow<'a, str> {
+        match self.encoding() {
+            None => String::from_utf8_lossy(data),
+            Some(encoding) => encoding.decode_without_bom_handling(data).0,
+        }
+    }
+
+    /// Encodes text, failing on characters the charset has no bytes for.
+    pub fn encode<'a>(&self, field_name: &str, s: &'a str) -> Result<Cow<'a, [u8]>, Error> {
+        match self.encoding() {
+            None => Ok(Cow::Borrowed(s.as_bytes())),
+            Some(encoding) => match encoding.encode(s) {
+                (_, _, true) => Err(Error::IncorrectFieldData {
+                    field_name: field_name.into(),
+                    should_be: format!("representable in {}", self.name()),
+                }),
+                (v, _, false) => Ok(v),
+            },
+        }
+    }
+}
+
+/// Character sets of tags and ISO fields.
+///
+/// The default charset applies to all tags. ISO fields may carry binary data, so they are
+/// converted only when configured with [`CharsetConfig::with_iso_field`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CharsetConfig {
+    pub default: Charset,
+    pub tags: BTreeMap<u16, Charset>,
+    pub iso_fields: BTreeMap<u16, Charset>,
+}
+
+impl CharsetConfig {
+    pub fn new(default: Charset) -> Self {
+        Self {
+            default,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_tag(mut self, tag: u16, charset: Charset) -> Self {
+        self.tags.insert(tag, charset);
+        self
+    }
+
+    pub fn with_iso_field(mut self, field: u16, charset: Charset) -> Self {
+        self.iso_fields.insert(field, charset);
+        self
+    }
+
+    /// Charset of given tag or field, `None` if its data is kept as is.
+    pub fn charset(&self, tag: &Tag) -> Option<Charset> {
+        match tag {
+            Tag::Regular(i) => Some(self.tags.get(i).copied().unwrap_or(self.default)),
+            Tag::Iso(i) => self.iso_fields.get(i).copied(),
+            Tag::IsoSubfield(..) => None,
+        }
+    }
+
+    /// Re-encodes text fields of the raw message from UTF-8.
+    fn encode_raw(&self, raw: &mut RawMessage) -> Result<Bytes, Error> {
+        for field in raw.fields.iter_mut() {
+            let charset = match self.charset(field.tag()) {
+                Some(Charset::Utf8) | None => continue,
+                Some(v) => v,
+            };
+            let text = String::from_utf8_lossy(&field.data);
+            let data = charset
+                .encode(&field.tag().to_string(), &text)?
+                .into_owned();
+            *field = RawField::new(field.tag().clone(), data)?;
+        }
+        raw.encode()
+    }
+}
+
+impl SigmaRequest {
+    /// Decodes with tag and field text in given charsets.
+    pub fn decode_charset(data: Bytes, charsets: &CharsetConfig) -> Result<Self, Error> {
+        let mut req = Self::decode(data.clone())?;
+        for field in RawMessage::decode_request(data, Default::default())?.fields {
+            match (field.tag(), charsets.charset(field.tag())) {
+                (Tag::Regular(i), Some(charset)) => {
+                    req.tags
+                        .insert(*i, charset.decode(&field.data).into_owned());
+                }
+                (Tag::Iso(i), Some(charset)) => {
+                    let v = IsoFieldData::String(charset.decode(&field.data).into_owned());
+                    req.iso_fields.insert(*i, v);
+                }
+                _ => {}
+            }
+        }
+        Ok(req)
+    }
+
+    /// Encodes with tag and field text in given charsets.
+    pub fn encode_charset(&self, charsets: &CharsetConfig) -> Result<Bytes, Error> {
+        charsets.encode_raw(&mut self.to_raw()?)
+    }
+}
+
+impl SigmaResponse {
+    /// Decodes with text of T0033, T0048 and T0050 in given charsets.
+    pub fn decode_charset(data: Bytes, charsets: &CharsetConfig) -> Result<Self, Error> {
+        let mut resp = Self::decode(data.clone())?;
+        for field in RawMessage::decode_response(data, Default::default())?.fields {
+            let charset = match charsets.charset(field.tag()) {
+                Some(v) => v,
+                None => continue,
+            };
+            let text = || Some(charset.decode(&field.data).into_owned());
+            match field.tag() {
+                Tag::Regular(33) => resp.xri = text(),
+                Tag::Regular(48) => resp.adata = text(),
+                Tag::Regular(50) => resp.supdata = text(),
+                _ => {}
+            }
+        }
+        Ok(resp)
+    }
+
+    /// Encodes with text of T0033, T0048 and T0050 in given charsets.
+    pub fn encode_charset(&self, charsets: &CharsetConfig) -> Result<Bytes, Error> {
+        charsets.encode_raw(&mut self.to_raw()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charsets() {
+        assert_eq!(
+            Charset::Cp1251.decode(b"\xcf\xf0\xe8\xe2\xe5\xf2"),
+            "Привет"
+        );
+        assert_eq!(Charset::Cp866.decode(b"\x8f\xe0\xa8\xa2\xa5\xe2"), "Привет");
+        assert_eq!(Charset::Utf8.decode(b"\xff"), "\u{fffd}");
+
+        assert_eq!(
+            Charset::Cp1251.encode("T0014", "Ёж").unwrap(),
+            &b"\xa8\xe6"[..]
+        );
+        assert_eq!(
+            Charset::Cp866.encode("T0014", "€"),
+            Err(Error::incorrect_field_data(
+                "T0014",
+                "representable in CP866"
+            ))
+        );
+    }
+
+    #[test]
+    fn request() {
+        let charsets = CharsetConfig::new(Charset::Cp1251)
+            .with_tag(18, Charset::Utf8)
+            .with_iso_field(43, Charset::Cp1251);
+
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        req.tags.insert(14, "Магазин".into());
+        req.tags.insert(18, "Y".into());
+        req.iso_fields.insert(43, "Москва".into());
+        req.iso_fields.insert(55, vec![0x9f, 0x26].into());
+
+        let data = req.encode_charset(&charsets).unwrap();
+        assert!(data
+            .windows(7)
+            .any(|w| w == b"\xcc\xe0\xe3\xe0\xe7\xe8\xed"));
+        assert_ne!(SigmaRequest::decode(data.clone()).unwrap(), req);
+        assert_eq!(SigmaRequest::decode_charset(data, &charsets).unwrap(), req);
+    }
+
+    #[test]
+    fn response() {
+        let charsets = CharsetConfig::new(Charset::Cp866);
+        let mut resp = SigmaResponse::new("0110", 1, 8100).unwrap();
+        resp.adata = Some("Отказ".into());
+
+        let data = resp.encode_charset(&charsets).unwrap();
+        assert_eq!(data.len(), resp.encode().unwrap().len() - 5);
+        let decoded = SigmaResponse::decode_charset(data, &charsets).unwrap();
+        assert_eq!(decoded.adata.as_deref(), Some("Отказ"));
+        assert_eq!(decoded.reason, 8100);
+    }
+}
@@ -0,0 +1,593 @@
+//! Asynchronous client over [`SigmaClientProtocol`] framed transport.
+//!
+//! [`SigmaClient`] owns a background task which writes requests, matches incoming responses
+//! with pending requests by authorization serno and keeps an idle connection alive with echo
+//! messages when configured so.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::{sleep_until, timeout, Instant};
+use tokio_util::codec::Framed;
+
+use crate::codec::{ClientProtocolError, SigmaClientProtocol};
+use crate::metrics::Metrics;
+use crate::util::gen_random_short_auth_serno;
+use crate::{SigmaRequest, SigmaResponse};
+
+pub mod pool;
+pub mod reconnect;
+
+/// Errors of [`SigmaClient`] requests.
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error(transparent)]
+    Protocol(#[from] ClientProtocolError),
+    #[error("Request with auth serno {0} is already in flight")]
+    DuplicateSerno(u64),
+    #[error("Request timed out")]
+    Timeout,
+    #[error("Request timed out, reversal was not queued: {0}")]
+    ReversalNotQueued(String),
+    #[error("Connection dropped while request was in flight")]
+    Disconnected,
+    #[error("Connection closed")]
+    Closed,
+    #[error("No available connection")]
+    NoAvailableConnection,
+}
+
+/// State of the connection of [`SigmaClient`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// Idle connection keepalive settings.
+#[derive(Debug, Clone)]
+pub struct KeepaliveConfig {
+    /// Period without any traffic after which an echo is sent.
+    pub idle: Duration,
+    /// Time to wait for the echo response before the connection is considered dead.
+    pub timeout: Duration,
+    /// Source of echo requests.
+    pub source: String,
+}
+
+impl KeepaliveConfig {
+    pub fn new(idle: Duration, timeout: Duration) -> Self {
+        Self {
+            idle,
+            timeout,
+            source: "X".into(),
+        }
+    }
+}
+
+/// Destination of reversals automatically generated for timed out requests.
+pub trait ReversalQueue: std::fmt::Debug + Send + Sync {
+    fn queue_reversal(
+        &self,
+        reversal: SigmaRequest,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+impl ReversalQueue for mpsc::UnboundedSender<SigmaRequest> {
+    fn queue_reversal(
+        &self,
+        reversal: SigmaRequest,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.send(reversal)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "saf")]
+impl ReversalQueue for crate::saf::SafQueue {
+    fn queue_reversal(
+        &self,
+        reversal: SigmaRequest,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.enqueue(reversal)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Time to wait for the response to each request.
+    pub request_timeout: Duration,
+    /// Keepalive settings, no echo messages are sent if absent.
+    pub keepalive: Option<KeepaliveConfig>,
+    /// Codec used for the connection.
+    pub codec: SigmaClientProtocol,
+    /// Queue for reversals of timed out requests, no reversals are generated if absent.
+    pub reversals: Option<Arc<dyn ReversalQueue>>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(30),
+            keepalive: None,
+            codec: SigmaClientProtocol::default(),
+            reversals: None,
+        }
+    }
+}
+
+type ResponseSender = oneshot::Sender<Result<SigmaResponse, ClientError>>;
+
+enum Command {
+    Send(SigmaRequest, ResponseSender),
+    Cancel(u64),
+}
+
+/// Handle of a single Sigma connection; cloned handles share the connection.
+#[derive(Debug, Clone)]
+pub struct SigmaClient {
+    commands: mpsc::UnboundedSender<Command>,
+    state: watch::Receiver<ConnectionState>,
+    request_timeout: Duration,
+    reversals: Option<Arc<dyn ReversalQueue>>,
+}
+
+impl SigmaClient {
+    /// Starts a client over given transport. Must be called within a tokio runtime.
+    pub fn new<T>(io: T, config: ClientConfig) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (commands, commands_rx) = mpsc::unbounded_channel();
+        let (state_tx, state) = watch::channel(ConnectionState::Connected);
+        let request_timeout = config.request_timeout;
+        let reversals = config.reversals;
+        let metrics = config.codec.metrics().cloned();
+
+        tokio::spawn(run_connection(
+            Framed::new(io, config.codec),
+            commands_rx,
+            state_tx,
+            config.keepalive,
+            metrics,
+        ));
+
+        Self {
+            commands,
+            state,
+            request_timeout,
+            reversals,
+        }
+    }
+
+    /// Connects to given address over TCP and starts a client.
+    pub async fn connect<A: ToSocketAddrs>(
+        addr: A,
+        config: ClientConfig,
+    ) -> Result<Self, std::io::Error> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(Self::new(stream, config))
+    }
+
+    /// Sends request and waits for the response with the same authorization serno.
+    ///
+    /// If reversal queue is configured, reversal of a timed out authorization or financial
+    /// request is put into it.
+    pub async fn send(&self, req: SigmaRequest) -> Result<SigmaResponse, ClientError> {
+        let serno = req.auth_serno;
+        let original = match self.reversals.is_some() && req.needs_reversal() {
+            true => Some(req.clone()),
+            false => None,
+        };
+        let (tx, rx) = oneshot::channel();
+        self.commands
+            .send(Command::Send(req, tx))
+            .map_err(|_| ClientError::Closed)?;
+
+        match timeout(self.request_timeout, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(ClientError::Closed),
+            Err(_) => {
+                let _ = self.commands.send(Command::Cancel(serno));
+                match (self.reversals.as_ref(), original) {
+                    (Some(reversals), Some(original)) => {
+                        let reversal = original
+                            .to_reversal()
+                            .map_err(|err| ClientError::ReversalNotQueued(err.to_string()))?;
+                        reversals
+                            .queue_reversal(reversal)
+                            .map_err(|err| ClientError::ReversalNotQueued(err.to_string()))?;
+                        Err(ClientError::Timeout)
+                    }
+                    _ => Err(ClientError::Timeout),
+                }
+            }
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.state() == ConnectionState::Connected
+    }
+
+    /// Watches connection state changes.
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Waits until the connection is closed.
+    pub async fn closed(&self) {
+        let mut state = self.state.clone();
+        while *state.borrow_and_update() == ConnectionState::Connected {
+            if state.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Request waiting for its response.
+struct Pending {
+    reply: ResponseSender,
+    mti: String,
+    sent: Instant,
+}
+
+struct Keepalive {
+    config: KeepaliveConfig,
+    last_activity: Instant,
+    echo: Option<(u64, Instant)>,
+}
+
+impl Keepalive {
+    fn deadline(&self) -> Instant {
+        match self.echo {
+            Some((_, deadline)) => deadline,
+            None => self.last_activity + self.config.idle,
+        }
+    }
+}
+
+async fn run_connection<T>(
+    mut framed: Framed<T, SigmaClientProtocol>,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    state: watch::Sender<ConnectionState>,
+    keepalive: Option<KeepaliveConfig>,
+    metrics: Option<Arc<dyn Metrics>>,
+) where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut pending: HashMap<u64, Pending> = HashMap::new();
+    let mut keepalive = keepalive.map(|config| Keepalive {
+        config,
+        last_activity: Instant::now(),
+        echo: None,
+    });
+    let far_future = Instant::now() + Duration::from_secs(86400 * 365);
+
+    loop {
+        let deadline = keepalive
+            .as_ref()
+            .map(Keepalive::deadline)
+            .unwrap_or(far_future);
+
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Send(req, reply)) => {
+                    let serno = req.auth_serno;
+                    let echo = keepalive.as_ref().and_then(|k| k.echo).map(|(serno, _)| serno);
+                    if pending.contains_key(&serno) || echo == Some(serno) {
+                        let _ = reply.send(Err(ClientError::DuplicateSerno(serno)));
+                        continue;
+                    }
+                    let mti = req.mti().to_string();
+                    match framed.send(req).await {
+                        Ok(()) => {}
+                        // Request could not be encoded, nothing was written
+                        Err(err @ ClientProtocolError::ExtfgSigma(_)) => {
+                            let _ = reply.send(Err(err.into()));
+                            continue;
+                        }
+                        Err(err) => {
+                            let _ = reply.send(Err(err.into()));
+                            break;
+                        }
+                    }
+                    pending.insert(serno, Pending { reply, mti, sent: Instant::now() });
+                    if let Some(keepalive) = keepalive.as_mut() {
+                        keepalive.last_activity = Instant::now();
+                    }
+                }
+                Some(Command::Cancel(serno)) => {
+                    pending.remove(&serno);
+                }
+                None => break,
+            },
+            frame = framed.next() => match frame {
+                Some(Ok(resp)) => {
+                    if let Some(keepalive) = keepalive.as_mut() {
+                        keepalive.last_activity = Instant::now();
+                        if matches!(keepalive.echo, Some((serno, _)) if serno == resp.auth_serno) {
+                            keepalive.echo = None;
+                            continue;
+                        }
+                    }
+                    if let Some(request) = pending.remove(&resp.auth_serno) {
+                        if let Some(metrics) = &metrics {
+                            metrics.response_latency(&request.mti, request.sent.elapsed());
+                        }
+                        let _ = request.reply.send(Ok(resp));
+                    }
+                }
+                Some(Err(_err)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(error = %_err, "Connection dropped after receive error");
+                    break;
+                }
+                None => break,
+            },
+            _ = sleep_until(deadline), if keepalive.is_some() => {
+                let keepalive = match keepalive.as_mut() {
+                    Some(v) => v,
+                    None => continue,
+                };
+                if keepalive.echo.is_some() {
+                    // Echo was not answered in time
+                    break;
+                }
+                // Serno of a pending request would take its response for the echo reply
+                let serno = loop {
+                    let serno = gen_random_short_auth_serno();
+                    if !pending.contains_key(&serno) {
+                        break serno;
+                    }
+                };
+                let echo = match SigmaRequest::echo(&keepalive.config.source, serno) {
+                    Ok(v) => v,
+                    Err(_) => break,
+                };
+                if framed.send(echo).await.is_err() {
+                    break;
+                }
+                keepalive.echo = Some((serno, Instant::now() + keepalive.config.timeout));
+            }
+        }
+    }
+
+    let _ = state.send(ConnectionState::Disconnected);
+    for (_, request) in pending.drain() {
+        let _ = request.reply.send(Err(ClientError::Disconnected));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    use super::*;
+
+    async fn read_request(io: &mut DuplexStream) -> SigmaRequest {
+        let mut len = [0u8; 5];
+        io.read_exact(&mut len).await.unwrap();
+        let msg_len = std::str::from_utf8(&len).unwrap().parse::<usize>().unwrap();
+        let mut data = vec![0u8; msg_len];
+        io.read_exact(&mut data).await.unwrap();
+
+        let mut frame = len.to_vec();
+        frame.extend_from_slice(&data);
+        SigmaRequest::decode(Bytes::from(frame)).unwrap()
+    }
+
+    async fn write_response(io: &mut DuplexStream, mti: &str, serno: u64, reason: u32) {
+        let resp = SigmaResponse::new(mti, serno, reason).unwrap();
+        io.write_all(&resp.encode().unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn send_and_match_response() {
+        let (client_io, mut server_io) = duplex(4096);
+        let client = SigmaClient::new(client_io, ClientConfig::default());
+
+        let server = tokio::spawn(async move {
+            let first = read_request(&mut server_io).await;
+            let second = read_request(&mut server_io).await;
+            // Respond in reverse order
+            write_response(&mut server_io, "0110", second.auth_serno, 8100).await;
+            write_response(&mut server_io, "0110", first.auth_serno, 8495).await;
+            server_io
+        });
+
+        let (first, second) = tokio::join!(
+            client.send(SigmaRequest::new("N", "M", "0100", 1).unwrap()),
+            client.send(SigmaRequest::new("N", "M", "0100", 2).unwrap()),
+        );
+        assert_eq!(first.unwrap().reason, 8495);
+        assert_eq!(second.unwrap().reason, 8100);
+        assert!(client.is_alive());
+        drop(server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn metrics() {
+        let metrics = Arc::new(crate::metrics::CountingMetrics::new());
+        let config = ClientConfig {
+            codec: SigmaClientProtocol::new().with_metrics(metrics.clone()),
+            ..ClientConfig::default()
+        };
+        let (client_io, mut server_io) = duplex(4096);
+        let client = SigmaClient::new(client_io, config);
+
+        let server = tokio::spawn(async move {
+            let req = read_request(&mut server_io).await;
+            write_response(&mut server_io, "0110", req.auth_serno, 8100).await;
+            server_io
+        });
+
+        let req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        let length = req.encode().unwrap().len() as u64;
+        client.send(req).await.unwrap();
+
+        assert_eq!(metrics.frames_encoded(), 1);
+        assert_eq!(metrics.bytes_encoded(), length);
+        assert_eq!(metrics.frames_decoded(), 1);
+        assert_eq!(metrics.reasons().get(&8100), Some(&1));
+        assert_eq!(metrics.latencies()["0100"].count, 1);
+        assert!(metrics.decode_errors().is_empty());
+        drop(server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn unencodable_request() {
+        let (client_io, mut server_io) = duplex(4096);
+        let client = SigmaClient::new(client_io, ClientConfig::default());
+
+        let server = tokio::spawn(async move {
+            let req = read_request(&mut server_io).await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            write_response(&mut server_io, "0110", req.auth_serno, 8100).await;
+            server_io
+        });
+
+        let mut bad = SigmaRequest::new("N", "M", "0100", 2).unwrap();
+        bad.tags.insert(18, "x".repeat(10000));
+        let (pending, bad) = tokio::join!(
+            client.send(SigmaRequest::new("N", "M", "0100", 1).unwrap()),
+            async {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                client.send(bad).await
+            },
+        );
+        assert!(matches!(
+            bad,
+            Err(ClientError::Protocol(ClientProtocolError::ExtfgSigma(_)))
+        ));
+        assert_eq!(pending.unwrap().reason, 8100);
+        assert!(client.is_alive());
+        drop(server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn request_timeout() {
+        let (client_io, _server_io) = duplex(4096);
+        let client = SigmaClient::new(
+            client_io,
+            ClientConfig {
+                request_timeout: Duration::from_millis(20),
+                ..Default::default()
+            },
+        );
+
+        let result = client
+            .send(SigmaRequest::new("N", "M", "0100", 1).unwrap())
+            .await;
+        assert!(matches!(result, Err(ClientError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn reversal_on_timeout() {
+        let (client_io, _server_io) = duplex(4096);
+        let (reversals, mut reversals_rx) = mpsc::unbounded_channel();
+        let client = SigmaClient::new(
+            client_io,
+            ClientConfig {
+                request_timeout: Duration::from_millis(20),
+                reversals: Some(Arc::new(reversals)),
+                ..Default::default()
+            },
+        );
+
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        req.iso_fields.insert(11, "100250".into());
+        let result = client.send(req).await;
+        assert!(matches!(result, Err(ClientError::Timeout)));
+
+        let reversal = reversals_rx.try_recv().unwrap();
+        assert_eq!(reversal.mti(), "0400");
+        assert_eq!(reversal.iso_fields.get(&11).unwrap(), "100250");
+
+        // Advices are not reversed
+        let result = client
+            .send(SigmaRequest::new("Y", "M", "0120", 2).unwrap())
+            .await;
+        assert!(matches!(result, Err(ClientError::Timeout)));
+        assert!(reversals_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn connection_closed() {
+        let (client_io, mut server_io) = duplex(4096);
+        let client = SigmaClient::new(client_io, ClientConfig::default());
+
+        let server = tokio::spawn(async move {
+            read_request(&mut server_io).await;
+        });
+
+        let result = client
+            .send(SigmaRequest::new("N", "M", "0100", 1).unwrap())
+            .await;
+        server.await.unwrap();
+        assert!(matches!(result, Err(ClientError::Disconnected)));
+        client.closed().await;
+        assert_eq!(client.state(), ConnectionState::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn keepalive_echo_answered() {
+        let (client_io, mut server_io) = duplex(4096);
+        let client = SigmaClient::new(
+            client_io,
+            ClientConfig {
+                keepalive: Some(KeepaliveConfig::new(
+                    Duration::from_millis(10),
+                    Duration::from_millis(500),
+                )),
+                ..Default::default()
+            },
+        );
+
+        for _ in 0..2 {
+            let echo = read_request(&mut server_io).await;
+            assert_eq!(echo.mti(), "0800");
+            assert_eq!(echo.iso_fields.get(&70).unwrap(), "301");
+            write_response(&mut server_io, "0810", echo.auth_serno, 0).await;
+        }
+        assert!(client.is_alive());
+    }
+
+    #[tokio::test]
+    async fn keepalive_echo_unanswered() {
+        let (client_io, mut server_io) = duplex(4096);
+        let client = SigmaClient::new(
+            client_io,
+            ClientConfig {
+                keepalive: Some(KeepaliveConfig::new(
+                    Duration::from_millis(10),
+                    Duration::from_millis(200),
+                )),
+                ..Default::default()
+            },
+        );
+
+        let echo = read_request(&mut server_io).await;
+        assert_eq!(echo.mti(), "0800");
+        // Request can not take the serno of the outstanding echo
+        let req = SigmaRequest::new("N", "M", "0100", echo.auth_serno).unwrap();
+        assert!(matches!(
+            client.send(req).await,
+            Err(ClientError::DuplicateSerno(serno)) if serno == echo.auth_serno
+        ));
+        client.closed().await;
+        assert!(!client.is_alive());
+    }
+}
@@ -0,0 +1,442 @@
+//! Pool of connections to several Sigma host instances.
+//!
+//! [`SigmaPool`] keeps a number of [`ReconnectingClient`]s to every endpoint, picks one of the
+//! connected ones for every request according to [`Balancing`] and takes an endpoint out of
+//! service for a while after a number of consecutive failures.
+
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::reconnect::{ReconnectConfig, ReconnectingClient};
+use super::{ClientError, ConnectionState};
+use crate::codec::ClientProtocolError;
+use crate::{SigmaRequest, SigmaResponse};
+
+/// Strategy of picking a connection for a request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Balancing {
+    #[default]
+    RoundRobin,
+    LeastInFlight,
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub connections_per_endpoint: usize,
+    pub balancing: Balancing,
+    /// Number of consecutive failed requests after which an endpoint is taken out of service.
+    pub max_failures: u32,
+    /// Time an endpoint stays out of service.
+    pub quarantine: Duration,
+    /// Configuration of every connection.
+    pub reconnect: ReconnectConfig,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            connections_per_endpoint: 1,
+            balancing: Balancing::default(),
+            max_failures: 3,
+            quarantine: Duration::from_secs(10),
+            reconnect: ReconnectConfig::default(),
+        }
+    }
+}
+
+/// Health snapshot of a single endpoint of [`SigmaPool`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointHealth {
+    pub addr: String,
+    /// Number of currently connected connections.
+    pub connected: usize,
+    pub in_flight: usize,
+    pub consecutive_failures: u32,
+    pub in_service: bool,
+}
+
+struct Connection {
+    client: ReconnectingClient,
+    in_flight: AtomicUsize,
+}
+
+struct Endpoint {
+    addr: String,
+    connections: Vec<Connection>,
+    failures: AtomicU32,
+    out_of_service_until: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn in_service(&self) -> bool {
+        match *self.out_of_service_until.lock().unwrap() {
+            Some(v) => v <= Instant::now(),
+            None => true,
+        }
+    }
+
+    /// Checks whether the endpoint may be selected, returning it to service with cleared
+    /// failures once the quarantine is over.
+    fn select(&self) -> bool {
+        let mut until = self.out_of_service_until.lock().unwrap();
+        match *until {
+            Some(v) if v > Instant::now() => false,
+            Some(_) => {
+                *until = None;
+                self.failures.store(0, Ordering::Relaxed);
+                true
+            }
+            None => true,
+        }
+    }
+
+    fn report(&self, success: bool, config: &PoolConfig) {
+        if success {
+            self.failures.store(0, Ordering::Relaxed);
+            return;
+        }
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= config.max_failures {
+            *self.out_of_service_until.lock().unwrap() = Some(Instant::now() + config.quarantine);
+        }
+    }
+}
+
+/// Decrements in-flight counter of a connection when the request is finished or cancelled.
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Load balancing pool of connections to several endpoints.
+#[derive(Clone)]
+pub struct SigmaPool {
+    endpoints: Arc<Vec<Endpoint>>,
+    next: Arc<AtomicUsize>,
+    config: PoolConfig,
+}
+
+impl std::fmt::Debug for SigmaPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigmaPool")
+            .field("health", &self.health())
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl SigmaPool {
+    /// Starts connections to all endpoints. Must be called within a tokio runtime.
+    pub fn connect<I, S>(endpoints: I, config: PoolConfig) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let endpoints = endpoints
+            .into_iter()
+            .map(|addr| {
+                let addr = addr.into();
+                let connections = (0..config.connections_per_endpoint.max(1))
+                    .map(|_| Connection {
+                        client: ReconnectingClient::connect(addr.clone(), config.reconnect.clone()),
+                        in_flight: AtomicUsize::new(0),
+                    })
+                    .collect();
+                Endpoint {
+                    addr,
+                    connections,
+                    failures: AtomicU32::new(0),
+                    out_of_service_until: Mutex::new(None),
+                }
+            })
+            .collect();
+
+        Self {
+            endpoints: Arc::new(endpoints),
+            next: Arc::new(AtomicUsize::new(0)),
+            config,
+        }
+    }
+
+    /// Sends request over one of the available connections.
+    pub async fn send(&self, req: SigmaRequest) -> Result<SigmaResponse, ClientError> {
+        let (endpoint, connection) = self.pick().ok_or(ClientError::NoAvailableConnection)?;
+
+        connection.in_flight.fetch_add(1, Ordering::Relaxed);
+        let _guard = InFlightGuard(&connection.in_flight);
+        let result = connection.client.send(req).await;
+        let failed = match &result {
+            // Request could not be encoded, the endpoint is not to blame
+            Err(ClientError::Protocol(ClientProtocolError::ExtfgSigma(_))) => return result,
+            Err(ClientError::Protocol(_))
+            | Err(ClientError::Timeout)
+            | Err(ClientError::ReversalNotQueued(_))
+            | Err(ClientError::Disconnected)
+            | Err(ClientError::Closed) => true,
+            _ => false,
+        };
+        endpoint.report(!failed, &self.config);
+        result
+    }
+
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| EndpointHealth {
+                addr: endpoint.addr.clone(),
+                connected: endpoint
+                    .connections
+                    .iter()
+                    .filter(|c| c.client.state() == ConnectionState::Connected)
+                    .count(),
+                in_flight: endpoint
+                    .connections
+                    .iter()
+                    .map(|c| c.in_flight.load(Ordering::Relaxed))
+                    .sum(),
+                consecutive_failures: endpoint.failures.load(Ordering::Relaxed),
+                in_service: endpoint.in_service(),
+            })
+            .collect()
+    }
+
+    fn pick(&self) -> Option<(&Endpoint, &Connection)> {
+        let candidates = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.select())
+            .flat_map(|endpoint| {
+                endpoint
+                    .connections
+                    .iter()
+                    .filter(|c| c.client.state() == ConnectionState::Connected)
+                    .map(move |c| (endpoint, c))
+            })
+            .collect::<Vec<_>>();
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        match self.config.balancing {
+            Balancing::RoundRobin => Some(candidates[start % candidates.len()]),
+            Balancing::LeastInFlight => (0..candidates.len())
+                .map(|i| candidates[(start + i) % candidates.len()])
+                .min_by_key(|(_, c)| c.in_flight.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::client::ClientConfig;
+
+    /// Answers every request with the given reason code, optionally after a delay.
+    async fn serve(listener: TcpListener, reason: u32, delay: Duration) {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                loop {
+                    let req = match read_request(&mut stream).await {
+                        Some(v) => v,
+                        None => return,
+                    };
+                    tokio::time::sleep(delay).await;
+                    let resp = SigmaResponse::new("0110", req.auth_serno, reason).unwrap();
+                    if stream.write_all(&resp.encode().unwrap()).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    }
+
+    async fn read_request(io: &mut TcpStream) -> Option<SigmaRequest> {
+        let mut len = [0u8; 5];
+        io.read_exact(&mut len).await.ok()?;
+        let msg_len = std::str::from_utf8(&len).ok()?.parse::<usize>().ok()?;
+        let mut frame = len.to_vec();
+        frame.resize(5 + msg_len, 0);
+        io.read_exact(&mut frame[5..]).await.ok()?;
+        SigmaRequest::decode(Bytes::from(frame)).ok()
+    }
+
+    async fn endpoint(reason: u32, delay: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve(listener, reason, delay));
+        addr
+    }
+
+    async fn wait_connected(pool: &SigmaPool, count: usize) {
+        while pool.health().iter().map(|h| h.connected).sum::<usize>() < count {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    fn request(serno: u64) -> SigmaRequest {
+        SigmaRequest::new("N", "M", "0100", serno).unwrap()
+    }
+
+    #[tokio::test]
+    async fn round_robin() {
+        let first = endpoint(1, Duration::ZERO).await;
+        let second = endpoint(2, Duration::ZERO).await;
+        let pool = SigmaPool::connect(vec![first, second], PoolConfig::default());
+        wait_connected(&pool, 2).await;
+
+        let mut reasons = Vec::new();
+        for serno in 0..4 {
+            reasons.push(pool.send(request(serno)).await.unwrap().reason);
+        }
+        reasons.sort_unstable();
+        assert_eq!(reasons, vec![1, 1, 2, 2]);
+    }
+
+    #[tokio::test]
+    async fn least_in_flight() {
+        let slow = endpoint(1, Duration::from_millis(200)).await;
+        let fast = endpoint(2, Duration::ZERO).await;
+        let pool = SigmaPool::connect(
+            vec![slow, fast],
+            PoolConfig {
+                balancing: Balancing::LeastInFlight,
+                ..Default::default()
+            },
+        );
+        wait_connected(&pool, 2).await;
+
+        // The first request goes to the slow endpoint, the rest avoid it while it is busy
+        let (first, reasons) = tokio::join!(pool.send(request(0)), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let mut reasons = Vec::new();
+            for serno in 1..4 {
+                reasons.push(pool.send(request(serno)).await.unwrap().reason);
+            }
+            reasons
+        });
+        assert_eq!(first.unwrap().reason, 1);
+        assert_eq!(reasons, vec![2, 2, 2]);
+        assert_eq!(pool.health().iter().map(|h| h.in_flight).sum::<usize>(), 0);
+    }
+
+    #[tokio::test]
+    async fn out_of_service_after_failures() {
+        let silent = endpoint(1, Duration::from_secs(60)).await;
+        let pool = SigmaPool::connect(
+            vec![silent],
+            PoolConfig {
+                max_failures: 2,
+                quarantine: Duration::from_secs(60),
+                reconnect: ReconnectConfig {
+                    client: ClientConfig {
+                        request_timeout: Duration::from_millis(10),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        wait_connected(&pool, 1).await;
+
+        for serno in 0..2 {
+            assert!(matches!(
+                pool.send(request(serno)).await,
+                Err(ClientError::Timeout)
+            ));
+        }
+
+        let health = pool.health();
+        assert_eq!(health[0].consecutive_failures, 2);
+        assert!(!health[0].in_service);
+        assert!(matches!(
+            pool.send(request(3)).await,
+            Err(ClientError::NoAvailableConnection)
+        ));
+    }
+
+    #[tokio::test]
+    async fn unencodable_requests_are_not_failures() {
+        let addr = endpoint(8100, Duration::ZERO).await;
+        let pool = SigmaPool::connect(
+            vec![addr],
+            PoolConfig {
+                max_failures: 1,
+                ..Default::default()
+            },
+        );
+        wait_connected(&pool, 1).await;
+
+        for serno in 0..3 {
+            let mut req = request(serno);
+            req.tags.insert(18, "Y".repeat(10000));
+            assert!(matches!(
+                pool.send(req).await,
+                Err(ClientError::Protocol(ClientProtocolError::ExtfgSigma(_)))
+            ));
+        }
+        let health = pool.health();
+        assert_eq!(health[0].consecutive_failures, 0);
+        assert!(health[0].in_service);
+        assert_eq!(pool.send(request(3)).await.unwrap().reason, 8100);
+    }
+
+    #[tokio::test]
+    async fn back_in_service_after_quarantine() {
+        let silent = endpoint(1, Duration::from_secs(60)).await;
+        let pool = SigmaPool::connect(
+            vec![silent],
+            PoolConfig {
+                max_failures: 1,
+                quarantine: Duration::from_millis(50),
+                reconnect: ReconnectConfig {
+                    client: ClientConfig {
+                        request_timeout: Duration::from_millis(10),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        wait_connected(&pool, 1).await;
+
+        assert!(pool.send(request(0)).await.is_err());
+        assert!(!pool.health()[0].in_service);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        // Reading health does not reset the failures, only selection does
+        let _ = format!("{:?}", pool);
+        let health = pool.health();
+        assert!(health[0].in_service);
+        assert_eq!(health[0].consecutive_failures, 1);
+        assert!(matches!(
+            pool.send(request(1)).await,
+            Err(ClientError::Timeout)
+        ));
+        assert_eq!(pool.health()[0].consecutive_failures, 1);
+        assert!(!pool.health()[0].in_service);
+    }
+
+    #[tokio::test]
+    async fn no_endpoints() {
+        let pool = SigmaPool::connect(Vec::<String>::new(), PoolConfig::default());
+        assert!(matches!(
+            pool.send(request(0)).await,
+            Err(ClientError::NoAvailableConnection)
+        ));
+    }
+}
@@ -0,0 +1,312 @@
+//! Connection manager which keeps a [`SigmaClient`] connected.
+//!
+//! [`ReconnectingClient`] reconnects with exponential backoff and jitter after the connection
+//! is dropped. Requests in flight at the moment of disconnect fail with
+//! [`ClientError::Disconnected`], except the ones marked SAF which are sent again over
+//! the next connection.
+
+use std::time::Duration;
+
+use futures_util::Stream;
+use rand::Rng;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::watch;
+use tokio::time::sleep;
+
+use super::{ClientConfig, ClientError, ConnectionState, SigmaClient};
+use crate::{SigmaRequest, SigmaResponse};
+
+/// Exponential backoff between reconnection attempts.
+#[derive(Debug, Clone)]
+pub struct BackoffConfig {
+    /// Delay before the first reconnection attempt.
+    pub initial: Duration,
+    /// Upper limit of the delay.
+    pub max: Duration,
+    /// Factor the delay is multiplied by after every failed attempt.
+    pub multiplier: f64,
+    /// Fraction of the delay by which it is randomly increased or decreased, from 0 to 1.
+    pub jitter: f64,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl BackoffConfig {
+    /// Delay before reconnection attempt with given number (starting with 0).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = self.multiplier.max(1.0).powi(attempt.min(64) as i32);
+        let base = self.initial.as_secs_f64() * exp;
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = match jitter > 0.0 {
+            true => rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter),
+            false => 1.0,
+        };
+        Duration::from_secs_f64((base * factor).min(self.max.as_secs_f64()))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReconnectConfig {
+    /// Configuration of every underlying connection.
+    pub client: ClientConfig,
+    pub backoff: BackoffConfig,
+}
+
+/// Client which reconnects to the Sigma host each time the connection is dropped.
+///
+/// The manager task stops when all handles are dropped.
+#[derive(Debug, Clone)]
+pub struct ReconnectingClient {
+    current: watch::Receiver<Option<SigmaClient>>,
+    state: watch::Receiver<ConnectionState>,
+}
+
+impl ReconnectingClient {
+    /// Starts connection manager for given address. Must be called within a tokio runtime.
+    pub fn connect<A>(addr: A, config: ReconnectConfig) -> Self
+    where
+        A: ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        let (current_tx, current) = watch::channel(None);
+        let (state_tx, state) = watch::channel(ConnectionState::Connecting);
+
+        tokio::spawn(run_manager(addr, config, current_tx, state_tx));
+
+        Self { current, state }
+    }
+
+    /// Sends request over the current connection.
+    ///
+    /// Request fails with [`ClientError::Disconnected`] if there is no connection or
+    /// the connection is dropped before the response arrives, unless it is marked SAF:
+    /// such a request waits for the next connection and is sent again.
+    pub async fn send(&self, req: SigmaRequest) -> Result<SigmaResponse, ClientError> {
+        let saf = req.saf() == "Y";
+        let mut current = self.current.clone();
+
+        loop {
+            let client = current.borrow_and_update().clone();
+            let client = match client {
+                Some(v) => v,
+                None if saf => {
+                    current.changed().await.map_err(|_| ClientError::Closed)?;
+                    continue;
+                }
+                None => return Err(ClientError::Disconnected),
+            };
+
+            match client.send(req.clone()).await {
+                Err(ClientError::Disconnected) | Err(ClientError::Closed) if saf => {
+                    // Wait for the manager to replace the dropped connection
+                    current.changed().await.map_err(|_| ClientError::Closed)?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Stream of connection states starting with the current one.
+    ///
+    /// Intermediate states may be skipped if they change faster than the stream is polled.
+    pub fn state_stream(&self) -> impl Stream<Item = ConnectionState> {
+        futures_util::stream::unfold(
+            (self.state.clone(), true),
+            |(mut state, first)| async move {
+                if !first && state.changed().await.is_err() {
+                    return None;
+                }
+                let v = *state.borrow_and_update();
+                Some((v, (state, false)))
+            },
+        )
+    }
+}
+
+async fn run_manager<A>(
+    addr: A,
+    config: ReconnectConfig,
+    current: watch::Sender<Option<SigmaClient>>,
+    state: watch::Sender<ConnectionState>,
+) where
+    A: ToSocketAddrs + Clone + Send + Sync + 'static,
+{
+    let mut attempt = 0;
+
+    loop {
+        let _ = state.send(ConnectionState::Connecting);
+
+        let stream = tokio::select! {
+            _ = current.closed() => return,
+            stream = TcpStream::connect(addr.clone()) => stream,
+        };
+
+        let client = match stream {
+            Ok(stream) => {
+                attempt = 0;
+                SigmaClient::new(stream, config.client.clone())
+            }
+            Err(_) => {
+                let delay = config.backoff.delay(attempt);
+                attempt = attempt.saturating_add(1);
+                tokio::select! {
+                    _ = current.closed() => return,
+                    _ = sleep(delay) => continue,
+                }
+            }
+        };
+
+        let _ = current.send(Some(client.clone()));
+        let _ = state.send(ConnectionState::Connected);
+
+        tokio::select! {
+            _ = current.closed() => return,
+            _ = client.closed() => {}
+        }
+
+        let _ = current.send(None);
+        let _ = state.send(ConnectionState::Disconnected);
+        let delay = config.backoff.delay(attempt);
+        attempt = attempt.saturating_add(1);
+        tokio::select! {
+            _ = current.closed() => return,
+            _ = sleep(delay) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures_util::StreamExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn config() -> ReconnectConfig {
+        ReconnectConfig {
+            client: ClientConfig {
+                request_timeout: Duration::from_secs(5),
+                ..Default::default()
+            },
+            backoff: BackoffConfig {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(50),
+                ..Default::default()
+            },
+        }
+    }
+
+    async fn read_request(io: &mut TcpStream) -> SigmaRequest {
+        let mut len = [0u8; 5];
+        io.read_exact(&mut len).await.unwrap();
+        let msg_len = std::str::from_utf8(&len).unwrap().parse::<usize>().unwrap();
+        let mut frame = len.to_vec();
+        frame.resize(5 + msg_len, 0);
+        io.read_exact(&mut frame[5..]).await.unwrap();
+        SigmaRequest::decode(Bytes::from(frame)).unwrap()
+    }
+
+    #[test]
+    fn backoff_delay() {
+        let backoff = BackoffConfig {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.0,
+        };
+        assert_eq!(backoff.delay(0), Duration::from_millis(100));
+        assert_eq!(backoff.delay(1), Duration::from_millis(200));
+        assert_eq!(backoff.delay(3), Duration::from_millis(800));
+        assert_eq!(backoff.delay(4), Duration::from_secs(1));
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_jitter() {
+        let backoff = BackoffConfig {
+            initial: Duration::from_millis(100),
+            jitter: 0.5,
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let delay = backoff.delay(0);
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(150));
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect_and_requeue_saf() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            // The first connection is dropped with both requests in flight
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_request(&mut stream).await;
+            read_request(&mut stream).await;
+            drop(stream);
+
+            // The second one answers the resent SAF request
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let req = read_request(&mut stream).await;
+            assert_eq!(req.saf(), "Y");
+            let resp = SigmaResponse::new("0130", req.auth_serno, 8100).unwrap();
+            stream.write_all(&resp.encode().unwrap()).await.unwrap();
+            stream
+        });
+
+        let client = ReconnectingClient::connect(addr, config());
+        let mut states = Box::pin(client.state_stream());
+        while states.next().await != Some(ConnectionState::Connected) {}
+
+        let (regular, saf) = tokio::join!(
+            client.send(SigmaRequest::new("N", "M", "0100", 1).unwrap()),
+            async {
+                // Make sure the SAF request is sent after the regular one
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                client
+                    .send(SigmaRequest::new("Y", "M", "0120", 2).unwrap())
+                    .await
+            }
+        );
+        assert!(matches!(regular, Err(ClientError::Disconnected)));
+        assert_eq!(saf.unwrap().reason, 8100);
+        assert_eq!(client.state(), ConnectionState::Connected);
+        drop(server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn not_connected() {
+        // Nothing listens on the port after the listener is dropped
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let client = ReconnectingClient::connect(addr, config());
+        assert_eq!(client.state(), ConnectionState::Connecting);
+        let result = client
+            .send(SigmaRequest::new("N", "M", "0100", 1).unwrap())
+            .await;
+        assert!(matches!(result, Err(ClientError::Disconnected)));
+    }
+}
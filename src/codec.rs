@@ -1,9 +1,36 @@
-use bytes::{BufMut, BytesMut};
-use tokio_util::codec::{Decoder, Encoder};
+//! Runtime-independent framing of Sigma messages.
+//!
+//! [`SigmaClientProtocol`] incrementally decodes [`SigmaResponse`]s from and encodes
+//! [`SigmaRequest`]s into a [`BytesMut`] buffer with [`SigmaClientProtocol::decode_response`]
+//! and [`SigmaClientProtocol::encode_request`]. It is hooked into transports by adapters, each
+//! under its own feature:
+//! * `codec`: `tokio_util::codec` `Decoder` and `Encoder`, for `Framed` over tokio I/O;
+//! * `futures-codec`: `asynchronous_codec` `Decoder` and `Encoder`, for `Framed` over `futures`
+//!   `AsyncRead`/`AsyncWrite`;
+//! * `buffer`: `BufferedProtocol` over plain byte slices, for transports driven by the caller.
 
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+#[cfg(feature = "charset")]
+use crate::charset::CharsetConfig;
+use crate::metrics::Metrics;
 use crate::{SigmaRequest, SigmaResponse};
 
-/// Errors of [`tokio_util::codec::Framed`] stream with [`SigmaClientProtocol`] codec.
+#[cfg(feature = "buffer")]
+mod buffer;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+#[cfg(feature = "futures-codec")]
+mod futures_codec;
+#[cfg(feature = "codec")]
+mod tokio_codec;
+
+#[cfg(feature = "buffer")]
+pub use buffer::BufferedProtocol;
+
+/// Errors of [`SigmaClientProtocol`] and of the transports framed with it.
 #[derive(Debug, thiserror::Error)]
 pub enum ClientProtocolError {
     #[error(transparent)]
@@ -12,10 +39,25 @@ pub enum ClientProtocolError {
     WrongLenUtf8(#[from] std::str::Utf8Error),
     #[error(transparent)]
     WrongLenInt(#[from] std::num::ParseIntError),
+    #[error("Message length {length} exceeds maximum of {max}")]
+    MessageTooLong { length: usize, max: usize },
     #[error(transparent)]
     StdIoError(#[from] std::io::Error),
 }
 
+impl ClientProtocolError {
+    /// Name of the variant, or of the inner [`crate::Error`] variant; suitable as a metrics label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::ExtfgSigma(err) => err.kind(),
+            Self::WrongLenUtf8(_) => "WrongLenUtf8",
+            Self::WrongLenInt(_) => "WrongLenInt",
+            Self::MessageTooLong { .. } => "MessageTooLong",
+            Self::StdIoError(_) => "StdIoError",
+        }
+    }
+}
+
 impl PartialEq for ClientProtocolError {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -25,6 +67,13 @@ impl PartialEq for ClientProtocolError {
             (Self::ExtfgSigma(x), Self::ExtfgSigma(y)) => x == y,
             (Self::WrongLenUtf8(x), Self::WrongLenUtf8(y)) => x == y,
             (Self::WrongLenInt(x), Self::WrongLenInt(y)) => x == y,
+            (
+                Self::MessageTooLong { length, max },
+                Self::MessageTooLong {
+                    length: other_length,
+                    max: other_max,
+                },
+            ) => length == other_length && max == other_max,
             (_, _) => false,
         }
     }
@@ -32,49 +81,315 @@ impl PartialEq for ClientProtocolError {
 
 pub const LENGTH_BYTES_COUNT: usize = 5;
 
+/// Largest message length which fits into the length header.
+pub const MAX_MESSAGE_LENGTH: usize = 99999;
+
+/// Smallest plausible response length: MTI and authorization serno.
+const MIN_MESSAGE_LENGTH: usize = 14;
+
+/// Behaviour of [`SigmaClientProtocol`] after a corrupt frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResyncStrategy {
+    /// Return an error, the stream can not be recovered.
+    #[default]
+    Fail,
+    /// Skip a corrupt frame or bytes until the next plausible frame header.
+    SkipToNextHeader,
+}
+
+/// Counters of [`SigmaClientProtocol`] decoder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodecStats {
+    pub frames_decoded: u64,
+    pub frames_skipped: u64,
+    pub bytes_skipped: u64,
+}
+
 /// Codec for semi-automated encoding/decoding of [`SigmaRequest`]s and [`SigmaResponse`]s.
-pub struct SigmaClientProtocol;
+#[derive(Debug, Clone)]
+pub struct SigmaClientProtocol {
+    max_message_length: usize,
+    resync: ResyncStrategy,
+    stats: CodecStats,
+    #[cfg(feature = "charset")]
+    charset: Option<CharsetConfig>,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl Default for SigmaClientProtocol {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl SigmaClientProtocol {
+    const DEFAULT: Self = Self {
+        max_message_length: MAX_MESSAGE_LENGTH,
+        resync: ResyncStrategy::Fail,
+        stats: CodecStats {
+            frames_decoded: 0,
+            frames_skipped: 0,
+            bytes_skipped: 0,
+        },
+        #[cfg(feature = "charset")]
+        charset: None,
+        metrics: None,
+    };
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets maximum accepted message length (not including the length header).
+    ///
+    /// Defaults to [`MAX_MESSAGE_LENGTH`], the most the 5 digit header can express, so
+    /// [`ClientProtocolError::MessageTooLong`] only occurs with a smaller configured limit.
+    pub fn with_max_message_length(mut self, v: usize) -> Self {
+        self.max_message_length = v.min(MAX_MESSAGE_LENGTH);
+        self
+    }
+
+    pub fn with_resync(mut self, v: ResyncStrategy) -> Self {
+        self.resync = v;
+        self
+    }
+
+    /// Decodes and encodes tag and field text in given charsets.
+    #[cfg(feature = "charset")]
+    pub fn with_charset(mut self, v: CharsetConfig) -> Self {
+        self.charset = Some(v);
+        self
+    }
+
+    /// Reports frames, bytes, decode errors and response reasons to given metrics.
+    /// `SigmaClient` of the `client` feature reports response latencies to them as well.
+    pub fn with_metrics(mut self, v: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(v);
+        self
+    }
 
-impl Decoder for SigmaClientProtocol {
-    type Item = SigmaResponse;
-    type Error = ClientProtocolError;
+    pub fn max_message_length(&self) -> usize {
+        self.max_message_length
+    }
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let current_length = src.len();
+    pub fn resync(&self) -> ResyncStrategy {
+        self.resync
+    }
 
-        if current_length < LENGTH_BYTES_COUNT {
-            src.reserve(LENGTH_BYTES_COUNT - current_length);
-            return Ok(None);
+    pub fn stats(&self) -> &CodecStats {
+        &self.stats
+    }
+
+    pub fn metrics(&self) -> Option<&Arc<dyn Metrics>> {
+        self.metrics.as_ref()
+    }
+
+    fn decode_error(&self, err: &ClientProtocolError) {
+        if let Some(metrics) = &self.metrics {
+            metrics.decode_error(err.kind());
+        }
+    }
+
+    #[cfg(feature = "charset")]
+    pub fn charset(&self) -> Option<&CharsetConfig> {
+        self.charset.as_ref()
+    }
+
+    fn decode_data(&self, data: Bytes) -> Result<SigmaResponse, crate::Error> {
+        #[cfg(feature = "charset")]
+        if let Some(charset) = &self.charset {
+            return SigmaResponse::decode_charset(data, charset);
         }
+        SigmaResponse::decode(data)
+    }
+
+    fn encode_data(&self, item: &SigmaRequest) -> Result<Bytes, crate::Error> {
+        #[cfg(feature = "charset")]
+        if let Some(charset) = &self.charset {
+            return item.encode_charset(charset);
+        }
+        item.encode()
+    }
 
-        let msg_len = std::str::from_utf8(&src[0..LENGTH_BYTES_COUNT])
+    fn parse_length(&self, src: &[u8]) -> Result<usize, ClientProtocolError> {
+        let length = std::str::from_utf8(src)
             .map_err(ClientProtocolError::from)?
             .parse::<usize>()
             .map_err(ClientProtocolError::from)?;
 
-        let overall_length = msg_len + LENGTH_BYTES_COUNT;
+        if length > self.max_message_length {
+            return Err(ClientProtocolError::MessageTooLong {
+                length,
+                max: self.max_message_length,
+            });
+        }
+
+        Ok(length)
+    }
+
+    /// Splits the next complete frame, including its length header, off the buffer.
+    ///
+    /// Incorrect length headers are handled according to the resync strategy, the frame itself
+    /// is not decoded.
+    pub fn next_frame(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<BytesMut>, ClientProtocolError> {
+        loop {
+            let current_length = src.len();
 
-        Ok(match current_length < overall_length {
-            true => {
+            if current_length < LENGTH_BYTES_COUNT {
+                src.reserve(LENGTH_BYTES_COUNT - current_length);
+                return Ok(None);
+            }
+
+            let msg_len = match self.parse_length(&src[0..LENGTH_BYTES_COUNT]) {
+                Ok(v) => v,
+                Err(err) => {
+                    self.decode_error(&err);
+                    match self.resync {
+                        ResyncStrategy::Fail => {
+                            #[cfg(feature = "tracing")]
+                            tracing::warn!(error = %err, "Incorrect frame header");
+                            return Err(err);
+                        }
+                        ResyncStrategy::SkipToNextHeader => {
+                            #[cfg(feature = "tracing")]
+                            tracing::warn!(error = %err, "Skipping incorrect frame header");
+                            self.skip_to_next_header(src);
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            let overall_length = msg_len + LENGTH_BYTES_COUNT;
+
+            if current_length < overall_length {
                 src.reserve(overall_length - current_length);
-                None
+                return Ok(None);
             }
-            false => Some(SigmaResponse::decode(src.split_to(overall_length).into())?),
-        })
+
+            #[cfg(feature = "tracing")]
+            tracing::debug!(length = overall_length, "Frame received");
+
+            return Ok(Some(src.split_to(overall_length)));
+        }
     }
-}
 
-impl Encoder<SigmaRequest> for SigmaClientProtocol {
-    type Error = ClientProtocolError;
+    /// Checks whether a frame may start at the beginning of `src` judging by available bytes:
+    /// a 5 digit length in range followed by a 4 digit MTI, preceded by SAF and source in
+    /// requests.
+    fn is_plausible_header(&self, src: &[u8]) -> bool {
+        let length = &src[..src.len().min(LENGTH_BYTES_COUNT)];
+        if !length.iter().all(u8::is_ascii_digit) {
+            return false;
+        }
+        if length.len() < LENGTH_BYTES_COUNT {
+            return true;
+        }
+        match self.parse_length(length) {
+            Ok(length) if length >= MIN_MESSAGE_LENGTH => {}
+            _ => return false,
+        }
 
-    fn encode(&mut self, item: SigmaRequest, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        dst.put(item.encode()?);
+        let header = &src[LENGTH_BYTES_COUNT..];
+        let mti_at = |offset: usize| header.iter().skip(offset).take(4).all(u8::is_ascii_digit);
+        mti_at(0) || matches!(header.first(), None | Some(b'Y' | b'N')) && mti_at(2)
+    }
+
+    /// Drops bytes of a corrupt header until the next position a frame may start at.
+    fn skip_to_next_header(&mut self, src: &mut BytesMut) {
+        let skip = (1..src.len())
+            .find(|i| self.is_plausible_header(&src[*i..]))
+            .unwrap_or_else(|| src.len());
+
+        let _ = src.split_to(skip);
+        self.stats.frames_skipped += 1;
+        self.stats.bytes_skipped += skip as u64;
+    }
+
+    /// Decodes the next response, `None` if `src` does not contain a complete frame yet.
+    ///
+    /// Consumed bytes are removed from `src`, incomplete frame is left in place. Named apart
+    /// from `Decoder::decode` of the adapters so the trait methods are not shadowed.
+    pub fn decode_response(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<SigmaResponse>, ClientProtocolError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("sigma_codec_decode", buffered = src.len()).entered();
+
+        loop {
+            let frame = match self.next_frame(src)? {
+                Some(v) => v,
+                None => return Ok(None),
+            };
+            if let Some(resp) = self.decode_frame(frame)? {
+                return Ok(Some(resp));
+            }
+        }
+    }
+
+    /// Decodes a frame returned by [`next_frame`](Self::next_frame), `None` if the frame is
+    /// corrupt and skipped according to [`ResyncStrategy`].
+    pub(crate) fn decode_frame(
+        &mut self,
+        frame: BytesMut,
+    ) -> Result<Option<SigmaResponse>, ClientProtocolError> {
+        let overall_length = frame.len();
+
+        match self.decode_data(frame.freeze()) {
+            Ok(resp) => {
+                self.stats.frames_decoded += 1;
+                if let Some(metrics) = &self.metrics {
+                    metrics.frame_decoded(overall_length);
+                    metrics.response_reason(resp.reason);
+                }
+                Ok(Some(resp))
+            }
+            Err(err) => match self.resync {
+                ResyncStrategy::Fail => {
+                    let err = err.into();
+                    self.decode_error(&err);
+                    Err(err)
+                }
+                ResyncStrategy::SkipToNextHeader => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(length = overall_length, "Skipping corrupt frame");
+                    self.decode_error(&err.into());
+                    self.stats.frames_skipped += 1;
+                    self.stats.bytes_skipped += overall_length as u64;
+                    Ok(None)
+                }
+            },
+        }
+    }
+
+    /// Appends encoded request to `dst`.
+    pub fn encode_request(
+        &mut self,
+        item: &SigmaRequest,
+        dst: &mut BytesMut,
+    ) -> Result<(), ClientProtocolError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("sigma_codec_encode").entered();
+
+        let data = self.encode_data(item)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(length = data.len(), "Frame sent");
+        if let Some(metrics) = &self.metrics {
+            metrics.frame_encoded(data.len());
+        }
+        dst.put(data);
         Ok(())
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "codec"))]
 mod tests {
+    use tokio_util::codec::Decoder;
+
     use super::*;
 
     #[test]
@@ -83,7 +398,10 @@ mod tests {
         let mut buf = BytesMut::new();
         buf.put(DATA);
 
-        assert!(matches!(SigmaClientProtocol.decode(&mut buf), Ok(None)));
+        assert!(matches!(
+            SigmaClientProtocol::default().decode(&mut buf),
+            Ok(None)
+        ));
         assert_eq!(buf, DATA);
     }
 
@@ -93,7 +411,10 @@ mod tests {
         let mut buf = BytesMut::new();
         buf.put(DATA);
 
-        assert!(matches!(SigmaClientProtocol.decode(&mut buf), Ok(None)));
+        assert!(matches!(
+            SigmaClientProtocol::default().decode(&mut buf),
+            Ok(None)
+        ));
         assert_eq!(buf, DATA);
     }
 
@@ -103,7 +424,10 @@ mod tests {
         let mut buf = BytesMut::new();
         buf.put(DATA);
 
-        assert!(matches!(SigmaClientProtocol.decode(&mut buf), Ok(None)));
+        assert!(matches!(
+            SigmaClientProtocol::default().decode(&mut buf),
+            Ok(None)
+        ));
         assert_eq!(buf, DATA);
     }
 
@@ -113,7 +437,10 @@ mod tests {
         let mut buf = BytesMut::new();
         buf.put(DATA);
 
-        assert!(matches!(SigmaClientProtocol.decode(&mut buf), Ok(None)));
+        assert!(matches!(
+            SigmaClientProtocol::default().decode(&mut buf),
+            Ok(None)
+        ));
         assert_eq!(buf, DATA);
     }
 
@@ -123,7 +450,123 @@ mod tests {
         let mut buf = BytesMut::new();
         buf.put(DATA);
 
-        assert!(matches!(SigmaClientProtocol.decode(&mut buf), Ok(Some(_))));
+        assert!(matches!(
+            SigmaClientProtocol::default().decode(&mut buf),
+            Ok(Some(_))
+        ));
         assert_eq!(buf, b""[..]);
     }
+
+    #[test]
+    fn decode_too_long() {
+        const DATA: &[u8] = b"00100";
+        let mut buf = BytesMut::new();
+        buf.put(DATA);
+
+        let mut codec = SigmaClientProtocol::new().with_max_message_length(99);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap_err(),
+            ClientProtocolError::MessageTooLong {
+                length: 100,
+                max: 99
+            }
+        );
+        assert_eq!(buf, DATA);
+    }
+
+    #[test]
+    fn resync_after_garbage() {
+        const DATA: &[u8] = b"\x0000x0002401104007040978T\x00\x31\x00\x00\x048495";
+        let mut buf = BytesMut::new();
+        buf.put(DATA);
+
+        let mut codec = SigmaClientProtocol::new().with_resync(ResyncStrategy::SkipToNextHeader);
+        let resp = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(resp.auth_serno, 4007040978);
+        assert_eq!(buf, b""[..]);
+        assert_eq!(
+            codec.stats(),
+            &CodecStats {
+                frames_decoded: 1,
+                frames_skipped: 1,
+                bytes_skipped: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn resync_after_too_long() {
+        const DATA: &[u8] = b"000990002401104007040978T\x00\x31\x00\x00\x048495";
+        let mut buf = BytesMut::new();
+        buf.put(DATA);
+
+        let mut codec = SigmaClientProtocol::new()
+            .with_max_message_length(50)
+            .with_resync(ResyncStrategy::SkipToNextHeader);
+        assert!(matches!(codec.decode(&mut buf), Ok(Some(_))));
+        assert_eq!(codec.stats().bytes_skipped, 5);
+    }
+
+    #[test]
+    fn resync_keeps_possible_header() {
+        const DATA: &[u8] = b"x0002";
+        let mut buf = BytesMut::new();
+        buf.put(DATA);
+
+        let mut codec = SigmaClientProtocol::new().with_resync(ResyncStrategy::SkipToNextHeader);
+        assert!(matches!(codec.decode(&mut buf), Ok(None)));
+        assert_eq!(buf, b"0002"[..]);
+
+        // Request header with SAF and source before the MTI
+        let mut buf = BytesMut::from(&b"xx00016NM0100"[..]);
+        let mut codec = SigmaClientProtocol::new().with_resync(ResyncStrategy::SkipToNextHeader);
+        assert!(matches!(codec.next_frame(&mut buf), Ok(None)));
+        assert_eq!(buf, b"00016NM0100"[..]);
+    }
+
+    #[test]
+    fn resync_after_corrupt_frame() {
+        const DATA: &[u8] = b"0002401104007040978T\x00\x31\x00\x00\x04ABCD0002401104007040979T\x00\x31\x00\x00\x048495";
+        let mut buf = BytesMut::new();
+        buf.put(DATA);
+
+        let mut codec = SigmaClientProtocol::new().with_resync(ResyncStrategy::SkipToNextHeader);
+        let resp = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(resp.auth_serno, 4007040979);
+        assert_eq!(codec.stats().frames_skipped, 1);
+        assert_eq!(codec.stats().bytes_skipped, 29);
+    }
+
+    #[cfg(feature = "charset")]
+    #[test]
+    fn decode_charset() {
+        use crate::charset::Charset;
+
+        const DATA: &[u8] =
+            b"0003101104007040979T\x00\x31\x00\x00\x048495T\x00\x48\x00\x00\x01\xe4";
+        let mut buf = BytesMut::new();
+        buf.put(DATA);
+
+        let mut codec =
+            SigmaClientProtocol::new().with_charset(CharsetConfig::new(Charset::Cp1251));
+        let resp = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(resp.adata.as_deref(), Some("д"));
+    }
+
+    #[test]
+    fn decode_metrics() {
+        const DATA: &[u8] = b"0002401104007040978T\x00\x31\x00\x00\x04ABCD0002401104007040979T\x00\x31\x00\x00\x048495";
+        let mut buf = BytesMut::new();
+        buf.put(DATA);
+
+        let metrics = Arc::new(crate::metrics::CountingMetrics::new());
+        let mut codec = SigmaClientProtocol::new()
+            .with_resync(ResyncStrategy::SkipToNextHeader)
+            .with_metrics(metrics.clone());
+        codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(metrics.frames_decoded(), 1);
+        assert_eq!(metrics.bytes_decoded(), 29);
+        assert_eq!(metrics.decode_errors().get("IncorrectFieldData"), Some(&1));
+        assert_eq!(metrics.reasons().get(&8495), Some(&1));
+    }
 }
@@ -0,0 +1,86 @@
+//! Plain buffer adapter of [`SigmaClientProtocol`], for transports driven by the caller.
+
+use bytes::{Bytes, BytesMut};
+
+use super::{ClientProtocolError, SigmaClientProtocol};
+use crate::{SigmaRequest, SigmaResponse};
+
+/// [`SigmaClientProtocol`] with its own receive buffer.
+///
+/// Received bytes are fed in chunks of any size with [`BufferedProtocol::feed`], complete
+/// responses are taken with [`BufferedProtocol::next_response`].
+#[derive(Debug, Clone, Default)]
+pub struct BufferedProtocol {
+    protocol: SigmaClientProtocol,
+    buffer: BytesMut,
+}
+
+impl BufferedProtocol {
+    pub fn new(protocol: SigmaClientProtocol) -> Self {
+        Self {
+            protocol,
+            buffer: BytesMut::new(),
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Next complete response, `None` if more data has to be fed.
+    pub fn next_response(&mut self) -> Result<Option<SigmaResponse>, ClientProtocolError> {
+        self.protocol.decode_response(&mut self.buffer)
+    }
+
+    /// Encoded request, ready to be sent.
+    pub fn encode(&mut self, req: &SigmaRequest) -> Result<Bytes, ClientProtocolError> {
+        let mut dst = BytesMut::new();
+        self.protocol.encode_request(req, &mut dst)?;
+        Ok(dst.freeze())
+    }
+
+    /// Amount of bytes fed but not consumed yet.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn protocol(&self) -> &SigmaClientProtocol {
+        &self.protocol
+    }
+
+    /// Protocol and the bytes not consumed yet.
+    pub fn into_parts(self) -> (SigmaClientProtocol, BytesMut) {
+        (self.protocol, self.buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feed_in_chunks() {
+        const DATA: &[u8] = b"0002401104007040978T\x00\x31\x00\x00\x0484950002401104007040979T\x00\x31\x00\x00\x048100000";
+        let mut buffered = BufferedProtocol::default();
+
+        let mut responses = Vec::new();
+        for chunk in DATA.chunks(7) {
+            buffered.feed(chunk);
+            while let Some(resp) = buffered.next_response().unwrap() {
+                responses.push((resp.auth_serno, resp.reason));
+            }
+        }
+        assert_eq!(responses, vec![(4007040978, 8495), (4007040979, 8100)]);
+        assert_eq!(buffered.buffered(), 3);
+        assert_eq!(buffered.protocol().stats().frames_decoded, 2);
+
+        let req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        assert_eq!(buffered.encode(&req).unwrap(), req.encode().unwrap());
+
+        buffered.feed(b"xx");
+        assert!(matches!(
+            buffered.next_response(),
+            Err(ClientProtocolError::WrongLenInt(_))
+        ));
+    }
+}
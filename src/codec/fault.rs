@@ -0,0 +1,353 @@
+//! Fault injection helpers for resilience testing of [`SigmaClientProtocol`](super::SigmaClientProtocol)
+//! and services built on top of it.
+//!
+//! [`FaultInjector`] turns a well-formed encoded frame into a sequence of (possibly corrupted)
+//! chunks, the way an unreliable peer or network would deliver them. Chunks can be fed into any
+//! [`Decoder`] directly with [`feed`] or served through an [`AsyncRead`] with [`FaultyStream`].
+
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio_util::codec::Decoder;
+
+use super::LENGTH_BYTES_COUNT;
+
+/// Single corruption applied to an encoded frame by [`FaultInjector`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Deliver the frame in chunks of at most given size (`0` is treated as `1`).
+    Fragment(usize),
+    /// Cut given amount of bytes off the end of the frame.
+    Truncate(usize),
+    /// Replace the length header with given value (only 5 least significant digits are used).
+    OverrideLength(usize),
+    /// Replace the byte of the length header at given position with given (non-digit) byte.
+    NonDigitLength { position: usize, byte: u8 },
+    /// Replace the byte at given offset from the frame start with an invalid BCD value.
+    InvalidBcd(usize),
+    /// Put given bytes in front of the frame.
+    Prepend(Bytes),
+}
+
+/// Configurable set of [`Fault`]s applied to encoded frames.
+///
+/// Content faults are applied in the order they were added, fragmentation is applied last.
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjector {
+    faults: Vec<Fault>,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds one more fault to apply.
+    pub fn with(mut self, fault: Fault) -> Self {
+        self.faults.push(fault);
+        self
+    }
+
+    pub fn faults(&self) -> &[Fault] {
+        &self.faults
+    }
+
+    /// Applies all content faults to the frame, ignoring fragmentation.
+    pub fn corrupt(&self, frame: &[u8]) -> BytesMut {
+        let mut buf = BytesMut::from(frame);
+
+        for fault in self.faults.iter() {
+            match fault {
+                Fault::Fragment(_) => {}
+                Fault::Truncate(n) => {
+                    let len = buf.len();
+                    buf.truncate(len.saturating_sub(*n));
+                }
+                Fault::OverrideLength(v) => {
+                    if buf.len() >= LENGTH_BYTES_COUNT {
+                        buf[0..LENGTH_BYTES_COUNT]
+                            .copy_from_slice(format!("{:05}", v % 100000).as_bytes());
+                    }
+                }
+                Fault::NonDigitLength { position, byte } => {
+                    if *position < LENGTH_BYTES_COUNT && *position < buf.len() {
+                        buf[*position] = *byte;
+                    }
+                }
+                Fault::InvalidBcd(offset) => {
+                    if *offset < buf.len() {
+                        buf[*offset] = 0xAF;
+                    }
+                }
+                Fault::Prepend(prefix) => {
+                    let mut prefixed = BytesMut::with_capacity(prefix.len() + buf.len());
+                    prefixed.extend_from_slice(prefix);
+                    prefixed.extend_from_slice(&buf);
+                    buf = prefixed;
+                }
+            }
+        }
+
+        buf
+    }
+
+    /// Applies all faults to the frame and splits the result into chunks to be delivered
+    /// by separate reads.
+    pub fn apply(&self, frame: &[u8]) -> Vec<Bytes> {
+        let mut buf = self.corrupt(frame);
+        let chunk_size = self
+            .faults
+            .iter()
+            .rev()
+            .find_map(|fault| match fault {
+                Fault::Fragment(size) => Some((*size).max(1)),
+                _ => None,
+            })
+            .unwrap_or_else(|| buf.len().max(1));
+
+        let mut chunks = Vec::with_capacity(buf.len() / chunk_size + 1);
+        while !buf.is_empty() {
+            let at = chunk_size.min(buf.len());
+            chunks.push(buf.split_to(at).freeze());
+        }
+        chunks
+    }
+}
+
+/// Outcome of [`feed`]ing chunks into a [`Decoder`].
+#[derive(Debug)]
+pub struct FeedReport<T, E> {
+    /// Successfully decoded items.
+    pub items: Vec<T>,
+    /// How many times decoder returned `Ok(None)` asking for more data.
+    pub incomplete: usize,
+    /// Error the decoder stopped with, if any.
+    pub error: Option<E>,
+    /// Bytes left in the decoder buffer.
+    pub remaining: BytesMut,
+}
+
+/// Feeds chunks one by one into the decoder the same way [`tokio_util::codec::FramedRead`] does:
+/// after every chunk decoder is called until it asks for more data. Stops at the first error.
+pub fn feed<D, I>(decoder: &mut D, chunks: I) -> FeedReport<D::Item, D::Error>
+where
+    D: Decoder,
+    I: IntoIterator<Item = Bytes>,
+{
+    let mut report = FeedReport {
+        items: Vec::new(),
+        incomplete: 0,
+        error: None,
+        remaining: BytesMut::new(),
+    };
+
+    for chunk in chunks {
+        report.remaining.extend_from_slice(&chunk);
+        loop {
+            match decoder.decode(&mut report.remaining) {
+                Ok(Some(item)) => report.items.push(item),
+                Ok(None) => {
+                    report.incomplete += 1;
+                    break;
+                }
+                Err(err) => {
+                    report.error = Some(err);
+                    return report;
+                }
+            }
+        }
+    }
+
+    report
+}
+
+/// In-memory [`AsyncRead`] transport serving every chunk by a separate read.
+#[derive(Debug, Default)]
+pub struct FaultyStream {
+    chunks: VecDeque<Bytes>,
+}
+
+impl FaultyStream {
+    pub fn new<I: IntoIterator<Item = Bytes>>(chunks: I) -> Self {
+        Self {
+            chunks: chunks.into_iter().collect(),
+        }
+    }
+
+    /// Appends frame with faults of given injector applied.
+    pub fn push_frame(&mut self, injector: &FaultInjector, frame: &[u8]) {
+        self.chunks.extend(injector.apply(frame));
+    }
+}
+
+impl AsyncRead for FaultyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if let Some(mut chunk) = self.chunks.pop_front() {
+            let at = chunk.len().min(buf.remaining());
+            buf.put_slice(&chunk.split_to(at));
+            if !chunk.is_empty() {
+                self.chunks.push_front(chunk);
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use tokio_util::codec::FramedRead;
+
+    use super::*;
+    use crate::codec::{ClientProtocolError, ResyncStrategy, SigmaClientProtocol};
+
+    const FRAME: &[u8] = b"0002401104007040978T\x00\x31\x00\x00\x048495";
+
+    #[test]
+    fn fragmented_frame() {
+        let chunks = FaultInjector::new().with(Fault::Fragment(1)).apply(FRAME);
+        assert_eq!(chunks.len(), FRAME.len());
+
+        let report = feed(&mut SigmaClientProtocol::default(), chunks);
+        assert_eq!(report.items.len(), 1);
+        assert_eq!(report.items[0].auth_serno, 4007040978);
+        // Decoder asks for more data after every chunk including the last one
+        assert_eq!(report.incomplete, FRAME.len());
+        assert!(report.error.is_none());
+        assert!(report.remaining.is_empty());
+    }
+
+    #[test]
+    fn frames_split_across_reads() {
+        let injector = FaultInjector::new().with(Fault::Fragment(7));
+        let chunks = [FRAME, FRAME, FRAME]
+            .iter()
+            .flat_map(|frame| injector.apply(frame))
+            .collect::<Vec<_>>();
+
+        let report = feed(&mut SigmaClientProtocol::default(), chunks);
+        assert_eq!(report.items.len(), 3);
+        assert!(report.error.is_none());
+        assert!(report.remaining.is_empty());
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let chunks = FaultInjector::new()
+            .with(Fault::Truncate(3))
+            .with(Fault::Fragment(4))
+            .apply(FRAME);
+
+        let report = feed(&mut SigmaClientProtocol::default(), chunks);
+        assert!(report.items.is_empty());
+        assert!(report.error.is_none());
+        assert_eq!(report.remaining, FRAME[..FRAME.len() - 3]);
+    }
+
+    #[test]
+    fn oversized_length() {
+        let chunks = FaultInjector::new()
+            .with(Fault::OverrideLength(99999))
+            .apply(FRAME);
+
+        let report = feed(&mut SigmaClientProtocol::default(), chunks);
+        assert!(report.items.is_empty());
+        assert_eq!(report.incomplete, 1);
+        assert!(report.error.is_none());
+    }
+
+    #[test]
+    fn non_digit_length() {
+        let chunks = FaultInjector::new()
+            .with(Fault::NonDigitLength {
+                position: 2,
+                byte: b'x',
+            })
+            .apply(FRAME);
+
+        let report = feed(&mut SigmaClientProtocol::default(), chunks);
+        assert!(report.items.is_empty());
+        assert!(matches!(
+            report.error,
+            Some(ClientProtocolError::WrongLenInt(_))
+        ));
+    }
+
+    #[test]
+    fn non_utf8_length() {
+        let chunks = FaultInjector::new()
+            .with(Fault::NonDigitLength {
+                position: 0,
+                byte: 0xFF,
+            })
+            .apply(FRAME);
+
+        let report = feed(&mut SigmaClientProtocol::default(), chunks);
+        assert!(matches!(
+            report.error,
+            Some(ClientProtocolError::WrongLenUtf8(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_bcd() {
+        // Offset of the BCD-encoded tag data length
+        let chunks = FaultInjector::new()
+            .with(Fault::InvalidBcd(23))
+            .with(Fault::Fragment(10))
+            .apply(FRAME);
+
+        let report = feed(&mut SigmaClientProtocol::default(), chunks);
+        assert!(report.items.is_empty());
+        assert!(matches!(
+            report.error,
+            Some(ClientProtocolError::ExtfgSigma(crate::Error::Bounds(_)))
+        ));
+    }
+
+    #[test]
+    fn prepended_garbage() {
+        let chunks = FaultInjector::new()
+            .with(Fault::Prepend(Bytes::from_static(b"\x00\x01")))
+            .apply(FRAME);
+
+        let report = feed(&mut SigmaClientProtocol::default(), chunks);
+        assert!(report.items.is_empty());
+        assert!(report.error.is_some());
+    }
+
+    #[test]
+    fn prepended_garbage_resync() {
+        let injector = FaultInjector::new()
+            .with(Fault::Prepend(Bytes::from_static(b"\x00\x01")))
+            .with(Fault::Fragment(2));
+        let mut codec = SigmaClientProtocol::new().with_resync(ResyncStrategy::SkipToNextHeader);
+
+        let report = feed(&mut codec, injector.apply(FRAME));
+        assert_eq!(report.items.len(), 1);
+        assert!(report.error.is_none());
+        assert_eq!(codec.stats().bytes_skipped, 2);
+    }
+
+    #[tokio::test]
+    async fn framed_read_over_faulty_stream() {
+        let mut stream = FaultyStream::default();
+        stream.push_frame(&FaultInjector::new().with(Fault::Fragment(3)), FRAME);
+        stream.push_frame(&FaultInjector::new().with(Fault::InvalidBcd(20)), FRAME);
+
+        let mut framed = FramedRead::new(stream, SigmaClientProtocol::default());
+        assert!(matches!(framed.next().await, Some(Ok(_))));
+        assert!(matches!(
+            framed.next().await,
+            Some(Err(ClientProtocolError::ExtfgSigma(_)))
+        ));
+    }
+}
@@ -0,0 +1,51 @@
+//! [`asynchronous_codec`] adapter of [`SigmaClientProtocol`], for `futures` I/O.
+
+use asynchronous_codec::{Decoder, Encoder};
+use bytes::BytesMut;
+
+use super::{ClientProtocolError, SigmaClientProtocol};
+use crate::{SigmaRequest, SigmaResponse};
+
+impl Decoder for SigmaClientProtocol {
+    type Item = SigmaResponse;
+    type Error = ClientProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode_response(src)
+    }
+}
+
+impl Encoder for SigmaClientProtocol {
+    type Item<'a> = SigmaRequest;
+    type Error = ClientProtocolError;
+
+    fn encode(&mut self, item: Self::Item<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode_request(&item, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use asynchronous_codec::Framed;
+    use futures_util::io::Cursor;
+    use futures_util::{SinkExt, StreamExt};
+
+    use super::*;
+
+    #[test]
+    fn framed() {
+        futures_executor::block_on(async {
+            let data = b"0002401104007040978T\x00\x31\x00\x00\x048495".to_vec();
+            let mut framed = Framed::new(Cursor::new(data), SigmaClientProtocol::new());
+
+            let resp = framed.next().await.unwrap().unwrap();
+            assert_eq!((resp.auth_serno, resp.reason), (4007040978, 8495));
+            assert!(framed.next().await.is_none());
+
+            let req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+            framed.send(req.clone()).await.unwrap();
+            let written = framed.into_inner().into_inner();
+            assert_eq!(&written[29..], &req.encode().unwrap()[..]);
+        });
+    }
+}
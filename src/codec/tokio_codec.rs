@@ -0,0 +1,24 @@
+//! [`tokio_util::codec`] adapter of [`SigmaClientProtocol`].
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::{ClientProtocolError, SigmaClientProtocol};
+use crate::{SigmaRequest, SigmaResponse};
+
+impl Decoder for SigmaClientProtocol {
+    type Item = SigmaResponse;
+    type Error = ClientProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode_response(src)
+    }
+}
+
+impl Encoder<SigmaRequest> for SigmaClientProtocol {
+    type Error = ClientProtocolError;
+
+    fn encode(&mut self, item: SigmaRequest, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode_request(&item, dst)
+    }
+}
@@ -0,0 +1,253 @@
+//! Typed accessors of ISO date and time fields.
+//!
+//! Most of the fields carry no year, so getters infer it relative to a reference date
+//! (usually the current one): the candidate date closest to the reference wins, which
+//! handles dates around New Year, e.g. `1231` received on January 1st belongs to the
+//! previous year.
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime};
+
+use crate::{Error, SigmaRequest};
+
+/// Transmission date and time, MMDDhhmmss.
+pub const TRANSMISSION_DATE_TIME_FIELD: u16 = 7;
+/// Local transaction time, hhmmss.
+pub const LOCAL_TIME_FIELD: u16 = 12;
+/// Local transaction date, MMDD.
+pub const LOCAL_DATE_FIELD: u16 = 13;
+/// Settlement date, MMDD.
+pub const SETTLEMENT_DATE_FIELD: u16 = 15;
+/// Capture date, MMDD.
+pub const CAPTURE_DATE_FIELD: u16 = 17;
+
+/// Date with given month and day closest to the reference date.
+pub fn infer_year(month: u32, day: u32, reference: NaiveDate) -> Option<NaiveDate> {
+    [reference.year(), reference.year() - 1, reference.year() + 1]
+        .iter()
+        .filter_map(|year| NaiveDate::from_ymd_opt(*year, month, day))
+        .min_by_key(|date| (*date - reference).num_days().abs())
+}
+
+fn digits(field: u16, v: &str, len: usize) -> Result<Vec<u32>, Error> {
+    let b = v.as_bytes();
+    if b.len() != len || !b.iter().all(u8::is_ascii_digit) {
+        return Err(Error::IncorrectFieldData {
+            field_name: format!("i{:03}", field),
+            should_be: format!("{} digits", len),
+        });
+    }
+    Ok(b.chunks(2)
+        .map(|c| ((c[0] - b'0') * 10 + c[1] - b'0') as u32)
+        .collect())
+}
+
+fn invalid(field: u16, should_be: &str) -> Error {
+    Error::IncorrectFieldData {
+        field_name: format!("i{:03}", field),
+        should_be: should_be.into(),
+    }
+}
+
+fn parse_mmdd(field: u16, v: &str, reference: NaiveDate) -> Result<NaiveDate, Error> {
+    let d = digits(field, v, 4)?;
+    infer_year(d[0], d[1], reference).ok_or_else(|| invalid(field, "valid date MMDD"))
+}
+
+fn parse_hhmmss(field: u16, v: &str) -> Result<NaiveTime, Error> {
+    let d = digits(field, v, 6)?;
+    NaiveTime::from_hms_opt(d[0], d[1], d[2]).ok_or_else(|| invalid(field, "valid time hhmmss"))
+}
+
+fn parse_mmddhhmmss(field: u16, v: &str, reference: NaiveDate) -> Result<NaiveDateTime, Error> {
+    digits(field, v, 10)?;
+    let date = parse_mmdd(field, &v[..4], reference)?;
+    let time = parse_hhmmss(field, &v[4..])?;
+    Ok(date.and_time(time))
+}
+
+impl SigmaRequest {
+    fn date_time_field<T>(
+        &self,
+        field: u16,
+        parse: impl FnOnce(&str) -> Result<T, Error>,
+    ) -> Result<Option<T>, Error> {
+        self.iso_fields
+            .get(&field)
+            .map(|v| parse(&v.to_cow_str_lossy()))
+            .transpose()
+    }
+
+    /// Transmission date and time (i007).
+    pub fn transmission_date_time(
+        &self,
+        reference: NaiveDate,
+    ) -> Result<Option<NaiveDateTime>, Error> {
+        self.date_time_field(TRANSMISSION_DATE_TIME_FIELD, |v| {
+            parse_mmddhhmmss(TRANSMISSION_DATE_TIME_FIELD, v, reference)
+        })
+    }
+
+    pub fn set_transmission_date_time(&mut self, v: NaiveDateTime) {
+        self.iso_fields.insert(
+            TRANSMISSION_DATE_TIME_FIELD,
+            v.format("%m%d%H%M%S").to_string().into(),
+        );
+    }
+
+    /// Local transaction time (i012).
+    pub fn local_time(&self) -> Result<Option<NaiveTime>, Error> {
+        self.date_time_field(LOCAL_TIME_FIELD, |v| parse_hhmmss(LOCAL_TIME_FIELD, v))
+    }
+
+    pub fn set_local_time(&mut self, v: NaiveTime) {
+        self.iso_fields
+            .insert(LOCAL_TIME_FIELD, v.format("%H%M%S").to_string().into());
+    }
+
+    /// Local transaction date (i013).
+    pub fn local_date(&self, reference: NaiveDate) -> Result<Option<NaiveDate>, Error> {
+        self.date_time_field(LOCAL_DATE_FIELD, |v| {
+            parse_mmdd(LOCAL_DATE_FIELD, v, reference)
+        })
+    }
+
+    pub fn set_local_date(&mut self, v: NaiveDate) {
+        self.iso_fields
+            .insert(LOCAL_DATE_FIELD, v.format("%m%d").to_string().into());
+    }
+
+    /// Local transaction date and time (i013 and i012), if both are present.
+    pub fn local_date_time(&self, reference: NaiveDate) -> Result<Option<NaiveDateTime>, Error> {
+        let date = self.local_date(reference)?;
+        let time = self.local_time()?;
+        Ok(date.zip(time).map(|(date, time)| date.and_time(time)))
+    }
+
+    /// Settlement date (i015).
+    pub fn settlement_date(&self, reference: NaiveDate) -> Result<Option<NaiveDate>, Error> {
+        self.date_time_field(SETTLEMENT_DATE_FIELD, |v| {
+            parse_mmdd(SETTLEMENT_DATE_FIELD, v, reference)
+        })
+    }
+
+    pub fn set_settlement_date(&mut self, v: NaiveDate) {
+        self.iso_fields
+            .insert(SETTLEMENT_DATE_FIELD, v.format("%m%d").to_string().into());
+    }
+
+    /// Capture date (i017).
+    pub fn capture_date(&self, reference: NaiveDate) -> Result<Option<NaiveDate>, Error> {
+        self.date_time_field(CAPTURE_DATE_FIELD, |v| {
+            parse_mmdd(CAPTURE_DATE_FIELD, v, reference)
+        })
+    }
+
+    pub fn set_capture_date(&mut self, v: NaiveDate) {
+        self.iso_fields
+            .insert(CAPTURE_DATE_FIELD, v.format("%m%d").to_string().into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn year_inference() {
+        assert_eq!(
+            infer_year(6, 29, date(2023, 6, 30)),
+            Some(date(2023, 6, 29))
+        );
+        assert_eq!(
+            infer_year(12, 31, date(2024, 1, 1)),
+            Some(date(2023, 12, 31))
+        );
+        assert_eq!(infer_year(1, 1, date(2023, 12, 31)), Some(date(2024, 1, 1)));
+        assert_eq!(infer_year(2, 29, date(2023, 3, 1)), Some(date(2024, 2, 29)));
+        assert_eq!(infer_year(2, 29, date(2024, 3, 1)), Some(date(2024, 2, 29)));
+        assert_eq!(infer_year(2, 30, date(2024, 3, 1)), None);
+        assert_eq!(infer_year(13, 1, date(2024, 3, 1)), None);
+    }
+
+    #[test]
+    fn getters() {
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        let reference = date(2024, 1, 1);
+        assert_eq!(req.transmission_date_time(reference), Ok(None));
+        assert_eq!(req.local_date_time(reference), Ok(None));
+
+        req.iso_fields.insert(7, "1231235959".into());
+        req.iso_fields.insert(12, "235958".into());
+        req.iso_fields.insert(13, "1231".into());
+        req.iso_fields.insert(15, "0101".into());
+        req.iso_fields.insert(17, "1230".into());
+
+        assert_eq!(
+            req.transmission_date_time(reference).unwrap(),
+            Some(date(2023, 12, 31).and_hms_opt(23, 59, 59).unwrap())
+        );
+        assert_eq!(
+            req.local_date_time(reference).unwrap(),
+            Some(date(2023, 12, 31).and_hms_opt(23, 59, 58).unwrap())
+        );
+        assert_eq!(req.settlement_date(reference).unwrap(), Some(reference));
+        assert_eq!(
+            req.capture_date(reference).unwrap(),
+            Some(date(2023, 12, 30))
+        );
+    }
+
+    #[test]
+    fn incorrect_values() {
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        let reference = date(2024, 1, 1);
+
+        req.iso_fields.insert(7, "123123595".into());
+        req.iso_fields.insert(12, "246000".into());
+        req.iso_fields.insert(13, "1332".into());
+        req.iso_fields.insert(15, "01O1".into());
+
+        assert!(req.transmission_date_time(reference).is_err());
+        assert!(req.local_time().is_err());
+        assert_eq!(
+            req.local_date(reference),
+            Err(Error::IncorrectFieldData {
+                field_name: "i013".into(),
+                should_be: "valid date MMDD".into()
+            })
+        );
+        assert_eq!(
+            req.settlement_date(reference),
+            Err(Error::IncorrectFieldData {
+                field_name: "i015".into(),
+                should_be: "4 digits".into()
+            })
+        );
+    }
+
+    #[test]
+    fn setters() {
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        let dt = date(2023, 6, 9).and_hms_opt(5, 7, 8).unwrap();
+
+        req.set_transmission_date_time(dt);
+        req.set_local_time(dt.time());
+        req.set_local_date(dt.date());
+        req.set_settlement_date(date(2023, 6, 10));
+        req.set_capture_date(dt.date());
+
+        assert_eq!(req.iso_fields.get(&7).unwrap(), "0609050708");
+        assert_eq!(req.iso_fields.get(&12).unwrap(), "050708");
+        assert_eq!(req.iso_fields.get(&13).unwrap(), "0609");
+        assert_eq!(req.iso_fields.get(&15).unwrap(), "0610");
+        assert_eq!(req.iso_fields.get(&17).unwrap(), "0609");
+        assert_eq!(
+            req.transmission_date_time(date(2023, 6, 9)).unwrap(),
+            Some(dt)
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Strictness of [`SigmaRequest::decode_with`](crate::SigmaRequest::decode_with) and
+//! [`SigmaResponse::decode_with`](crate::SigmaResponse::decode_with).
+
+use std::fmt;
+
+use bytes::Bytes;
+
+use crate::util::{bytes_split_to, decode_field_from_cursor, split_field_from_cursor};
+use crate::{Error, Tag};
+
+/// What the decoder tolerates.
+///
+/// The default is [`DecodeOptions::lenient`], which is what [`SigmaRequest::decode`](crate::SigmaRequest::decode)
+/// and [`SigmaResponse::decode`](crate::SigmaResponse::decode) use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Fail on non-ASCII header fields and tag (T) data instead of decoding them lossy.
+    pub reject_non_ascii: bool,
+    /// Fail on bytes following the message length instead of ignoring them.
+    pub reject_trailing_bytes: bool,
+    /// Fail on serno padded with spaces instead of trimming it.
+    pub reject_padded_serno: bool,
+    /// Fail on fields of kind other than T, I and S instead of skipping them; set in both
+    /// profiles, as decoding always failed on them.
+    pub reject_unknown_tag_kinds: bool,
+}
+
+impl DecodeOptions {
+    pub fn strict() -> Self {
+        Self {
+            reject_non_ascii: true,
+            reject_trailing_bytes: true,
+            reject_padded_serno: true,
+            reject_unknown_tag_kinds: true,
+        }
+    }
+
+    pub fn lenient() -> Self {
+        Self {
+            reject_non_ascii: false,
+            reject_trailing_bytes: false,
+            reject_padded_serno: false,
+            reject_unknown_tag_kinds: true,
+        }
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self::lenient()
+    }
+}
+
+/// Deviation tolerated by lenient decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeWarning {
+    /// Header field or tag data with non-ASCII bytes, named as "MTI", "T0018" etc.
+    NonAscii(String),
+    /// Number of bytes following the message.
+    TrailingBytes(usize),
+    PaddedSerno,
+    /// Kind byte of the skipped field.
+    UnknownTagKind(u8),
+}
+
+impl fmt::Display for DecodeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NonAscii(name) => write!(f, "Non-ASCII data in {}", name),
+            Self::TrailingBytes(n) => write!(f, "{} trailing bytes", n),
+            Self::PaddedSerno => f.write_str("Serno padded with spaces"),
+            Self::UnknownTagKind(b) => write!(f, "Skipped field of unknown kind 0x{:02X}", b),
+        }
+    }
+}
+
+/// Applies [`DecodeOptions`] and collects warnings.
+pub(crate) struct Decoder<'a> {
+    options: &'a DecodeOptions,
+    pub(crate) warnings: Vec<DecodeWarning>,
+}
+
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(options: &'a DecodeOptions) -> Self {
+        Self {
+            options,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Splits the message of given length off the data.
+    pub(crate) fn message(&mut self, mut data: Bytes) -> Result<Bytes, Error> {
+        let msg_len = parse_ascii_bytes_lossy!(
+            &bytes_split_to(&mut data, 5)?,
+            usize,
+            Error::incorrect_field_data("message length", "valid integer")
+        )?;
+        let msg = bytes_split_to(&mut data, msg_len)?;
+
+        if !data.is_empty() {
+            if self.options.reject_trailing_bytes {
+                return Err(Error::IncorrectData(format!(
+                    "{} trailing bytes after message",
+                    data.len()
+                )));
+            }
+            self.warnings.push(DecodeWarning::TrailingBytes(data.len()));
+        }
+        Ok(msg)
+    }
+
+    pub(crate) fn string(&mut self, name: &str, data: &[u8]) -> Result<String, Error> {
+        if !data.is_ascii() {
+            if self.options.reject_non_ascii {
+                return Err(Error::incorrect_field_data(name, "ASCII"));
+            }
+            self.warnings.push(DecodeWarning::NonAscii(name.into()));
+        }
+        Ok(String::from_utf8_lossy(data).into_owned())
+    }
+
+    pub(crate) fn serno(&mut self, data: &[u8]) -> Result<u64, Error> {
+        let s = self.string("Serno", data)?;
+        let trimmed = s.trim();
+        if trimmed.len() != s.len() {
+            if self.options.reject_padded_serno {
+                return Err(Error::incorrect_field_data("Serno", "10 digits"));
+            }
+            self.warnings.push(DecodeWarning::PaddedSerno);
+        }
+        trimmed
+            .parse::<u64>()
+            .map_err(|_| Error::incorrect_field_data("Serno", "u64"))
+    }
+
+    /// Next field of the message, fields of unknown kind are skipped unless rejected.
+    pub(crate) fn field(&mut self, data: &mut Bytes) -> Result<Option<(Tag, Bytes)>, Error> {
+        while !data.is_empty() {
+            if self.options.reject_unknown_tag_kinds {
+                return decode_field_from_cursor(data).map(Some);
+            }
+            let (tag_src, field_data) = split_field_from_cursor(data)?;
+            let kind = tag_src[0];
+            if !matches!(kind, b'T' | b'I' | b'S') {
+                self.warnings.push(DecodeWarning::UnknownTagKind(kind));
+                continue;
+            }
+            return Ok(Some((Tag::decode(tag_src)?, field_data)));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SigmaRequest, SigmaResponse};
+
+    const REQUEST: &[u8] = b"00033NM01000000000001\
+        T\x00\x18\x00\x00\x01Y\
+        I\x00\x02\x00\x00\x04\xd0\xb0bc";
+
+    #[test]
+    fn lenient() {
+        let mut data = REQUEST.to_vec();
+        // Serno " 000000001", T0018 of non-ASCII byte, field of unknown kind and trailing bytes
+        data[11] = b' ';
+        data[27] = 0xff;
+        data.extend_from_slice(b"X\x00\x01\x00\x00\x01Z");
+        data[0..5].copy_from_slice(b"00040");
+        data.extend_from_slice(b"000");
+
+        let skipping = DecodeOptions {
+            reject_unknown_tag_kinds: false,
+            ..DecodeOptions::lenient()
+        };
+        let (req, warnings) =
+            SigmaRequest::decode_with(Bytes::from(data.clone()), &skipping).unwrap();
+        assert_eq!(req.auth_serno, 1);
+        assert_eq!(req.tags.get(&18).unwrap(), "\u{fffd}");
+        assert_eq!(req.iso_fields.get(&2).unwrap(), &b"\xd0\xb0bc"[..]);
+        assert_eq!(
+            warnings,
+            vec![
+                DecodeWarning::TrailingBytes(3),
+                DecodeWarning::PaddedSerno,
+                DecodeWarning::NonAscii("T0018".into()),
+                DecodeWarning::UnknownTagKind(b'X'),
+            ]
+        );
+        let strict = DecodeOptions::strict();
+        assert_eq!(
+            SigmaRequest::decode_with(Bytes::from(data), &strict),
+            Err(Error::IncorrectData(
+                "3 trailing bytes after message".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn strict() {
+        let strict = DecodeOptions::strict();
+        let (req, warnings) =
+            SigmaRequest::decode_with(Bytes::from_static(REQUEST), &strict).unwrap();
+        assert!(warnings.is_empty());
+        // ISO fields may carry binary data
+        assert_eq!(req.iso_fields.get(&2).unwrap(), &b"\xd0\xb0bc"[..]);
+
+        let options = |f: fn(&mut DecodeOptions)| {
+            let mut options = DecodeOptions::lenient();
+            f(&mut options);
+            options
+        };
+
+        let mut data = REQUEST.to_vec();
+        data[11] = b' ';
+        assert_eq!(
+            SigmaRequest::decode_with(
+                Bytes::from(data),
+                &options(|o| o.reject_padded_serno = true)
+            ),
+            Err(Error::incorrect_field_data("Serno", "10 digits"))
+        );
+
+        let mut data = REQUEST.to_vec();
+        data[27] = 0xff;
+        assert_eq!(
+            SigmaRequest::decode_with(Bytes::from(data), &options(|o| o.reject_non_ascii = true)),
+            Err(Error::incorrect_field_data("T0018", "ASCII"))
+        );
+
+        let mut data = REQUEST.to_vec();
+        data[21] = b'X';
+        assert_eq!(
+            SigmaRequest::decode_with(
+                Bytes::from(data),
+                &options(|o| o.reject_unknown_tag_kinds = true)
+            ),
+            Err(Error::IncorrectTag("Unknown kind".into()))
+        );
+    }
+
+    #[test]
+    fn default_rejects_unknown_kind() {
+        let mut data = REQUEST.to_vec();
+        data[21] = b'X';
+        assert_eq!(
+            SigmaRequest::decode(Bytes::from(data)),
+            Err(Error::IncorrectTag("Unknown kind".into()))
+        );
+
+        let data = b"0002401100000000001X\x00\x31\x00\x00\x048100";
+        assert_eq!(
+            SigmaResponse::decode(Bytes::from_static(data)).unwrap_err(),
+            Error::IncorrectTag("Unknown kind".into())
+        );
+
+        // Without unknown fields lenient decoding matches the default one
+        let (req, _) =
+            SigmaRequest::decode_with(Bytes::from_static(REQUEST), &DecodeOptions::lenient())
+                .unwrap();
+        assert_eq!(
+            SigmaRequest::decode(Bytes::from_static(REQUEST)).unwrap(),
+            req
+        );
+    }
+
+    #[test]
+    fn response() {
+        let data = b"0002401100000000001T\x00\x31\x00\x00\x048100\xff\xff";
+        let (resp, warnings) =
+            SigmaResponse::decode_with(Bytes::from_static(data), &DecodeOptions::default())
+                .unwrap();
+        assert_eq!(resp.reason, 8100);
+        assert_eq!(warnings, vec![DecodeWarning::TrailingBytes(2)]);
+        assert!(
+            SigmaResponse::decode_with(Bytes::from_static(data), &DecodeOptions::strict()).is_err()
+        );
+        assert_eq!(
+            DecodeWarning::TrailingBytes(2).to_string(),
+            "2 trailing bytes"
+        );
+    }
+}
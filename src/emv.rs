@@ -0,0 +1,581 @@
+//! EMV ICC data (ISO field 55) in BER-TLV format.
+
+use std::fmt::Write;
+
+use crate::{IsoFieldData, SigmaRequest};
+
+/// ISO field which carries ICC data.
+pub const ICC_DATA_FIELD: u16 = 55;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq, Clone)]
+pub enum EmvError {
+    #[error("Truncated tag at offset {offset}")]
+    TruncatedTag { offset: usize },
+    #[error("Tag at offset {offset} is longer than 4 bytes")]
+    TagTooLong { offset: usize },
+    #[error("Truncated length of tag {tag:X} at offset {offset}")]
+    TruncatedLength { tag: u32, offset: usize },
+    #[error("Unsupported length byte {byte:#04X} of tag {tag:X} at offset {offset}")]
+    UnsupportedLength { tag: u32, offset: usize, byte: u8 },
+    #[error("Value of tag {tag:X} at offset {offset} should be {expected} bytes long, {available} available")]
+    TruncatedValue {
+        tag: u32,
+        offset: usize,
+        expected: usize,
+        available: usize,
+    },
+    #[error("Incorrect tag {0:X}")]
+    IncorrectTag(u32),
+    #[error("Value of tag {tag:X} should be {should_be}")]
+    IncorrectValue { tag: u32, should_be: String },
+}
+
+/// Data format of EMV data element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmvFormat {
+    /// `b`: binary.
+    Binary,
+    /// `n`: BCD digits, right justified and padded with leading zeros.
+    Numeric,
+    /// `cn`: BCD digits, left justified and padded with trailing `F`s.
+    CompressedNumeric,
+    /// `an`/`ans`: ASCII characters.
+    Alphanumeric,
+}
+
+/// Dictionary entry of a known EMV tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmvTagInfo {
+    pub tag: u32,
+    pub name: &'static str,
+    pub format: EmvFormat,
+    /// Fixed length of the value in bytes, if any.
+    pub length: Option<usize>,
+}
+
+macro_rules! emv_tags {
+    ($(($tag:literal, $name:literal, $format:ident, $length:expr)),* $(,)?) => {
+        /// Dictionary of common EMV tags.
+        pub const EMV_TAGS: &[EmvTagInfo] = &[
+            $(EmvTagInfo {
+                tag: $tag,
+                name: $name,
+                format: EmvFormat::$format,
+                length: $length,
+            }),*
+        ];
+    };
+}
+
+emv_tags! {
+    (0x4F, "Application Identifier (AID)", Binary, None),
+    (0x50, "Application Label", Alphanumeric, None),
+    (0x57, "Track 2 Equivalent Data", Binary, None),
+    (0x5A, "Application PAN", CompressedNumeric, None),
+    (0x82, "Application Interchange Profile", Binary, Some(2)),
+    (0x84, "Dedicated File Name", Binary, None),
+    (0x8A, "Authorisation Response Code", Alphanumeric, Some(2)),
+    (0x91, "Issuer Authentication Data", Binary, None),
+    (0x95, "Terminal Verification Results", Binary, Some(5)),
+    (0x9A, "Transaction Date", Numeric, Some(3)),
+    (0x9C, "Transaction Type", Numeric, Some(1)),
+    (0x5F24, "Application Expiration Date", Numeric, Some(3)),
+    (0x5F2A, "Transaction Currency Code", Numeric, Some(2)),
+    (0x5F34, "PAN Sequence Number", Numeric, Some(1)),
+    (0x9F02, "Amount, Authorised", Numeric, Some(6)),
+    (0x9F03, "Amount, Other", Numeric, Some(6)),
+    (0x9F06, "Application Identifier (Terminal)", Binary, None),
+    (0x9F07, "Application Usage Control", Binary, Some(2)),
+    (0x9F09, "Application Version Number (Terminal)", Binary, Some(2)),
+    (0x9F10, "Issuer Application Data", Binary, None),
+    (0x9F1A, "Terminal Country Code", Numeric, Some(2)),
+    (0x9F1E, "Interface Device Serial Number", Alphanumeric, Some(8)),
+    (0x9F21, "Transaction Time", Numeric, Some(3)),
+    (0x9F26, "Application Cryptogram", Binary, Some(8)),
+    (0x9F27, "Cryptogram Information Data", Binary, Some(1)),
+    (0x9F33, "Terminal Capabilities", Binary, Some(3)),
+    (0x9F34, "Cardholder Verification Method Results", Binary, Some(3)),
+    (0x9F35, "Terminal Type", Numeric, Some(1)),
+    (0x9F36, "Application Transaction Counter", Binary, Some(2)),
+    (0x9F37, "Unpredictable Number", Binary, Some(4)),
+    (0x9F41, "Transaction Sequence Counter", Numeric, None),
+    (0x9F53, "Transaction Category Code", Alphanumeric, Some(1)),
+    (0x9F6E, "Form Factor Indicator", Binary, None),
+}
+
+/// Dictionary entry of given tag, if it is known.
+pub fn tag_info(tag: u32) -> Option<&'static EmvTagInfo> {
+    EMV_TAGS.iter().find(|info| info.tag == tag)
+}
+
+/// Single BER-TLV data element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tlv {
+    pub tag: u32,
+    pub value: Vec<u8>,
+}
+
+impl Tlv {
+    pub fn new(tag: u32, value: Vec<u8>) -> Self {
+        Self { tag, value }
+    }
+
+    pub fn info(&self) -> Option<&'static EmvTagInfo> {
+        tag_info(self.tag)
+    }
+
+    /// Whether the element is a constructed one (contains other elements).
+    pub fn is_constructed(&self) -> bool {
+        tag_bytes(self.tag).first().is_some_and(|b| b & 0x20 != 0)
+    }
+}
+
+fn tag_bytes(tag: u32) -> Vec<u8> {
+    let bytes = tag.to_be_bytes();
+    let skip = bytes.iter().take(3).take_while(|b| **b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+fn encode_length(len: usize, buf: &mut Vec<u8>) {
+    match len {
+        0..=0x7F => buf.push(len as u8),
+        0x80..=0xFF => buf.extend_from_slice(&[0x81, len as u8]),
+        0x100..=0xFFFF => buf.extend_from_slice(&[0x82, (len >> 8) as u8, len as u8]),
+        _ => {
+            buf.push(0x84);
+            buf.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+}
+
+/// Decodes BCD digits, `F` nibbles are treated as padding and skipped.
+fn decode_bcd_digits(tag: u32, data: &[u8]) -> Result<String, EmvError> {
+    let mut s = String::with_capacity(data.len() * 2);
+    for nibble in data.iter().flat_map(|b| [b >> 4, b & 0x0F]) {
+        match nibble {
+            0..=9 => s.push((b'0' + nibble) as char),
+            0x0F => {}
+            _ => {
+                return Err(EmvError::IncorrectValue {
+                    tag,
+                    should_be: "BCD digits".into(),
+                })
+            }
+        }
+    }
+    Ok(s)
+}
+
+fn encode_bcd_digits(tag: u32, digits: &str, padding: u8) -> Result<Vec<u8>, EmvError> {
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(EmvError::IncorrectValue {
+            tag,
+            should_be: "digits".into(),
+        });
+    }
+    let mut nibbles = digits.bytes().map(|b| b - b'0').collect::<Vec<_>>();
+    if nibbles.len() % 2 == 1 {
+        match padding {
+            0 => nibbles.insert(0, 0),
+            _ => nibbles.push(padding),
+        }
+    }
+    Ok(nibbles.chunks(2).map(|c| (c[0] << 4) | c[1]).collect())
+}
+
+/// Ordered list of ICC data elements.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmvData {
+    pub elements: Vec<Tlv>,
+}
+
+impl EmvData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, EmvError> {
+        let mut elements = Vec::new();
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let offset = pos;
+
+            let mut tag = data[pos] as u32;
+            pos += 1;
+            if tag & 0x1F == 0x1F {
+                loop {
+                    let b = *data.get(pos).ok_or(EmvError::TruncatedTag { offset })?;
+                    if pos - offset >= 4 {
+                        return Err(EmvError::TagTooLong { offset });
+                    }
+                    tag = (tag << 8) | b as u32;
+                    pos += 1;
+                    if b & 0x80 == 0 {
+                        break;
+                    }
+                }
+            }
+
+            let length_offset = pos;
+            let first = *data
+                .get(pos)
+                .ok_or(EmvError::TruncatedLength { tag, offset })?;
+            pos += 1;
+            let len = match first {
+                0..=0x7F => first as usize,
+                0x81..=0x84 => {
+                    let count = (first & 0x7F) as usize;
+                    let bytes = data
+                        .get(pos..pos + count)
+                        .ok_or(EmvError::TruncatedLength { tag, offset })?;
+                    pos += count;
+                    bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize)
+                }
+                _ => {
+                    return Err(EmvError::UnsupportedLength {
+                        tag,
+                        offset: length_offset,
+                        byte: first,
+                    })
+                }
+            };
+
+            let value = data.get(pos..pos + len).ok_or(EmvError::TruncatedValue {
+                tag,
+                offset,
+                expected: len,
+                available: data.len() - pos,
+            })?;
+            pos += len;
+            elements.push(Tlv::new(tag, value.to_vec()));
+        }
+
+        Ok(Self { elements })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for element in self.elements.iter() {
+            buf.extend_from_slice(&tag_bytes(element.tag));
+            encode_length(element.value.len(), &mut buf);
+            buf.extend_from_slice(&element.value);
+        }
+        buf
+    }
+
+    /// Value of the first element with given tag.
+    pub fn get(&self, tag: u32) -> Option<&[u8]> {
+        self.elements
+            .iter()
+            .find(|element| element.tag == tag)
+            .map(|element| element.value.as_slice())
+    }
+
+    /// Replaces value of the first element with given tag or appends a new element.
+    pub fn set(&mut self, tag: u32, value: Vec<u8>) -> Result<(), EmvError> {
+        if tag == 0 || tag_bytes(tag).len() > 4 {
+            return Err(EmvError::IncorrectTag(tag));
+        }
+        match self.elements.iter_mut().find(|element| element.tag == tag) {
+            Some(element) => element.value = value,
+            None => self.elements.push(Tlv::new(tag, value)),
+        }
+        Ok(())
+    }
+
+    /// Removes all elements with given tag, returning the value of the first one.
+    pub fn remove(&mut self, tag: u32) -> Option<Vec<u8>> {
+        let pos = self
+            .elements
+            .iter()
+            .position(|element| element.tag == tag)?;
+        let removed = self.elements.remove(pos);
+        self.elements.retain(|element| element.tag != tag);
+        Some(removed.value)
+    }
+
+    /// Value of a numeric (`n` or `cn`) element as digits.
+    pub fn get_numeric(&self, tag: u32) -> Result<Option<String>, EmvError> {
+        self.get(tag)
+            .map(|value| decode_bcd_digits(tag, value))
+            .transpose()
+    }
+
+    /// Sets value of a numeric element; the format and the length are taken from the dictionary.
+    pub fn set_numeric(&mut self, tag: u32, digits: &str) -> Result<(), EmvError> {
+        let info = tag_info(tag);
+        let (padding, length) = match info.map(|info| (info.format, info.length)) {
+            Some((EmvFormat::CompressedNumeric, length)) => (0x0F, length),
+            Some((EmvFormat::Numeric, length)) | Some((EmvFormat::Binary, length)) => (0, length),
+            Some((EmvFormat::Alphanumeric, _)) => {
+                return Err(EmvError::IncorrectValue {
+                    tag,
+                    should_be: "alphanumeric".into(),
+                })
+            }
+            None => (0, None),
+        };
+
+        let mut value = encode_bcd_digits(tag, digits, padding)?;
+        if let Some(length) = length {
+            if value.len() > length {
+                return Err(EmvError::IncorrectValue {
+                    tag,
+                    should_be: format!("at most {} digits", length * 2),
+                });
+            }
+            let fill = length - value.len();
+            if padding == 0 {
+                value.splice(0..0, std::iter::repeat(0x00).take(fill));
+            } else {
+                value.resize(length, 0xFF);
+            }
+        }
+        self.set(tag, value)
+    }
+
+    /// Value of an alphanumeric element.
+    pub fn get_text(&self, tag: u32) -> Option<String> {
+        self.get(tag)
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+    }
+
+    pub fn amount_authorised(&self) -> Result<Option<u64>, EmvError> {
+        self.get_numeric_as(0x9F02)
+    }
+
+    pub fn set_amount_authorised(&mut self, amount: u64) -> Result<(), EmvError> {
+        self.set_numeric(0x9F02, &amount.to_string())
+    }
+
+    pub fn currency_code(&self) -> Result<Option<u16>, EmvError> {
+        self.get_numeric_as(0x5F2A)
+    }
+
+    pub fn set_currency_code(&mut self, code: u16) -> Result<(), EmvError> {
+        self.set_numeric(0x5F2A, &code.to_string())
+    }
+
+    pub fn transaction_type(&self) -> Result<Option<u8>, EmvError> {
+        self.get_numeric_as(0x9C)
+    }
+
+    /// Transaction date as `YYMMDD`.
+    pub fn transaction_date(&self) -> Result<Option<String>, EmvError> {
+        self.get_numeric(0x9A)
+    }
+
+    pub fn cryptogram(&self) -> Option<&[u8]> {
+        self.get(0x9F26)
+    }
+
+    pub fn cryptogram_information(&self) -> Option<u8> {
+        self.get(0x9F27).and_then(|v| v.first().copied())
+    }
+
+    pub fn application_transaction_counter(&self) -> Option<u16> {
+        self.get(0x9F36)
+            .filter(|v| v.len() == 2)
+            .map(|v| u16::from_be_bytes([v[0], v[1]]))
+    }
+
+    pub fn terminal_verification_results(&self) -> Option<&[u8]> {
+        self.get(0x95)
+    }
+
+    fn get_numeric_as<T: std::str::FromStr>(&self, tag: u32) -> Result<Option<T>, EmvError> {
+        self.get_numeric(tag)?
+            .map(|v| {
+                v.parse::<T>().map_err(|_| EmvError::IncorrectValue {
+                    tag,
+                    should_be: "number".into(),
+                })
+            })
+            .transpose()
+    }
+
+    /// Human-readable dump with tag names from the dictionary.
+    pub fn describe(&self) -> String {
+        let mut s = String::new();
+        for element in self.elements.iter() {
+            let name = element.info().map_or("Unknown", |info| info.name);
+            let _ = write!(s, "{:X} {}: ", element.tag, name);
+            for b in element.value.iter() {
+                let _ = write!(s, "{:02X}", b);
+            }
+            s.push('\n');
+        }
+        s
+    }
+}
+
+impl SigmaRequest {
+    /// Parsed ICC data (i055), if present.
+    pub fn emv_data(&self) -> Result<Option<EmvData>, EmvError> {
+        self.iso_fields
+            .get(&ICC_DATA_FIELD)
+            .map(|v| EmvData::parse(v.as_bytes()))
+            .transpose()
+    }
+
+    pub fn set_emv_data(&mut self, data: &EmvData) {
+        self.iso_fields
+            .insert(ICC_DATA_FIELD, IsoFieldData::Raw(data.encode()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ICC: &[u8] = b"\x9F\x26\x08\x11\x22\x33\x44\x55\x66\x77\x88\x9F\x27\x01\x80\x95\x05\x00\x00\x00\x80\x00\x9A\x03\x23\x10\x18\x9C\x01\x00\x5F\x2A\x02\x06\x43\x9F\x02\x06\x00\x00\x00\x01\x00\x00\x9F\x36\x02\x00\x2A";
+
+    #[test]
+    fn parse_and_encode() {
+        let data = EmvData::parse(ICC).unwrap();
+        assert_eq!(data.elements.len(), 8);
+        assert_eq!(
+            data.elements.iter().map(|e| e.tag).collect::<Vec<_>>(),
+            vec![0x9F26, 0x9F27, 0x95, 0x9A, 0x9C, 0x5F2A, 0x9F02, 0x9F36]
+        );
+        assert_eq!(
+            data.cryptogram().unwrap(),
+            b"\x11\x22\x33\x44\x55\x66\x77\x88"
+        );
+        assert_eq!(data.cryptogram_information(), Some(0x80));
+        assert_eq!(
+            data.terminal_verification_results().unwrap(),
+            b"\x00\x00\x00\x80\x00"
+        );
+        assert_eq!(data.transaction_date().unwrap().unwrap(), "231018");
+        assert_eq!(data.transaction_type().unwrap(), Some(0));
+        assert_eq!(data.currency_code().unwrap(), Some(643));
+        assert_eq!(data.amount_authorised().unwrap(), Some(10000));
+        assert_eq!(data.application_transaction_counter(), Some(42));
+
+        assert_eq!(data.encode(), ICC);
+    }
+
+    #[test]
+    fn long_lengths() {
+        let mut data = EmvData::new();
+        data.set(0x9F10, vec![0xAB; 200]).unwrap();
+        data.set(0x91, vec![0xCD; 300]).unwrap();
+
+        let encoded = data.encode();
+        assert_eq!(&encoded[..4], b"\x9F\x10\x81\xC8");
+        assert_eq!(&encoded[204..208], b"\x91\x82\x01\x2C");
+        assert_eq!(EmvData::parse(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn setters() {
+        let mut data = EmvData::parse(ICC).unwrap();
+        data.set_amount_authorised(123456).unwrap();
+        data.set_currency_code(978).unwrap();
+        data.set_numeric(0x5A, "5555441234561").unwrap();
+        data.set(0x9F37, vec![1, 2, 3, 4]).unwrap();
+
+        assert_eq!(data.get(0x9F02).unwrap(), b"\x00\x00\x00\x12\x34\x56");
+        assert_eq!(data.get(0x5F2A).unwrap(), b"\x09\x78");
+        assert_eq!(data.get(0x5A).unwrap(), b"\x55\x55\x44\x12\x34\x56\x1F");
+        assert_eq!(data.get_numeric(0x5A).unwrap().unwrap(), "5555441234561");
+        assert_eq!(data.elements.len(), 10);
+        assert_eq!(data.elements.last().unwrap().tag, 0x9F37);
+
+        assert!(data.set_numeric(0x9F02, "1234567890123").is_err());
+        assert!(data.set_numeric(0x9F02, "12A").is_err());
+        assert!(data.set_numeric(0x9F1E, "1234").is_err());
+        assert!(data.set(0, vec![]).is_err());
+
+        assert_eq!(data.remove(0x9F37), Some(vec![1, 2, 3, 4]));
+        assert_eq!(data.remove(0x9F37), None);
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(
+            EmvData::parse(b"\x9F"),
+            Err(EmvError::TruncatedTag { offset: 0 })
+        );
+        assert_eq!(
+            EmvData::parse(b"\x9F\x81\x81\x81\x01\x00"),
+            Err(EmvError::TagTooLong { offset: 0 })
+        );
+        assert_eq!(
+            EmvData::parse(b"\x9C\x01\x00\x9F\x27"),
+            Err(EmvError::TruncatedLength {
+                tag: 0x9F27,
+                offset: 3
+            })
+        );
+        assert_eq!(
+            EmvData::parse(b"\x91\x82\x01"),
+            Err(EmvError::TruncatedLength {
+                tag: 0x91,
+                offset: 0
+            })
+        );
+        assert_eq!(
+            EmvData::parse(b"\x91\x80\x00\x00"),
+            Err(EmvError::UnsupportedLength {
+                tag: 0x91,
+                offset: 1,
+                byte: 0x80
+            })
+        );
+        assert_eq!(
+            EmvData::parse(b"\x9F\x26\x08\x11\x22"),
+            Err(EmvError::TruncatedValue {
+                tag: 0x9F26,
+                offset: 0,
+                expected: 8,
+                available: 2
+            })
+        );
+        assert!(EmvData::parse(b"\x9A\x03\x23\x1A\x18")
+            .unwrap()
+            .transaction_date()
+            .is_err());
+    }
+
+    #[test]
+    fn dictionary() {
+        let info = tag_info(0x9F26).unwrap();
+        assert_eq!(info.name, "Application Cryptogram");
+        assert_eq!(info.format, EmvFormat::Binary);
+        assert_eq!(info.length, Some(8));
+        assert!(tag_info(0xDF01).is_none());
+
+        let describe = EmvData::parse(b"\x9C\x01\x00\xDF\x01\x01\xFF")
+            .unwrap()
+            .describe();
+        assert_eq!(describe, "9C Transaction Type: 00\nDF01 Unknown: FF\n");
+    }
+
+    #[test]
+    fn constructed() {
+        assert!(Tlv::new(0x70, vec![]).is_constructed());
+        assert!(Tlv::new(0xBF0C, vec![]).is_constructed());
+        assert!(!Tlv::new(0x9F26, vec![]).is_constructed());
+    }
+
+    #[test]
+    fn request_icc_data() {
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        assert_eq!(req.emv_data(), Ok(None));
+
+        req.iso_fields.insert(55, ICC.into());
+        let mut data = req.emv_data().unwrap().unwrap();
+        data.set_amount_authorised(1).unwrap();
+        req.set_emv_data(&data);
+
+        assert_eq!(
+            req.emv_data().unwrap().unwrap().amount_authorised(),
+            Ok(Some(1))
+        );
+        req.iso_fields.insert(55, b"\x9F".as_ref().into());
+        assert!(req.emv_data().is_err());
+    }
+}
@@ -0,0 +1,910 @@
+//! Conversion between Sigma messages and ISO 8583:1987 messages.
+//!
+//! [`Iso8583Message`] keeps field values unencoded (digits of BCD fields as ASCII, binary
+//! fields as is) and is encoded into bytes according to [`Iso8583Spec`]. Message length
+//! header is not included, as it is specific to the network.
+//!
+//! Sigma-specific data is carried in a private field ([`Iso8583Spec::sigma_field`]) in the
+//! same order as in the Sigma message header: SAF (1), source (1) and MTI (4) for requests,
+//! authorization serno (10), then every tag as 4 digit tag id, 3 digit length and data.
+
+use std::collections::BTreeMap;
+
+use crate::{Error, FeeData, IsoFieldData, SigmaRequest, SigmaResponse};
+
+#[derive(Debug, thiserror::Error, PartialEq, Clone)]
+pub enum Iso8583Error {
+    #[error("Field {0} is not defined by the spec")]
+    UnknownField(u16),
+    #[error("Field {0} is reserved for Sigma data")]
+    ReservedField(u16),
+    #[error("Field {field} has length {length}, should be {expected}")]
+    IncorrectLength {
+        field: u16,
+        length: usize,
+        expected: usize,
+    },
+    #[error("Field {field} has length {length}, at most {max} allowed")]
+    FieldTooLong {
+        field: u16,
+        length: usize,
+        max: usize,
+    },
+    #[error("Field {field} should be {should_be}")]
+    IncorrectData { field: u16, should_be: String },
+    #[error("Unexpected end of message reading {0}")]
+    UnexpectedEnd(String),
+    #[error("Missing field {0}")]
+    MissingField(u16),
+    #[error(transparent)]
+    Sigma(#[from] Error),
+}
+
+/// Encoding of numbers: MTI and lengths of variable length fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberEncoding {
+    #[default]
+    Ascii,
+    Bcd,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BitmapEncoding {
+    /// 8 bytes per bitmap.
+    #[default]
+    Binary,
+    /// 16 hexadecimal characters per bitmap.
+    Hex,
+}
+
+/// Encoding of field data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataEncoding {
+    /// Characters as is, length is in bytes.
+    Ascii,
+    /// Packed digits, length is in digits.
+    Bcd,
+    /// Bytes as is, length is in bytes.
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldLength {
+    Fixed(usize),
+    /// Variable length with 2 digit length prefix and given maximum.
+    LlVar(usize),
+    /// Variable length with 3 digit length prefix and given maximum.
+    LllVar(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldSpec {
+    pub length: FieldLength,
+    pub encoding: DataEncoding,
+}
+
+impl FieldSpec {
+    pub fn new(length: FieldLength, encoding: DataEncoding) -> Self {
+        Self { length, encoding }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    N,
+    An,
+    B,
+}
+
+use FieldLength::{Fixed, LlVar, LllVar};
+use Kind::{An, B, N};
+
+/// Field definitions of ISO 8583:1987, binary fields have their length in bytes.
+const ISO8583_1987: &[(u16, Kind, FieldLength)] = &[
+    (2, N, LlVar(19)),
+    (3, N, Fixed(6)),
+    (4, N, Fixed(12)),
+    (5, N, Fixed(12)),
+    (6, N, Fixed(12)),
+    (7, N, Fixed(10)),
+    (8, N, Fixed(8)),
+    (9, N, Fixed(8)),
+    (10, N, Fixed(8)),
+    (11, N, Fixed(6)),
+    (12, N, Fixed(6)),
+    (13, N, Fixed(4)),
+    (14, N, Fixed(4)),
+    (15, N, Fixed(4)),
+    (16, N, Fixed(4)),
+    (17, N, Fixed(4)),
+    (18, N, Fixed(4)),
+    (19, N, Fixed(3)),
+    (20, N, Fixed(3)),
+    (21, N, Fixed(3)),
+    (22, N, Fixed(3)),
+    (23, N, Fixed(3)),
+    (24, N, Fixed(3)),
+    (25, N, Fixed(2)),
+    (26, N, Fixed(2)),
+    (27, N, Fixed(1)),
+    (28, An, Fixed(9)),
+    (29, An, Fixed(9)),
+    (30, An, Fixed(9)),
+    (31, An, Fixed(9)),
+    (32, N, LlVar(11)),
+    (33, N, LlVar(11)),
+    (34, An, LlVar(28)),
+    (35, An, LlVar(37)),
+    (36, An, LllVar(104)),
+    (37, An, Fixed(12)),
+    (38, An, Fixed(6)),
+    (39, An, Fixed(2)),
+    (40, An, Fixed(3)),
+    (41, An, Fixed(8)),
+    (42, An, Fixed(15)),
+    (43, An, Fixed(40)),
+    (44, An, LlVar(25)),
+    (45, An, LlVar(76)),
+    (46, An, LllVar(999)),
+    (47, An, LllVar(999)),
+    (48, An, LllVar(999)),
+    (49, An, Fixed(3)),
+    (50, An, Fixed(3)),
+    (51, An, Fixed(3)),
+    (52, B, Fixed(8)),
+    (53, N, Fixed(16)),
+    (54, An, LllVar(120)),
+    (55, B, LllVar(999)),
+    (56, An, LllVar(999)),
+    (57, An, LllVar(999)),
+    (58, An, LllVar(999)),
+    (59, An, LllVar(999)),
+    (60, An, LllVar(999)),
+    (61, An, LllVar(999)),
+    (62, An, LllVar(999)),
+    (63, An, LllVar(999)),
+    (64, B, Fixed(8)),
+    (66, N, Fixed(1)),
+    (67, N, Fixed(2)),
+    (68, N, Fixed(3)),
+    (69, N, Fixed(3)),
+    (70, N, Fixed(3)),
+    (71, N, Fixed(4)),
+    (72, N, Fixed(4)),
+    (73, N, Fixed(6)),
+    (74, N, Fixed(10)),
+    (75, N, Fixed(10)),
+    (76, N, Fixed(10)),
+    (77, N, Fixed(10)),
+    (78, N, Fixed(10)),
+    (79, N, Fixed(10)),
+    (80, N, Fixed(10)),
+    (81, N, Fixed(10)),
+    (82, N, Fixed(12)),
+    (83, N, Fixed(12)),
+    (84, N, Fixed(12)),
+    (85, N, Fixed(12)),
+    (86, N, Fixed(16)),
+    (87, N, Fixed(16)),
+    (88, N, Fixed(16)),
+    (89, N, Fixed(16)),
+    (90, N, Fixed(42)),
+    (91, An, Fixed(1)),
+    (92, An, Fixed(2)),
+    (93, An, Fixed(5)),
+    (94, An, Fixed(7)),
+    (95, An, Fixed(42)),
+    (96, B, Fixed(8)),
+    (97, An, Fixed(17)),
+    (98, An, Fixed(25)),
+    (99, N, LlVar(11)),
+    (100, N, LlVar(11)),
+    (101, An, LlVar(17)),
+    (102, An, LlVar(28)),
+    (103, An, LlVar(28)),
+    (104, An, LllVar(100)),
+    (105, An, LllVar(999)),
+    (106, An, LllVar(999)),
+    (107, An, LllVar(999)),
+    (108, An, LllVar(999)),
+    (109, An, LllVar(999)),
+    (110, An, LllVar(999)),
+    (111, An, LllVar(999)),
+    (112, An, LllVar(999)),
+    (113, An, LllVar(999)),
+    (114, An, LllVar(999)),
+    (115, An, LllVar(999)),
+    (116, An, LllVar(999)),
+    (117, An, LllVar(999)),
+    (118, An, LllVar(999)),
+    (119, An, LllVar(999)),
+    (120, An, LllVar(999)),
+    (121, An, LllVar(999)),
+    (122, An, LllVar(999)),
+    (123, An, LllVar(999)),
+    (124, An, LllVar(999)),
+    (125, An, LllVar(999)),
+    (126, An, LllVar(999)),
+    (127, An, LllVar(999)),
+    (128, B, Fixed(8)),
+];
+
+/// Field definitions and encodings of ISO 8583 messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Iso8583Spec {
+    pub fields: BTreeMap<u16, FieldSpec>,
+    pub mti_encoding: NumberEncoding,
+    pub length_encoding: NumberEncoding,
+    pub bitmap_encoding: BitmapEncoding,
+    /// Private field which carries Sigma header and tags.
+    pub sigma_field: u16,
+}
+
+impl Default for Iso8583Spec {
+    fn default() -> Self {
+        Self::ascii()
+    }
+}
+
+impl Iso8583Spec {
+    fn iso8583_1987(numeric: DataEncoding, numbers: NumberEncoding) -> Self {
+        let fields = ISO8583_1987
+            .iter()
+            .map(|(i, kind, length)| {
+                let encoding = match kind {
+                    N => numeric,
+                    An => DataEncoding::Ascii,
+                    B => DataEncoding::Binary,
+                };
+                (*i, FieldSpec::new(*length, encoding))
+            })
+            .collect();
+        Self {
+            fields,
+            mti_encoding: numbers,
+            length_encoding: numbers,
+            bitmap_encoding: BitmapEncoding::Binary,
+            sigma_field: 126,
+        }
+    }
+
+    /// ISO 8583:1987 with MTI, lengths and numeric fields in ASCII.
+    pub fn ascii() -> Self {
+        Self::iso8583_1987(DataEncoding::Ascii, NumberEncoding::Ascii)
+    }
+
+    /// ISO 8583:1987 with MTI, lengths and numeric fields in BCD.
+    pub fn bcd() -> Self {
+        Self::iso8583_1987(DataEncoding::Bcd, NumberEncoding::Bcd)
+    }
+
+    pub fn with_field(mut self, i: u16, spec: FieldSpec) -> Self {
+        self.fields.insert(i, spec);
+        self
+    }
+
+    pub fn with_bitmap_encoding(mut self, v: BitmapEncoding) -> Self {
+        self.bitmap_encoding = v;
+        self
+    }
+
+    pub fn with_sigma_field(mut self, v: u16) -> Self {
+        self.sigma_field = v;
+        self
+    }
+
+    fn field(&self, i: u16) -> Result<&FieldSpec, Iso8583Error> {
+        match i {
+            2..=128 => self.fields.get(&i).ok_or(Iso8583Error::UnknownField(i)),
+            _ => Err(Iso8583Error::UnknownField(i)),
+        }
+    }
+}
+
+fn bcd_encode(digits: &[u8], field: u16, buf: &mut Vec<u8>) -> Result<(), Iso8583Error> {
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return Err(Iso8583Error::IncorrectData {
+            field,
+            should_be: "digits".into(),
+        });
+    }
+    let pad = digits.len() % 2;
+    let nibbles = std::iter::repeat(0)
+        .take(pad)
+        .chain(digits.iter().map(|d| d - b'0'));
+    let nibbles = nibbles.collect::<Vec<_>>();
+    buf.extend(nibbles.chunks(2).map(|c| (c[0] << 4) | c[1]));
+    Ok(())
+}
+
+fn bcd_decode(data: &[u8], digits: usize, field: u16) -> Result<Vec<u8>, Iso8583Error> {
+    let mut s = Vec::with_capacity(data.len() * 2);
+    for nibble in data.iter().flat_map(|b| [b >> 4, b & 0x0F]) {
+        if nibble > 9 {
+            return Err(Iso8583Error::IncorrectData {
+                field,
+                should_be: "BCD digits".into(),
+            });
+        }
+        s.push(b'0' + nibble);
+    }
+    Ok(s.split_off(s.len() - digits))
+}
+
+fn encode_number(
+    n: usize,
+    digits: usize,
+    encoding: NumberEncoding,
+    field: u16,
+    buf: &mut Vec<u8>,
+) -> Result<(), Iso8583Error> {
+    let s = format!("{:0width$}", n, width = digits);
+    match encoding {
+        NumberEncoding::Ascii => buf.extend_from_slice(s.as_bytes()),
+        NumberEncoding::Bcd => bcd_encode(s.as_bytes(), field, buf)?,
+    }
+    Ok(())
+}
+
+/// Reads cursor for decoding.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize, what: &str) -> Result<&'a [u8], Iso8583Error> {
+        let v = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| Iso8583Error::UnexpectedEnd(what.into()))?;
+        self.pos += n;
+        Ok(v)
+    }
+
+    fn number(
+        &mut self,
+        digits: usize,
+        encoding: NumberEncoding,
+        field: u16,
+        what: &str,
+    ) -> Result<Vec<u8>, Iso8583Error> {
+        match encoding {
+            NumberEncoding::Ascii => {
+                let v = self.take(digits, what)?;
+                if !v.iter().all(u8::is_ascii_digit) {
+                    return Err(Iso8583Error::IncorrectData {
+                        field,
+                        should_be: format!("{} digits", what),
+                    });
+                }
+                Ok(v.to_vec())
+            }
+            NumberEncoding::Bcd => bcd_decode(self.take(digits.div_ceil(2), what)?, digits, field),
+        }
+    }
+}
+
+fn digits_to_usize(v: &[u8]) -> usize {
+    v.iter().fold(0, |acc, d| acc * 10 + (d - b'0') as usize)
+}
+
+/// ISO 8583 message with unencoded field values.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Iso8583Message {
+    pub mti: String,
+    pub fields: BTreeMap<u16, Vec<u8>>,
+}
+
+impl Iso8583Message {
+    pub fn new(mti: &str) -> Self {
+        Self {
+            mti: mti.into(),
+            fields: BTreeMap::new(),
+        }
+    }
+
+    pub fn encode(&self, spec: &Iso8583Spec) -> Result<Vec<u8>, Iso8583Error> {
+        let mut buf = Vec::with_capacity(512);
+
+        if self.mti.len() != 4 {
+            return Err(Iso8583Error::IncorrectData {
+                field: 0,
+                should_be: "4 digits".into(),
+            });
+        }
+        match spec.mti_encoding {
+            NumberEncoding::Ascii if self.mti.bytes().all(|b| b.is_ascii_digit()) => {
+                buf.extend_from_slice(self.mti.as_bytes())
+            }
+            NumberEncoding::Ascii => {
+                return Err(Iso8583Error::IncorrectData {
+                    field: 0,
+                    should_be: "4 digits".into(),
+                })
+            }
+            NumberEncoding::Bcd => bcd_encode(self.mti.as_bytes(), 0, &mut buf)?,
+        }
+
+        let mut bitmap = [0u8; 16];
+        let secondary = self.fields.keys().any(|i| *i > 64);
+        if secondary {
+            bitmap[0] |= 0x80;
+        }
+        for i in self.fields.keys() {
+            spec.field(*i)?;
+            let bit = (*i - 1) as usize;
+            bitmap[bit / 8] |= 0x80 >> (bit % 8);
+        }
+        let bitmap = &bitmap[..if secondary { 16 } else { 8 }];
+        match spec.bitmap_encoding {
+            BitmapEncoding::Binary => buf.extend_from_slice(bitmap),
+            BitmapEncoding::Hex => {
+                for b in bitmap {
+                    buf.extend_from_slice(format!("{:02X}", b).as_bytes());
+                }
+            }
+        }
+
+        for (i, v) in self.fields.iter() {
+            let field = spec.field(*i)?;
+            let length = v.len();
+            match field.length {
+                Fixed(expected) if length != expected => {
+                    return Err(Iso8583Error::IncorrectLength {
+                        field: *i,
+                        length,
+                        expected,
+                    })
+                }
+                LlVar(max) | LllVar(max) if length > max => {
+                    return Err(Iso8583Error::FieldTooLong {
+                        field: *i,
+                        length,
+                        max,
+                    })
+                }
+                LlVar(_) => encode_number(length, 2, spec.length_encoding, *i, &mut buf)?,
+                LllVar(_) => encode_number(length, 3, spec.length_encoding, *i, &mut buf)?,
+                Fixed(_) => {}
+            }
+            match field.encoding {
+                DataEncoding::Bcd => bcd_encode(v, *i, &mut buf)?,
+                DataEncoding::Ascii | DataEncoding::Binary => buf.extend_from_slice(v),
+            }
+        }
+
+        Ok(buf)
+    }
+
+    pub fn decode(spec: &Iso8583Spec, data: &[u8]) -> Result<Self, Iso8583Error> {
+        let mut reader = Reader { data, pos: 0 };
+
+        let mti = reader.number(4, spec.mti_encoding, 0, "MTI")?;
+        let mut msg = Self::new(&String::from_utf8_lossy(&mti));
+
+        let read_bitmap = |reader: &mut Reader| -> Result<[u8; 8], Iso8583Error> {
+            let mut bitmap = [0u8; 8];
+            match spec.bitmap_encoding {
+                BitmapEncoding::Binary => bitmap.copy_from_slice(reader.take(8, "bitmap")?),
+                BitmapEncoding::Hex => {
+                    let hex = reader.take(16, "bitmap")?;
+                    for (b, c) in bitmap.iter_mut().zip(hex.chunks(2)) {
+                        *b = std::str::from_utf8(c)
+                            .ok()
+                            .and_then(|c| u8::from_str_radix(c, 16).ok())
+                            .ok_or_else(|| Iso8583Error::IncorrectData {
+                                field: 1,
+                                should_be: "hexadecimal bitmap".into(),
+                            })?;
+                    }
+                }
+            }
+            Ok(bitmap)
+        };
+        let mut bitmap = read_bitmap(&mut reader)?.to_vec();
+        if bitmap[0] & 0x80 != 0 {
+            bitmap.extend_from_slice(&read_bitmap(&mut reader)?);
+        }
+
+        for bit in 1..bitmap.len() * 8 {
+            if bitmap[bit / 8] & (0x80 >> (bit % 8)) == 0 {
+                continue;
+            }
+            let i = bit as u16 + 1;
+            let field = spec.field(i)?;
+            let what = format!("field {}", i);
+            let (digits, max) = match field.length {
+                Fixed(v) => (0, v),
+                LlVar(max) => (2, max),
+                LllVar(max) => (3, max),
+            };
+            let length = match digits {
+                0 => max,
+                _ => digits_to_usize(&reader.number(digits, spec.length_encoding, i, &what)?),
+            };
+            if length > max {
+                return Err(Iso8583Error::FieldTooLong {
+                    field: i,
+                    length,
+                    max,
+                });
+            }
+            let value = match field.encoding {
+                DataEncoding::Bcd => {
+                    bcd_decode(reader.take(length.div_ceil(2), &what)?, length, i)?
+                }
+                DataEncoding::Ascii | DataEncoding::Binary => reader.take(length, &what)?.to_vec(),
+            };
+            msg.fields.insert(i, value);
+        }
+
+        Ok(msg)
+    }
+}
+
+fn encode_sigma_tags<'a>(
+    tags: impl Iterator<Item = (u16, &'a [u8])>,
+    field: u16,
+    buf: &mut Vec<u8>,
+) -> Result<(), Iso8583Error> {
+    for (tag, v) in tags {
+        if tag > 9999 || v.len() > 999 {
+            return Err(Iso8583Error::IncorrectData {
+                field,
+                should_be: "Sigma tags with id up to 9999 and data up to 999 bytes".into(),
+            });
+        }
+        buf.extend_from_slice(format!("{:04}{:03}", tag, v.len()).as_bytes());
+        buf.extend_from_slice(v);
+    }
+    Ok(())
+}
+
+fn decode_sigma_tags(field: u16, data: &[u8]) -> Result<Vec<(u16, &[u8])>, Iso8583Error> {
+    let mut reader = Reader { data, pos: 0 };
+    let mut tags = Vec::new();
+    while reader.pos < data.len() {
+        let tag = digits_to_usize(&reader.number(4, NumberEncoding::Ascii, field, "Sigma tag")?);
+        let length =
+            digits_to_usize(&reader.number(3, NumberEncoding::Ascii, field, "Sigma tag length")?);
+        tags.push((tag as u16, reader.take(length, "Sigma tag data")?));
+    }
+    Ok(tags)
+}
+
+fn decode_serno(field: u16, data: &[u8]) -> Result<u64, Iso8583Error> {
+    std::str::from_utf8(data)
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .ok_or_else(|| Iso8583Error::IncorrectData {
+            field,
+            should_be: "Sigma serno".into(),
+        })
+}
+
+fn serno_digits(v: u64) -> String {
+    let s = format!("{:010}", v);
+    s[..10].to_string()
+}
+
+impl SigmaRequest {
+    /// Converts the request into ISO 8583 message.
+    ///
+    /// ISO MTI is taken from i000 if present, Sigma MTI is kept in the private field.
+    /// Subfields are concatenated in their order into their field, unless the field itself is
+    /// present.
+    pub fn to_iso8583(&self, spec: &Iso8583Spec) -> Result<Iso8583Message, Iso8583Error> {
+        let mti = match self.iso_fields.get(&0) {
+            Some(v) => v.to_cow_str_lossy().into_owned(),
+            None => self.mti().to_string(),
+        };
+        let mut msg = Iso8583Message::new(&mti);
+
+        for (i, v) in self.iso_fields.iter().filter(|(i, _)| **i > 1) {
+            if *i == spec.sigma_field {
+                return Err(Iso8583Error::ReservedField(*i));
+            }
+            msg.fields.insert(*i, v.as_bytes().to_vec());
+        }
+        for ((i, _), v) in self.iso_subfields.iter() {
+            if *i == spec.sigma_field {
+                return Err(Iso8583Error::ReservedField(*i));
+            }
+            if !self.iso_fields.contains_key(i) {
+                msg.fields
+                    .entry(*i)
+                    .or_default()
+                    .extend_from_slice(v.as_bytes());
+            }
+        }
+
+        let mut sigma = format!(
+            "{}{}{}{}",
+            self.saf(),
+            self.source(),
+            self.mti(),
+            serno_digits(self.auth_serno)
+        )
+        .into_bytes();
+        encode_sigma_tags(
+            self.tags.iter().map(|(k, v)| (*k, v.as_bytes())),
+            spec.sigma_field,
+            &mut sigma,
+        )?;
+        msg.fields.insert(spec.sigma_field, sigma);
+
+        Ok(msg)
+    }
+
+    /// Converts ISO 8583 message made by [`SigmaRequest::to_iso8583`] back. ISO MTI other than
+    /// the Sigma MTI is restored as i000; i000 equal to the Sigma MTI is redundant and is lost.
+    pub fn from_iso8583(msg: &Iso8583Message, spec: &Iso8583Spec) -> Result<Self, Iso8583Error> {
+        let field = spec.sigma_field;
+        let sigma = msg
+            .fields
+            .get(&field)
+            .ok_or(Iso8583Error::MissingField(field))?;
+        if sigma.len() < 16 {
+            return Err(Iso8583Error::UnexpectedEnd("Sigma header".into()));
+        }
+
+        let mut req = Self::new(
+            &String::from_utf8_lossy(&sigma[..1]),
+            &String::from_utf8_lossy(&sigma[1..2]),
+            &String::from_utf8_lossy(&sigma[2..6]),
+            decode_serno(field, &sigma[6..16])?,
+        )?;
+        if msg.mti != req.mti() {
+            req.iso_fields.insert(0, msg.mti.as_str().into());
+        }
+        for (tag, v) in decode_sigma_tags(field, &sigma[16..])? {
+            req.tags
+                .insert(tag, String::from_utf8_lossy(v).into_owned());
+        }
+        for (i, v) in msg.fields.iter().filter(|(i, _)| **i != field) {
+            req.iso_fields
+                .insert(*i, IsoFieldData::from_bytes(v.clone().into()));
+        }
+
+        Ok(req)
+    }
+}
+
+impl SigmaResponse {
+    pub fn to_iso8583(&self, spec: &Iso8583Spec) -> Result<Iso8583Message, Iso8583Error> {
+        let mut msg = Iso8583Message::new(self.mti());
+
+        let reason = self.reason.to_string();
+        let fees = self
+            .fees
+            .iter()
+            .map(FeeData::encode)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut tags = vec![(31, reason.as_bytes())];
+        tags.extend(fees.iter().map(|v| (32, v.as_ref())));
+        let optional = [(33, &self.xri), (48, &self.adata), (50, &self.supdata)];
+        tags.extend(
+            optional
+                .iter()
+                .filter_map(|(tag, v)| v.as_ref().map(|v| (*tag, v.as_bytes()))),
+        );
+
+        let mut sigma = serno_digits(self.auth_serno).into_bytes();
+        encode_sigma_tags(tags.into_iter(), spec.sigma_field, &mut sigma)?;
+        msg.fields.insert(spec.sigma_field, sigma);
+
+        Ok(msg)
+    }
+
+    /// Builds Sigma response of ISO 8583 message, which has to carry Sigma reason (T0031)
+    /// in the Sigma field. Other ISO fields are ignored.
+    pub fn from_iso8583(msg: &Iso8583Message, spec: &Iso8583Spec) -> Result<Self, Iso8583Error> {
+        let field = spec.sigma_field;
+        let sigma = msg
+            .fields
+            .get(&field)
+            .ok_or(Iso8583Error::MissingField(field))?;
+        if sigma.len() < 10 {
+            return Err(Iso8583Error::UnexpectedEnd("Sigma header".into()));
+        }
+
+        let mut resp = Self::new(&msg.mti, decode_serno(field, &sigma[..10])?, 0)?;
+        let mut reason = None;
+        for (tag, v) in decode_sigma_tags(field, &sigma[10..])? {
+            let text = || Some(String::from_utf8_lossy(v).into_owned());
+            match tag {
+                31 => {
+                    reason = Some(
+                        std::str::from_utf8(v)
+                            .ok()
+                            .and_then(|v| v.parse::<u32>().ok())
+                            .ok_or_else(|| Iso8583Error::IncorrectData {
+                                field,
+                                should_be: "valid reason (T0031)".into(),
+                            })?,
+                    )
+                }
+                32 => resp.fees.push(FeeData::from_slice(v)?),
+                33 => resp.xri = text(),
+                48 => resp.adata = text(),
+                50 => resp.supdata = text(),
+                _ => {}
+            }
+        }
+        resp.reason = reason.ok_or_else(|| Error::MissingField("T0031".into()))?;
+
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> SigmaRequest {
+        let mut req = SigmaRequest::new("Y", "M", "0200", 4007040978).unwrap();
+        req.tags.insert(0, "2371492071643".into());
+        req.tags.insert(18, "Y".into());
+        req.iso_fields.insert(0, "0100".into());
+        req.iso_fields.insert(2, "4111111111111111".into());
+        req.iso_fields.insert(3, "500000".into());
+        req.iso_fields.insert(4, "000100000000".into());
+        req.iso_fields.insert(7, "0629151748".into());
+        req.iso_fields.insert(11, "100250".into());
+        req.iso_fields.insert(32, "010455".into());
+        req.iso_fields
+            .insert(55, IsoFieldData::Raw(b"\x9F\x27\x01\x80".to_vec()));
+        req.iso_fields.insert(102, "40817810000000000001".into());
+        req
+    }
+
+    #[test]
+    fn ascii_request() {
+        let spec = Iso8583Spec::ascii();
+        let msg = request().to_iso8583(&spec).unwrap();
+        assert_eq!(msg.mti, "0100");
+
+        let encoded = msg.encode(&spec).unwrap();
+        assert_eq!(&encoded[..4], b"0100");
+        assert_eq!(
+            &encoded[4..20],
+            b"\xF2\x20\x00\x01\x00\x00\x02\x00\x00\x00\x00\x00\x04\x00\x00\x04"
+        );
+        assert_eq!(&encoded[20..38], b"164111111111111111");
+        assert!(encoded
+            .windows(39)
+            .any(|w| w == b"044YM0200400704097800000132371492071643".as_ref()));
+
+        let decoded = Iso8583Message::decode(&spec, &encoded).unwrap();
+        assert_eq!(decoded, msg);
+
+        let req = SigmaRequest::from_iso8583(&decoded, &spec).unwrap();
+        assert_eq!(req.saf(), "Y");
+        assert_eq!(req.source(), "M");
+        assert_eq!(req.mti(), "0200");
+        assert_eq!(req.auth_serno, 4007040978);
+        assert_eq!(req.iso_fields.get(&0).unwrap(), "0100");
+        assert_eq!(req.iso_fields.get(&55).unwrap(), b"\x9F\x27\x01\x80");
+        assert!(!req.iso_fields.contains_key(&126));
+        assert_eq!(req, request());
+
+        // Without i000 ISO MTI is the Sigma MTI
+        let mut plain = request();
+        plain.iso_fields.remove(&0);
+        let msg = plain.to_iso8583(&spec).unwrap();
+        assert_eq!(msg.mti, "0200");
+        assert_eq!(SigmaRequest::from_iso8583(&msg, &spec).unwrap(), plain);
+    }
+
+    #[test]
+    fn bcd_request() {
+        let spec = Iso8583Spec::bcd().with_bitmap_encoding(BitmapEncoding::Hex);
+        let mut req = request();
+        req.iso_fields.remove(&102);
+        req.iso_fields.insert(32, "10455".into());
+
+        let encoded = req.to_iso8583(&spec).unwrap().encode(&spec).unwrap();
+        assert_eq!(&encoded[..2], b"\x01\x00");
+        assert_eq!(&encoded[2..34], b"F2200001000002000000000000000004");
+        assert_eq!(&encoded[34..43], b"\x16\x41\x11\x11\x11\x11\x11\x11\x11");
+
+        let decoded = Iso8583Message::decode(&spec, &encoded).unwrap();
+        assert_eq!(decoded.fields.get(&32).unwrap(), b"10455");
+        assert_eq!(decoded.fields.get(&4).unwrap(), b"000100000000");
+        assert_eq!(
+            SigmaRequest::from_iso8583(&decoded, &spec).unwrap().tags,
+            req.tags
+        );
+    }
+
+    #[test]
+    fn subfields() {
+        let spec = Iso8583Spec::ascii().with_sigma_field(127);
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        req.iso_subfields.insert((48, 1), "USRDT".into());
+        req.iso_subfields.insert((48, 2), "|2595100250".into());
+
+        let msg = req.to_iso8583(&spec).unwrap();
+        assert_eq!(msg.fields.get(&48).unwrap(), b"USRDT|2595100250");
+        assert_eq!(msg.fields.get(&127).unwrap(), b"NM01000000000001");
+
+        req.iso_fields.insert(127, "X".into());
+        assert_eq!(req.to_iso8583(&spec), Err(Iso8583Error::ReservedField(127)));
+    }
+
+    #[test]
+    fn response() {
+        let spec = Iso8583Spec::ascii();
+        let mut resp = SigmaResponse::new("0110", 4007040978, 8100).unwrap();
+        resp.fees.push(FeeData {
+            reason: 8116,
+            currency: 978,
+            amount: 300,
+        });
+        resp.adata = Some("USRDT|2595100250".into());
+
+        let msg = resp.to_iso8583(&spec).unwrap();
+        let encoded = msg.encode(&spec).unwrap();
+        let decoded =
+            SigmaResponse::from_iso8583(&Iso8583Message::decode(&spec, &encoded).unwrap(), &spec)
+                .unwrap();
+        assert_eq!(decoded.mti(), "0110");
+        assert_eq!(decoded.auth_serno, 4007040978);
+        assert_eq!(decoded.reason, 8100);
+        assert_eq!(decoded.fees, resp.fees);
+        assert_eq!(decoded.adata, resp.adata);
+        assert_eq!(decoded.xri, None);
+
+        let mut msg = Iso8583Message::new("0110");
+        msg.fields.insert(126, b"4007040978".to_vec());
+        assert_eq!(
+            SigmaResponse::from_iso8583(&msg, &spec).unwrap_err(),
+            Iso8583Error::Sigma(Error::MissingField("T0031".into()))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let spec = Iso8583Spec::ascii();
+
+        let mut msg = Iso8583Message::new("0100");
+        msg.fields.insert(3, b"5000".to_vec());
+        assert_eq!(
+            msg.encode(&spec),
+            Err(Iso8583Error::IncorrectLength {
+                field: 3,
+                length: 4,
+                expected: 6
+            })
+        );
+
+        let mut msg = Iso8583Message::new("0100");
+        msg.fields.insert(2, b"41111111111111111111".to_vec());
+        assert_eq!(
+            msg.encode(&spec),
+            Err(Iso8583Error::FieldTooLong {
+                field: 2,
+                length: 20,
+                max: 19
+            })
+        );
+
+        let mut msg = Iso8583Message::new("0100");
+        msg.fields.insert(1, b"".to_vec());
+        assert_eq!(msg.encode(&spec), Err(Iso8583Error::UnknownField(1)));
+
+        let mut msg = Iso8583Message::new("0100");
+        msg.fields.insert(3, b"50000A".to_vec());
+        assert!(msg.encode(&Iso8583Spec::bcd()).is_err());
+
+        assert_eq!(
+            Iso8583Message::decode(&spec, b"0100\x20\x00\x00\x00\x00\x00\x00\x00500"),
+            Err(Iso8583Error::UnexpectedEnd("field 3".into()))
+        );
+        assert_eq!(
+            Iso8583Message::decode(&spec, b"01"),
+            Err(Iso8583Error::UnexpectedEnd("MTI".into()))
+        );
+    }
+}
@@ -5,13 +5,47 @@ use bytes::{Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::decode::Decoder;
+pub use crate::decode::{DecodeOptions, DecodeWarning};
+pub use crate::util::Tag;
 use crate::util::*;
 
 #[macro_use]
 mod util;
 
-#[cfg(feature = "codec")]
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "charset")]
+pub mod charset;
+#[cfg(feature = "client")]
+pub mod client;
 pub mod codec;
+#[cfg(feature = "chrono")]
+pub mod datetime;
+pub mod decode;
+pub mod emv;
+#[cfg(feature = "iso8583")]
+pub mod iso8583;
+pub mod metrics;
+pub mod network;
+pub mod pan;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+pub mod private_data;
+pub mod raw;
+#[cfg(feature = "record")]
+pub mod record;
+#[cfg(feature = "replay")]
+pub mod replay;
+pub mod reversal;
+#[cfg(feature = "saf")]
+pub mod saf;
+#[cfg(feature = "spec")]
+pub mod spec;
+pub mod standin;
+#[cfg(feature = "tracing")]
+mod trace;
+pub mod track2;
 
 #[derive(Debug, thiserror::Error, PartialEq, Clone)]
 pub enum Error {
@@ -31,6 +65,17 @@ pub enum Error {
 }
 
 impl Error {
+    /// Name of the variant, suitable as a metrics label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Bounds(_) => "Bounds",
+            Self::IncorrectTag(_) => "IncorrectTag",
+            Self::IncorrectFieldData { .. } => "IncorrectFieldData",
+            Self::MissingField(_) => "MissingField",
+            Self::IncorrectData(_) => "IncorrectData",
+        }
+    }
+
     fn incorrect_field_data(field_name: &str, should_be: &str) -> Self {
         Self::IncorrectFieldData {
             field_name: field_name.into(),
@@ -244,7 +289,45 @@ impl SigmaRequest {
         Ok(req)
     }
 
+    /// JSON object in the format of [`SigmaRequest::from_json_value`], all values are strings
+    /// except for serno. Non-UTF-8 ISO field data is converted lossy.
+    pub fn to_json_value(&self) -> Value {
+        let mut data = serde_json::Map::new();
+        data.insert("SAF".into(), self.saf.clone().into());
+        data.insert("SRC".into(), self.source.clone().into());
+        data.insert("MTI".into(), self.mti.clone().into());
+        data.insert("Serno".into(), self.auth_serno.into());
+        for (k, v) in self.tags.iter() {
+            data.insert(Tag::Regular(*k).to_string(), v.clone().into());
+        }
+        for (k, v) in self.iso_fields.iter() {
+            data.insert(Tag::Iso(*k).to_string(), v.to_cow_str_lossy().into());
+        }
+        for ((k, k1), v) in self.iso_subfields.iter() {
+            data.insert(
+                Tag::IsoSubfield(*k, *k1).to_string(),
+                v.to_cow_str_lossy().into(),
+            );
+        }
+        Value::Object(data)
+    }
+
     pub fn encode(&self) -> Result<Bytes, Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "sigma_request_encode",
+            mti = %self.mti,
+            auth_serno = self.auth_serno
+        )
+        .entered();
+
+        let result = self.encode_message();
+        #[cfg(feature = "tracing")]
+        crate::trace::encoded(&result);
+        result
+    }
+
+    fn encode_message(&self) -> Result<Bytes, Error> {
         let mut buf = BytesMut::with_capacity(8192);
         buf.extend_from_slice(b"00000");
 
@@ -270,38 +353,61 @@ impl SigmaRequest {
         }
 
         let msg_len = buf.len() - 5;
+        if msg_len > 99999 {
+            return Err(Error::Bounds(format!(
+                "message length {} exceeds 99999",
+                msg_len
+            )));
+        }
         buf[0..5].copy_from_slice(format!("{:05}", msg_len).as_bytes());
         Ok(buf.freeze())
     }
 
-    pub fn decode(mut data: Bytes) -> Result<Self, Error> {
+    pub fn decode(data: Bytes) -> Result<Self, Error> {
+        Self::decode_with(data, &DecodeOptions::default()).map(|(req, _)| req)
+    }
+
+    /// Decodes with given strictness, returning warnings of lenient decoding.
+    pub fn decode_with(
+        data: Bytes,
+        options: &DecodeOptions,
+    ) -> Result<(Self, Vec<DecodeWarning>), Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "sigma_request_decode",
+            length = data.len(),
+            mti = tracing::field::Empty,
+            auth_serno = tracing::field::Empty
+        )
+        .entered();
+
+        let result = Self::decode_message(data, options);
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok((req, warnings)) => crate::trace::decoded(&req.mti, req.auth_serno, warnings),
+            Err(err) => crate::trace::decode_failed(err),
+        }
+        result
+    }
+
+    fn decode_message(
+        data: Bytes,
+        options: &DecodeOptions,
+    ) -> Result<(Self, Vec<DecodeWarning>), Error> {
         let mut req = Self::new("N", "X", "0100", 0)?;
+        let mut decoder = Decoder::new(options);
+        let mut data = decoder.message(data)?;
 
-        let msg_len = parse_ascii_bytes_lossy!(
-            &bytes_split_to(&mut data, 5)?,
-            usize,
-            Error::incorrect_field_data("message length", "valid integer")
-        )?;
-        let mut data = bytes_split_to(&mut data, msg_len)?;
-
-        req.set_saf(String::from_utf8_lossy(&bytes_split_to(&mut data, 1)?).to_string())?;
-        req.set_source(String::from_utf8_lossy(&bytes_split_to(&mut data, 1)?).to_string())?;
-        req.set_mti(String::from_utf8_lossy(&bytes_split_to(&mut data, 4)?).to_string())?;
-        req.auth_serno = String::from_utf8_lossy(&bytes_split_to(&mut data, 10)?)
-            .trim()
-            .parse::<u64>()
-            .map_err(|_| Error::IncorrectFieldData {
-                field_name: "Serno".into(),
-                should_be: "u64".into(),
-            })?;
-
-        while !data.is_empty() {
-            let (tag, data_src) = decode_field_from_cursor(&mut data)?;
+        req.set_saf(decoder.string("SAF", &bytes_split_to(&mut data, 1)?)?)?;
+        req.set_source(decoder.string("SRC", &bytes_split_to(&mut data, 1)?)?)?;
+        req.set_mti(decoder.string("MTI", &bytes_split_to(&mut data, 4)?)?)?;
+        req.auth_serno = decoder.serno(&bytes_split_to(&mut data, 10)?)?;
 
+        while let Some((tag, data_src)) = decoder.field(&mut data)? {
             match tag {
                 Tag::Regular(i) => {
-                    req.tags
-                        .insert(i, String::from_utf8_lossy(&data_src).into_owned());
+                    let v = decoder.string(&tag.to_string(), &data_src)?;
+                    req.tags.insert(i, v);
                 }
                 Tag::Iso(i) => {
                     req.iso_fields.insert(i, IsoFieldData::from_bytes(data_src));
@@ -313,7 +419,7 @@ impl SigmaRequest {
             }
         }
 
-        Ok(req)
+        Ok((req, decoder.warnings))
     }
 
     pub fn saf(&self) -> &str {
@@ -437,34 +543,51 @@ impl SigmaResponse {
         })
     }
 
-    pub fn decode(mut data: Bytes) -> Result<Self, Error> {
+    pub fn decode(data: Bytes) -> Result<Self, Error> {
+        Self::decode_with(data, &DecodeOptions::default()).map(|(resp, _)| resp)
+    }
+
+    /// Decodes with given strictness, returning warnings of lenient decoding.
+    pub fn decode_with(
+        data: Bytes,
+        options: &DecodeOptions,
+    ) -> Result<(Self, Vec<DecodeWarning>), Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "sigma_response_decode",
+            length = data.len(),
+            mti = tracing::field::Empty,
+            auth_serno = tracing::field::Empty
+        )
+        .entered();
+
+        let result = Self::decode_message(data, options);
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok((resp, warnings)) => crate::trace::decoded(&resp.mti, resp.auth_serno, warnings),
+            Err(err) => crate::trace::decode_failed(err),
+        }
+        result
+    }
+
+    fn decode_message(
+        data: Bytes,
+        options: &DecodeOptions,
+    ) -> Result<(Self, Vec<DecodeWarning>), Error> {
         let mut resp = Self::new("0100", 0, 0)?;
+        let mut decoder = Decoder::new(options);
+        let mut data = decoder.message(data)?;
 
-        let msg_len = parse_ascii_bytes_lossy!(
-            &bytes_split_to(&mut data, 5)?,
-            usize,
-            Error::incorrect_field_data("message length", "valid integer")
-        )?;
-        let mut data = bytes_split_to(&mut data, msg_len)?;
-
-        resp.set_mti(String::from_utf8_lossy(&bytes_split_to(&mut data, 4)?).to_string())?;
-        resp.auth_serno = String::from_utf8_lossy(&bytes_split_to(&mut data, 10)?)
-            .trim()
-            .parse::<u64>()
-            .map_err(|_| Error::IncorrectFieldData {
-                field_name: "Serno".into(),
-                should_be: "u64".into(),
-            })?;
-
-        while !data.is_empty() {
+        resp.set_mti(decoder.string("MTI", &bytes_split_to(&mut data, 4)?)?)?;
+        resp.auth_serno = decoder.serno(&bytes_split_to(&mut data, 10)?)?;
+
+        while let Some((tag, data_src)) = decoder.field(&mut data)? {
             /*
              *  |
              *  |  T  | \x00 | \x31 | \x00 | \x00 | \x04 |  8  |  1  |  0  |  0  |
              *        |             |      |             |                       |
              *        |__ tag id ___|      |tag data len |_______ data __________|
              */
-            let (tag, data_src) = decode_field_from_cursor(&mut data)?;
-
             match tag {
                 Tag::Regular(31) => {
                     resp.reason = parse_ascii_bytes_lossy!(
@@ -476,18 +599,18 @@ impl SigmaResponse {
                 Tag::Regular(32) => {
                     resp.fees.push(FeeData::from_slice(&data_src)?);
                 }
-                Tag::Regular(33) => resp.xri = Some(String::from_utf8_lossy(&data_src).to_string()),
+                Tag::Regular(33) => resp.xri = Some(decoder.string("T0033", &data_src)?),
                 Tag::Regular(48) => {
-                    resp.adata = Some(String::from_utf8_lossy(&data_src).to_string());
+                    resp.adata = Some(decoder.string("T0048", &data_src)?);
                 }
                 Tag::Regular(50) => {
-                    resp.supdata = Some(String::from_utf8_lossy(&data_src).to_string());
+                    resp.supdata = Some(decoder.string("T0050", &data_src)?);
                 }
                 _ => {}
             }
         }
 
-        Ok(resp)
+        Ok((resp, decoder.warnings))
     }
 
     pub fn mti(&self) -> &str {
@@ -501,6 +624,21 @@ impl SigmaResponse {
     }
 
     pub fn encode(&self) -> Result<Bytes, Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(
+            "sigma_response_encode",
+            mti = %self.mti,
+            auth_serno = self.auth_serno
+        )
+        .entered();
+
+        let result = self.encode_message();
+        #[cfg(feature = "tracing")]
+        crate::trace::encoded(&result);
+        result
+    }
+
+    fn encode_message(&self) -> Result<Bytes, Error> {
         let mut buf = BytesMut::with_capacity(8192);
         buf.extend_from_slice(b"00000");
 
@@ -526,12 +664,19 @@ impl SigmaResponse {
         }
 
         let msg_len = buf.len() - 5;
+        if msg_len > 99999 {
+            return Err(Error::Bounds(format!(
+                "message length {} exceeds 99999",
+                msg_len
+            )));
+        }
         buf[0..5].copy_from_slice(format!("{:05}", msg_len).as_bytes());
         Ok(buf.freeze())
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::unnecessary_get_then_check)]
 mod tests {
     use super::*;
 
@@ -585,6 +730,8 @@ mod tests {
 
         let r: SigmaRequest =
             SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
+        assert_eq!(SigmaRequest::from_json_value(r.to_json_value()).unwrap(), r);
+        assert_eq!(r.to_json_value()["i041"], "990");
         assert_eq!(r.saf, "Y");
         assert_eq!(r.source, "M");
         assert_eq!(r.mti, "0200");
@@ -1170,6 +1317,27 @@ mod tests {
         .is_err());
     }
 
+    #[test]
+    fn encode_too_long() {
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        for i in 0..10 {
+            req.tags.insert(i, "\u{fffd}".repeat(3333));
+        }
+        assert_eq!(
+            req.encode(),
+            Err(Error::Bounds("message length 100066 exceeds 99999".into()))
+        );
+
+        let mut resp = SigmaResponse::new("0110", 1, 8100).unwrap();
+        let fee = FeeData {
+            reason: 8123,
+            currency: 643,
+            amount: 1234567890,
+        };
+        resp.fees = vec![fee; 5000];
+        assert!(matches!(resp.encode(), Err(Error::Bounds(_))));
+    }
+
     #[test]
     fn encode_sigma_response_fee_data_additional_data() {
         let src = r#"{"mti":"0110","auth_serno":4007040978,"reason":8100,"fees":[{"reason":8116,"currency":643,"amount":9000}],"adata":"CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI="}"#;
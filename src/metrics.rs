@@ -0,0 +1,155 @@
+//! Metrics hook of the codec and the client.
+//!
+//! Implement [`Metrics`] to forward measurements to a metrics backend and pass it to
+//! [`SigmaClientProtocol::with_metrics`](crate::codec::SigmaClientProtocol::with_metrics).
+//! [`CountingMetrics`] keeps plain counters in memory.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Receiver of measurements; all methods do nothing by default.
+pub trait Metrics: std::fmt::Debug + Send + Sync {
+    /// Frame of given length (including the length header) was encoded.
+    fn frame_encoded(&self, _bytes: usize) {}
+
+    /// Frame of given length (including the length header) was decoded.
+    fn frame_decoded(&self, _bytes: usize) {}
+
+    /// Frame could not be decoded, `kind` is the error variant name such as `"IncorrectTag"`.
+    fn decode_error(&self, _kind: &'static str) {}
+
+    /// Response was received `latency` after the request of given MTI was sent.
+    fn response_latency(&self, _mti: &str, _latency: Duration) {}
+
+    /// Response with given reason was decoded.
+    fn response_reason(&self, _reason: u32) {}
+}
+
+/// Latency totals of a single MTI.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count {
+            0 => None,
+            n => Some(self.total / n as u32),
+        }
+    }
+}
+
+/// In-memory [`Metrics`] implementation.
+#[derive(Debug, Default)]
+pub struct CountingMetrics {
+    frames_encoded: AtomicU64,
+    bytes_encoded: AtomicU64,
+    frames_decoded: AtomicU64,
+    bytes_decoded: AtomicU64,
+    decode_errors: Mutex<BTreeMap<&'static str, u64>>,
+    latencies: Mutex<BTreeMap<String, LatencyStats>>,
+    reasons: Mutex<BTreeMap<u32, u64>>,
+}
+
+impl CountingMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn frames_encoded(&self) -> u64 {
+        self.frames_encoded.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_encoded(&self) -> u64 {
+        self.bytes_encoded.load(Ordering::Relaxed)
+    }
+
+    pub fn frames_decoded(&self) -> u64 {
+        self.frames_decoded.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_decoded(&self) -> u64 {
+        self.bytes_decoded.load(Ordering::Relaxed)
+    }
+
+    /// Decode errors by error variant name.
+    pub fn decode_errors(&self) -> BTreeMap<&'static str, u64> {
+        self.decode_errors.lock().unwrap().clone()
+    }
+
+    /// Request to response latencies by request MTI.
+    pub fn latencies(&self) -> BTreeMap<String, LatencyStats> {
+        self.latencies.lock().unwrap().clone()
+    }
+
+    /// Responses by reason.
+    pub fn reasons(&self) -> BTreeMap<u32, u64> {
+        self.reasons.lock().unwrap().clone()
+    }
+}
+
+impl Metrics for CountingMetrics {
+    fn frame_encoded(&self, bytes: usize) {
+        self.frames_encoded.fetch_add(1, Ordering::Relaxed);
+        self.bytes_encoded
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn frame_decoded(&self, bytes: usize) {
+        self.frames_decoded.fetch_add(1, Ordering::Relaxed);
+        self.bytes_decoded
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn decode_error(&self, kind: &'static str) {
+        *self.decode_errors.lock().unwrap().entry(kind).or_default() += 1;
+    }
+
+    fn response_latency(&self, mti: &str, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        let stats = latencies.entry(mti.into()).or_default();
+        stats.count += 1;
+        stats.total += latency;
+        stats.max = stats.max.max(latency);
+    }
+
+    fn response_reason(&self, reason: u32) {
+        *self.reasons.lock().unwrap().entry(reason).or_default() += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counting() {
+        let metrics = CountingMetrics::new();
+        metrics.frame_encoded(40);
+        metrics.frame_encoded(60);
+        metrics.frame_decoded(29);
+        metrics.decode_error("IncorrectTag");
+        metrics.decode_error("IncorrectTag");
+        metrics.response_latency("0100", Duration::from_millis(10));
+        metrics.response_latency("0100", Duration::from_millis(30));
+        metrics.response_reason(8100);
+
+        assert_eq!(metrics.frames_encoded(), 2);
+        assert_eq!(metrics.bytes_encoded(), 100);
+        assert_eq!(metrics.frames_decoded(), 1);
+        assert_eq!(metrics.bytes_decoded(), 29);
+        assert_eq!(metrics.decode_errors().get("IncorrectTag"), Some(&2));
+        assert_eq!(metrics.reasons().get(&8100), Some(&1));
+
+        let latency = metrics.latencies()["0100"];
+        assert_eq!(latency.count, 2);
+        assert_eq!(latency.max, Duration::from_millis(30));
+        assert_eq!(latency.mean(), Some(Duration::from_millis(20)));
+        assert_eq!(LatencyStats::default().mean(), None);
+    }
+}
@@ -0,0 +1,134 @@
+//! Network management (08xx) messages.
+
+use crate::{Error, SigmaRequest, SigmaResponse};
+
+/// MTI of network management request.
+pub const NETWORK_MANAGEMENT_MTI: &str = "0800";
+
+/// ISO field which carries network management information code.
+pub const NETWORK_MANAGEMENT_CODE_FIELD: u16 = 70;
+
+/// Network management information code (ISO field 70).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkManagementCode {
+    SignOn,
+    SignOff,
+    Echo,
+}
+
+impl NetworkManagementCode {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::SignOn => "001",
+            Self::SignOff => "002",
+            Self::Echo => "301",
+        }
+    }
+
+    pub fn from_code(s: &str) -> Result<Self, Error> {
+        match s {
+            "001" => Ok(Self::SignOn),
+            "002" => Ok(Self::SignOff),
+            "301" => Ok(Self::Echo),
+            _ => Err(Error::incorrect_field_data(
+                "i070",
+                "network management code 001, 002 or 301",
+            )),
+        }
+    }
+}
+
+impl SigmaRequest {
+    /// Creates network management request with given information code.
+    pub fn network_management(
+        source: &str,
+        code: NetworkManagementCode,
+        auth_serno: u64,
+    ) -> Result<Self, Error> {
+        let mut req = Self::new("N", source, NETWORK_MANAGEMENT_MTI, auth_serno)?;
+        req.iso_fields
+            .insert(NETWORK_MANAGEMENT_CODE_FIELD, code.code().into());
+        Ok(req)
+    }
+
+    pub fn echo(source: &str, auth_serno: u64) -> Result<Self, Error> {
+        Self::network_management(source, NetworkManagementCode::Echo, auth_serno)
+    }
+
+    pub fn sign_on(source: &str, auth_serno: u64) -> Result<Self, Error> {
+        Self::network_management(source, NetworkManagementCode::SignOn, auth_serno)
+    }
+
+    pub fn sign_off(source: &str, auth_serno: u64) -> Result<Self, Error> {
+        Self::network_management(source, NetworkManagementCode::SignOff, auth_serno)
+    }
+
+    pub fn is_network_management(&self) -> bool {
+        self.mti().starts_with("08")
+    }
+
+    /// Network management code of the request, if it is a network management one.
+    pub fn network_management_code(&self) -> Option<NetworkManagementCode> {
+        if !self.is_network_management() {
+            return None;
+        }
+        self.iso_fields
+            .get(&NETWORK_MANAGEMENT_CODE_FIELD)
+            .and_then(|v| NetworkManagementCode::from_code(&v.to_cow_str_lossy()).ok())
+    }
+}
+
+impl SigmaResponse {
+    pub fn is_network_management(&self) -> bool {
+        self.mti().starts_with("08")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_echo() {
+        let req = SigmaRequest::echo("M", 1234567890).unwrap();
+        assert!(req.is_network_management());
+        assert_eq!(
+            req.network_management_code(),
+            Some(NetworkManagementCode::Echo)
+        );
+        assert_eq!(
+            req.encode().unwrap(),
+            b"00025NM08001234567890I\x00\x70\x00\x00\x03301"[..]
+        );
+    }
+
+    #[test]
+    fn sign_on_sign_off() {
+        let req = SigmaRequest::sign_on("M", 1).unwrap();
+        assert_eq!(req.mti(), "0800");
+        assert_eq!(req.iso_fields.get(&70).unwrap(), "001");
+
+        let req = SigmaRequest::sign_off("M", 1).unwrap();
+        assert_eq!(
+            req.network_management_code(),
+            Some(NetworkManagementCode::SignOff)
+        );
+    }
+
+    #[test]
+    fn not_network_management() {
+        let req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        assert!(!req.is_network_management());
+        assert_eq!(req.network_management_code(), None);
+    }
+
+    #[test]
+    fn network_management_response() {
+        assert!(SigmaResponse::new("0810", 1, 0)
+            .unwrap()
+            .is_network_management());
+        assert!(!SigmaResponse::new("0110", 1, 0)
+            .unwrap()
+            .is_network_management());
+    }
+}
@@ -0,0 +1,276 @@
+//! Primary account number (ISO field 2).
+
+use std::fmt;
+
+use crate::track2::{mask_track2, TRACK2_FIELD};
+use crate::{Error, SigmaRequest};
+
+/// ISO field which carries PAN.
+pub const PAN_FIELD: u16 = 2;
+
+pub const MIN_PAN_LENGTH: usize = 12;
+pub const MAX_PAN_LENGTH: usize = 19;
+
+/// Character used to mask PAN digits.
+pub const MASK_CHAR: char = '*';
+
+/// Luhn check digit for given digits (without the check digit).
+pub fn luhn_check_digit(digits: &str) -> Option<u8> {
+    let mut sum = 0u32;
+    for (i, b) in digits.bytes().rev().enumerate() {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        let mut d = (b - b'0') as u32;
+        if i % 2 == 0 {
+            d *= 2;
+            if d > 9 {
+                d -= 9;
+            }
+        }
+        sum += d;
+    }
+    Some(((10 - sum % 10) % 10) as u8)
+}
+
+/// Whether the last digit of given digits is a valid Luhn check digit.
+pub fn luhn_valid(digits: &str) -> bool {
+    if digits.is_empty() || !digits.is_ascii() {
+        return false;
+    }
+    let (payload, check) = digits.split_at(digits.len() - 1);
+    luhn_check_digit(payload).is_some_and(|v| check.as_bytes()[0] == b'0' + v)
+}
+
+/// Masks all but the first 6 and the last 4 characters of a PAN-like string.
+///
+/// Strings too short to keep anything unmasked are masked completely.
+pub fn mask_pan(s: &str) -> String {
+    let chars = s.chars().collect::<Vec<_>>();
+    if chars.len() < MIN_PAN_LENGTH {
+        return MASK_CHAR.to_string().repeat(chars.len());
+    }
+    chars
+        .iter()
+        .enumerate()
+        .map(|(i, c)| match i {
+            _ if i < 6 || i >= chars.len() - 4 => *c,
+            _ => MASK_CHAR,
+        })
+        .collect()
+}
+
+/// Primary account number, either full or masked.
+///
+/// `Debug` and `Display` implementations show the masked form only, use [`Pan::as_str`]
+/// to get the value itself.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Pan(String);
+
+impl Pan {
+    /// Parses PAN of 12 to 19 digits, some of which may be masked with `*`.
+    /// Luhn check digit is not validated, see [`Pan::parse_checked`].
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        if !(MIN_PAN_LENGTH..=MAX_PAN_LENGTH).contains(&s.len()) {
+            return Err(Error::IncorrectFieldData {
+                field_name: "PAN".into(),
+                should_be: format!("{} to {} digits", MIN_PAN_LENGTH, MAX_PAN_LENGTH),
+            });
+        }
+        if !s.chars().all(|c| c.is_ascii_digit() || c == MASK_CHAR) {
+            return Err(Error::incorrect_field_data("PAN", "digits or '*'"));
+        }
+        Ok(Self(s.into()))
+    }
+
+    /// Parses PAN and validates its Luhn check digit unless the PAN is masked.
+    pub fn parse_checked(s: &str) -> Result<Self, Error> {
+        let pan = Self::parse(s)?;
+        if !pan.is_masked() && !pan.luhn_valid() {
+            return Err(Error::incorrect_field_data("PAN", "valid Luhn check digit"));
+        }
+        Ok(pan)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn is_masked(&self) -> bool {
+        self.0.contains(MASK_CHAR)
+    }
+
+    /// Whether Luhn check digit is valid; always false for masked PAN.
+    pub fn luhn_valid(&self) -> bool {
+        luhn_valid(&self.0)
+    }
+
+    fn unmasked_prefix(&self, len: usize) -> Option<&str> {
+        let prefix = &self.0[..len];
+        match prefix.contains(MASK_CHAR) {
+            true => None,
+            false => Some(prefix),
+        }
+    }
+
+    /// 6 digit BIN, if it is not masked.
+    pub fn bin6(&self) -> Option<&str> {
+        self.unmasked_prefix(6)
+    }
+
+    /// 8 digit BIN, if it is not masked.
+    pub fn bin8(&self) -> Option<&str> {
+        self.unmasked_prefix(8)
+    }
+
+    /// Last 4 digits, if they are not masked.
+    pub fn last4(&self) -> Option<&str> {
+        let last4 = &self.0[self.0.len() - 4..];
+        match last4.contains(MASK_CHAR) {
+            true => None,
+            false => Some(last4),
+        }
+    }
+
+    /// PAN with all but the first 6 and the last 4 digits masked.
+    pub fn masked(&self) -> String {
+        mask_pan(&self.0)
+    }
+
+    /// PAN with all but the first `first` and the last `last` digits masked.
+    pub fn masked_with(&self, first: usize, last: usize, mask: char) -> String {
+        let len = self.0.len();
+        self.0
+            .chars()
+            .enumerate()
+            .map(|(i, c)| match i {
+                _ if i < first || i + last >= len => c,
+                _ => mask,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for Pan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Pan").field(&self.masked()).finish()
+    }
+}
+
+impl fmt::Display for Pan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.masked())
+    }
+}
+
+impl std::str::FromStr for Pan {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl SigmaRequest {
+    /// PAN (i002), if present.
+    pub fn pan(&self) -> Result<Option<Pan>, Error> {
+        self.iso_fields
+            .get(&PAN_FIELD)
+            .map(|v| Pan::parse(&v.to_cow_str_lossy()))
+            .transpose()
+    }
+
+    pub fn set_pan(&mut self, pan: &Pan) {
+        self.iso_fields.insert(PAN_FIELD, pan.as_str().into());
+    }
+
+    /// Copy of the request with PAN (i002) and track 2 data (i035) masked, suitable for logging.
+    pub fn masked(&self) -> SigmaRequest {
+        let mut req = self.clone();
+        if let Some(v) = req.iso_fields.get_mut(&PAN_FIELD) {
+            *v = mask_pan(&v.to_cow_str_lossy()).into();
+        }
+        if let Some(v) = req.iso_fields.get_mut(&TRACK2_FIELD) {
+            *v = mask_track2(&v.to_cow_str_lossy()).into();
+        }
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn luhn() {
+        assert_eq!(luhn_check_digit("7992739871"), Some(3));
+        assert!(luhn_valid("79927398713"));
+        assert!(luhn_valid("4111111111111111"));
+        assert!(!luhn_valid("4111111111111112"));
+        assert!(!luhn_valid("555544******1111"));
+        assert!(!luhn_valid(""));
+        // Non-ASCII digits must not be sliced inside a character
+        assert!(!luhn_valid("411111111111111\u{0661}"));
+        assert!(!luhn_valid("\u{0661}"));
+        assert_eq!(luhn_check_digit("4111\u{0661}"), None);
+    }
+
+    #[test]
+    fn parse() {
+        let pan = Pan::parse("4111111111111111").unwrap();
+        assert!(!pan.is_masked());
+        assert!(pan.luhn_valid());
+        assert_eq!(pan.bin6(), Some("411111"));
+        assert_eq!(pan.bin8(), Some("41111111"));
+        assert_eq!(pan.last4(), Some("1111"));
+        assert_eq!(pan.masked(), "411111******1111");
+        assert_eq!(pan.masked_with(4, 2, 'X'), "4111XXXXXXXXXX11");
+        assert_eq!(pan.to_string(), "411111******1111");
+        assert_eq!(format!("{:?}", pan), "Pan(\"411111******1111\")");
+
+        assert!(Pan::parse("41111111111").is_err());
+        assert!(Pan::parse("41111111111111111111").is_err());
+        assert!(Pan::parse("4111-1111-1111").is_err());
+        assert!(Pan::parse_checked("4111111111111112").is_err());
+        assert_eq!(
+            Pan::parse_checked(" 4111111111111111 ").unwrap().as_str(),
+            "4111111111111111"
+        );
+    }
+
+    #[test]
+    fn masked_input() {
+        let pan = Pan::parse_checked("555544******1111").unwrap();
+        assert!(pan.is_masked());
+        assert_eq!(pan.bin6(), Some("555544"));
+        assert_eq!(pan.bin8(), None);
+        assert_eq!(pan.last4(), Some("1111"));
+        assert_eq!(pan.masked(), "555544******1111");
+    }
+
+    #[test]
+    fn request_pan() {
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        assert_eq!(req.pan().unwrap(), None);
+
+        req.set_pan(&"4111111111111111".parse().unwrap());
+        assert_eq!(req.pan().unwrap().unwrap().bin8(), Some("41111111"));
+
+        let masked = req.masked();
+        assert_eq!(masked.iso_fields.get(&2).unwrap(), "411111******1111");
+        assert_eq!(req.iso_fields.get(&2).unwrap(), "4111111111111111");
+
+        req.iso_fields.insert(2, "12345".into());
+        assert!(req.pan().is_err());
+        assert_eq!(req.masked().iso_fields.get(&2).unwrap(), "*****");
+    }
+}
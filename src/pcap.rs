@@ -0,0 +1,724 @@
+//! Extraction of Sigma messages from pcap and pcapng captures.
+//!
+//! TCP streams are reassembled per direction, split into frames by
+//! [`SigmaClientProtocol::next_frame`] and decoded as requests or responses. Requests are
+//! paired with responses of the same authorization serno within the same connection. Corrupt
+//! data is skipped up to the next plausible frame header and reported in [`Capture::errors`].
+//!
+//! Supported link types are Ethernet (with 802.1Q tags), BSD loopback, raw IP and Linux cooked
+//! capture (SLL and SLL2), over IPv4 or IPv6. Packets of other link types are reported and
+//! skipped, fragmented IPv4 packets are ignored.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::BytesMut;
+use serde_json::{json, Value};
+
+use crate::codec::{ResyncStrategy, SigmaClientProtocol};
+use crate::{SigmaRequest, SigmaResponse};
+
+#[derive(Debug, thiserror::Error)]
+pub enum PcapError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Incorrect capture: {0}")]
+    Format(String),
+    #[error("Unsupported link type {0}")]
+    UnsupportedLinkType(u32),
+}
+
+fn format_error(s: &str) -> PcapError {
+    PcapError::Format(s.into())
+}
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+/// Captured link layer packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub timestamp: SystemTime,
+    pub link_type: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8], PcapError> {
+        self.data
+            .get(offset..offset + len)
+            .ok_or_else(|| format_error("truncated data"))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, PcapError> {
+        let b = self.bytes(offset, 2)?;
+        let b = [b[0], b[1]];
+        Ok(match self.big_endian {
+            true => u16::from_be_bytes(b),
+            false => u16::from_le_bytes(b),
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, PcapError> {
+        let b = self.bytes(offset, 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(match self.big_endian {
+            true => u32::from_be_bytes(b),
+            false => u32::from_le_bytes(b),
+        })
+    }
+}
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_IF_TSRESOL: u16 = 9;
+
+/// Reads packets of a pcap or pcapng capture.
+pub fn read_packets(data: &[u8]) -> Result<Vec<Packet>, PcapError> {
+    let le = Reader {
+        data,
+        big_endian: false,
+    };
+    match le.u32(0)? {
+        PCAPNG_SECTION_HEADER => read_pcapng(data),
+        PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS => read_pcap(le),
+        v if matches!(v.swap_bytes(), PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS) => read_pcap(Reader {
+            data,
+            big_endian: true,
+        }),
+        _ => Err(format_error("unknown file format")),
+    }
+}
+
+fn read_pcap(r: Reader) -> Result<Vec<Packet>, PcapError> {
+    let nanos = r.u32(0)? == PCAP_MAGIC_NANOS;
+    let link_type = r.u32(20)? & 0x0fff_ffff;
+
+    let mut packets = Vec::new();
+    let mut offset = 24;
+    while offset < r.data.len() {
+        let seconds = r.u32(offset)? as u64;
+        let fraction = r.u32(offset + 4)? as u64;
+        let len = r.u32(offset + 8)? as usize;
+        let timestamp = UNIX_EPOCH
+            + Duration::from_secs(seconds)
+            + match nanos {
+                true => Duration::from_nanos(fraction),
+                false => Duration::from_micros(fraction),
+            };
+        packets.push(Packet {
+            timestamp,
+            link_type,
+            data: r.bytes(offset + 16, len)?.to_vec(),
+        });
+        offset += 16 + len;
+    }
+    Ok(packets)
+}
+
+struct Interface {
+    link_type: u32,
+    /// Timestamp units per second.
+    units: u64,
+}
+
+fn read_pcapng(data: &[u8]) -> Result<Vec<Packet>, PcapError> {
+    let mut r = Reader {
+        data,
+        big_endian: false,
+    };
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut packets = Vec::new();
+
+    let mut offset = 0;
+    while offset < data.len() {
+        let block_type = r.u32(offset)?;
+        if block_type == PCAPNG_SECTION_HEADER {
+            r.big_endian = match r.bytes(offset + 8, 4)? {
+                // Byte order magic 0x1a2b3c4d
+                [0x1a, 0x2b, 0x3c, 0x4d] => true,
+                [0x4d, 0x3c, 0x2b, 0x1a] => false,
+                _ => return Err(format_error("incorrect byte order magic")),
+            };
+            interfaces.clear();
+        }
+        let block_len = r.u32(offset + 4)? as usize;
+        if block_len < 12 || block_len % 4 != 0 {
+            return Err(format_error("incorrect block length"));
+        }
+        let body = Reader {
+            data: r.bytes(offset + 8, block_len - 12)?,
+            big_endian: r.big_endian,
+        };
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => interfaces.push(Interface {
+                link_type: body.u16(0)? as u32,
+                units: interface_units(body)?,
+            }),
+            PCAPNG_ENHANCED_PACKET => {
+                let interface = interfaces
+                    .get(body.u32(0)? as usize)
+                    .ok_or_else(|| format_error("packet of unknown interface"))?;
+                let ticks = (body.u32(4)? as u64) << 32 | body.u32(8)? as u64;
+                let timestamp = UNIX_EPOCH
+                    .checked_add(
+                        Duration::from_secs(ticks / interface.units)
+                            + Duration::from_nanos(
+                                ((ticks % interface.units) as u128 * 1_000_000_000
+                                    / interface.units as u128)
+                                    as u64,
+                            ),
+                    )
+                    .ok_or_else(|| format_error("timestamp out of range"))?;
+                let len = body.u32(12)? as usize;
+                packets.push(Packet {
+                    timestamp,
+                    link_type: interface.link_type,
+                    data: body.bytes(20, len)?.to_vec(),
+                });
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                let interface = interfaces
+                    .first()
+                    .ok_or_else(|| format_error("packet of unknown interface"))?;
+                let len = (body.u32(0)? as usize).min(body.data.len() - 4);
+                packets.push(Packet {
+                    timestamp: UNIX_EPOCH,
+                    link_type: interface.link_type,
+                    data: body.bytes(4, len)?.to_vec(),
+                });
+            }
+            _ => {}
+        }
+        offset += block_len;
+    }
+    Ok(packets)
+}
+
+fn interface_units(body: Reader) -> Result<u64, PcapError> {
+    let mut offset = 8;
+    while offset + 4 <= body.data.len() {
+        let code = body.u16(offset)?;
+        let len = body.u16(offset + 2)? as usize;
+        if code == 0 {
+            break;
+        }
+        if code == PCAPNG_IF_TSRESOL && len == 1 {
+            let v = body.bytes(offset + 4, 1)?[0];
+            let exponent = (v & 0x7f) as u32;
+            let base: u64 = if v & 0x80 == 0 { 10 } else { 2 };
+            return base
+                .checked_pow(exponent)
+                .ok_or_else(|| format_error("unsupported timestamp resolution"));
+        }
+        offset += 4 + len.div_ceil(4) * 4;
+    }
+    Ok(1_000_000)
+}
+
+/// TCP segment of a packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub seq: u32,
+    pub syn: bool,
+    pub payload: Vec<u8>,
+}
+
+/// TCP segment of a packet, `None` for anything else.
+pub fn tcp_segment(packet: &Packet) -> Result<Option<Segment>, PcapError> {
+    let data = &packet.data[..];
+    let ip = match packet.link_type {
+        LINKTYPE_NULL => data.get(4..),
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ether_type = u16_be(data, offset);
+            while ether_type == Some(0x8100) || ether_type == Some(0x88a8) {
+                offset += 4;
+                ether_type = u16_be(data, offset);
+            }
+            match ether_type {
+                Some(0x0800) | Some(0x86dd) => data.get(offset + 2..),
+                _ => None,
+            }
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(data),
+        LINKTYPE_LINUX_SLL => data.get(16..),
+        LINKTYPE_LINUX_SLL2 => data.get(20..),
+        v => return Err(PcapError::UnsupportedLinkType(v)),
+    };
+    Ok(ip.and_then(ip_segment))
+}
+
+fn u16_be(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn ip_segment(data: &[u8]) -> Option<Segment> {
+    let (src, dst, tcp) = match data.first()? >> 4 {
+        4 => {
+            let header_len = ((data[0] & 0x0f) as usize) * 4;
+            let total_len = u16_be(data, 2)? as usize;
+            let fragmented = u16_be(data, 6)? & 0x3fff != 0;
+            if *data.get(9)? != 6 || fragmented || header_len < 20 {
+                return None;
+            }
+            let addr = |o: usize| -> Option<IpAddr> {
+                let b = data.get(o..o + 4)?;
+                Some(Ipv4Addr::new(b[0], b[1], b[2], b[3]).into())
+            };
+            (
+                addr(12)?,
+                addr(16)?,
+                data.get(header_len..total_len.min(data.len()))?,
+            )
+        }
+        6 => {
+            let payload_len = u16_be(data, 4)? as usize;
+            let addr = |o: usize| -> Option<IpAddr> {
+                let b: [u8; 16] = data.get(o..o + 16)?.try_into().ok()?;
+                Some(Ipv6Addr::from(b).into())
+            };
+            let mut next_header = *data.get(6)?;
+            let mut offset = 40;
+            // Hop-by-hop, routing and destination options extension headers
+            while matches!(next_header, 0 | 43 | 60) {
+                next_header = *data.get(offset)?;
+                offset += (*data.get(offset + 1)? as usize + 1) * 8;
+            }
+            if next_header != 6 {
+                return None;
+            }
+            (
+                addr(8)?,
+                addr(24)?,
+                data.get(offset..(40 + payload_len).min(data.len()))?,
+            )
+        }
+        _ => return None,
+    };
+
+    let header_len = ((*tcp.get(12)? >> 4) as usize) * 4;
+    let seq = tcp.get(4..8)?;
+    Some(Segment {
+        src: SocketAddr::new(src, u16_be(tcp, 0)?),
+        dst: SocketAddr::new(dst, u16_be(tcp, 2)?),
+        seq: u32::from_be_bytes([seq[0], seq[1], seq[2], seq[3]]),
+        syn: tcp.get(13)? & 0x02 != 0,
+        payload: tcp.get(header_len..)?.to_vec(),
+    })
+}
+
+/// Reassembly of one direction of a TCP connection.
+struct Stream {
+    next_seq: Option<u32>,
+    /// Segments received ahead of the expected one.
+    pending: BTreeMap<u32, Vec<u8>>,
+    buffer: BytesMut,
+    codec: SigmaClientProtocol,
+}
+
+impl Stream {
+    fn new() -> Self {
+        Self {
+            next_seq: None,
+            pending: BTreeMap::new(),
+            buffer: BytesMut::new(),
+            codec: SigmaClientProtocol::new().with_resync(ResyncStrategy::SkipToNextHeader),
+        }
+    }
+
+    /// Appends in-order data; retransmitted bytes are dropped.
+    fn push(&mut self, seq: u32, syn: bool, payload: Vec<u8>) {
+        if syn {
+            self.next_seq = Some(seq.wrapping_add(1));
+            self.pending.clear();
+            return;
+        }
+        let next_seq = *self.next_seq.get_or_insert(seq);
+        let ahead = seq.wrapping_sub(next_seq) as i32;
+        if ahead > 0 {
+            self.pending.insert(seq, payload);
+            return;
+        }
+        self.append(next_seq, seq, payload);
+
+        while let Some(seq) = self
+            .pending
+            .keys()
+            .copied()
+            .find(|seq| seq.wrapping_sub(self.next_seq.unwrap_or(*seq)) as i32 <= 0)
+        {
+            let payload = self.pending.remove(&seq).unwrap_or_default();
+            let next_seq = self.next_seq.unwrap_or(seq);
+            self.append(next_seq, seq, payload);
+        }
+    }
+
+    fn append(&mut self, next_seq: u32, seq: u32, payload: Vec<u8>) {
+        let seen = next_seq.wrapping_sub(seq) as usize;
+        if seen < payload.len() {
+            self.buffer.extend_from_slice(&payload[seen..]);
+            self.next_seq = Some(seq.wrapping_add(payload.len() as u32));
+        }
+    }
+}
+
+/// Message with the moment and endpoints of its capture.
+#[derive(Debug, Clone)]
+pub struct Captured<T> {
+    /// Time of the packet which completed the message.
+    pub timestamp: SystemTime,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub message: T,
+}
+
+/// Captured request and the response to it, if any.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    pub request: Captured<SigmaRequest>,
+    pub response: Option<Captured<SigmaResponse>>,
+}
+
+impl Exchange {
+    pub fn latency(&self) -> Option<Duration> {
+        let response = self.response.as_ref()?;
+        response
+            .timestamp
+            .duration_since(self.request.timestamp)
+            .ok()
+    }
+
+    /// JSON object with masked request, response and capture details.
+    pub fn to_json_value(&self) -> Value {
+        let micros =
+            |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        json!({
+            "client": self.request.src.to_string(),
+            "server": self.request.dst.to_string(),
+            "request_time_us": micros(self.request.timestamp),
+            "request": self.request.message.masked().to_json_value(),
+            "response_time_us": self.response.as_ref().map(|v| micros(v.timestamp)),
+            "response": self.response.as_ref().map(|v| serde_json::to_value(&v.message).unwrap_or_default()),
+        })
+    }
+}
+
+/// Messages of a capture.
+#[derive(Debug, Clone, Default)]
+pub struct Capture {
+    /// Requests in capture order with their responses.
+    pub exchanges: Vec<Exchange>,
+    /// Responses without preceding request of the same serno.
+    pub unmatched_responses: Vec<Captured<SigmaResponse>>,
+    /// Problems which made some data unusable, such as undecodable frames.
+    pub errors: Vec<String>,
+}
+
+impl Capture {
+    pub fn from_packets(packets: &[Packet]) -> Result<Self, PcapError> {
+        let mut capture = Self::default();
+        let mut streams: HashMap<(SocketAddr, SocketAddr), Stream> = HashMap::new();
+        // Requests awaiting response by client, server and serno
+        let mut awaiting: HashMap<(SocketAddr, SocketAddr, u64), usize> = HashMap::new();
+        // Packets skipped by unsupported link type
+        let mut unsupported: BTreeMap<u32, usize> = BTreeMap::new();
+
+        for packet in packets {
+            let segment = match tcp_segment(packet) {
+                Ok(Some(v)) => v,
+                Ok(None) => continue,
+                Err(_) => {
+                    *unsupported.entry(packet.link_type).or_default() += 1;
+                    continue;
+                }
+            };
+            let (src, dst) = (segment.src, segment.dst);
+            let stream = streams.entry((src, dst)).or_insert_with(Stream::new);
+            stream.push(segment.seq, segment.syn, segment.payload);
+
+            loop {
+                let skipped = stream.codec.stats().bytes_skipped;
+                let next = stream.codec.next_frame(&mut stream.buffer);
+                let skipped = stream.codec.stats().bytes_skipped - skipped;
+                if skipped > 0 {
+                    capture
+                        .errors
+                        .push(format!("{} -> {}: {} bytes skipped", src, dst, skipped));
+                }
+                let frame = match next {
+                    Ok(Some(v)) => v.freeze(),
+                    Ok(None) => break,
+                    Err(err) => {
+                        capture.errors.push(format!("{} -> {}: {}", src, dst, err));
+                        stream.buffer.clear();
+                        break;
+                    }
+                };
+
+                if let Ok(req) = SigmaRequest::decode(frame.clone()) {
+                    awaiting.insert((src, dst, req.auth_serno), capture.exchanges.len());
+                    capture.exchanges.push(Exchange {
+                        request: Captured {
+                            timestamp: packet.timestamp,
+                            src,
+                            dst,
+                            message: req,
+                        },
+                        response: None,
+                    });
+                    continue;
+                }
+
+                let resp = match SigmaResponse::decode(frame) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        capture.errors.push(format!("{} -> {}: {}", src, dst, err));
+                        continue;
+                    }
+                };
+                let captured = Captured {
+                    timestamp: packet.timestamp,
+                    src,
+                    dst,
+                    message: resp,
+                };
+                match awaiting.remove(&(dst, src, captured.message.auth_serno)) {
+                    Some(i) => capture.exchanges[i].response = Some(captured),
+                    None => capture.unmatched_responses.push(captured),
+                }
+            }
+        }
+
+        for ((src, dst), stream) in streams {
+            if !stream.pending.is_empty() || !stream.buffer.is_empty() {
+                capture
+                    .errors
+                    .push(format!("{} -> {}: incomplete data at the end", src, dst));
+            }
+        }
+        for (link_type, count) in unsupported {
+            capture.errors.push(format!(
+                "{}: {} packets skipped",
+                PcapError::UnsupportedLinkType(link_type),
+                count
+            ));
+        }
+        capture.errors.sort();
+        Ok(capture)
+    }
+
+    /// Reads pcap or pcapng capture data.
+    pub fn read(data: &[u8]) -> Result<Self, PcapError> {
+        Self::from_packets(&read_packets(data)?)
+    }
+
+    pub fn read_path<P: AsRef<Path>>(path: P) -> Result<Self, PcapError> {
+        Self::read(&fs::read(path)?)
+    }
+
+    /// JSON array of exchanges, see [`Exchange::to_json_value`].
+    pub fn to_json_value(&self) -> Value {
+        Value::Array(self.exchanges.iter().map(Exchange::to_json_value).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PCAP: &[u8] = include_bytes!("../tests/data/sigma.pcap");
+    const PCAPNG: &[u8] = include_bytes!("../tests/data/sigma.pcapng");
+
+    #[test]
+    fn pcap() {
+        let packets = read_packets(PCAP).unwrap();
+        assert_eq!(packets[0].link_type, LINKTYPE_ETHERNET);
+
+        let capture = Capture::from_packets(&packets).unwrap();
+        assert!(capture.errors.is_empty(), "{:?}", capture.errors);
+        assert!(capture.unmatched_responses.is_empty());
+        assert_eq!(capture.exchanges.len(), 3);
+
+        let client: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let server: SocketAddr = "10.0.0.2:7000".parse().unwrap();
+        let sernos = capture
+            .exchanges
+            .iter()
+            .map(|e| {
+                assert_eq!((e.request.src, e.request.dst), (client, server));
+                let resp = e.response.as_ref().unwrap();
+                assert_eq!((resp.src, resp.dst), (server, client));
+                assert_eq!(resp.message.auth_serno, e.request.message.auth_serno);
+                (e.request.message.auth_serno, resp.message.reason)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sernos,
+            vec![(4007040978, 8100), (4007040979, 8495), (4007040980, 8100)]
+        );
+        assert_eq!(
+            capture.exchanges[1].latency(),
+            Some(Duration::from_millis(30))
+        );
+    }
+
+    #[test]
+    fn pcapng() {
+        let capture = Capture::read(PCAPNG).unwrap();
+        assert!(capture.errors.is_empty(), "{:?}", capture.errors);
+        assert_eq!(capture.exchanges.len(), 1);
+        assert_eq!(capture.unmatched_responses.len(), 1);
+
+        let exchange = &capture.exchanges[0];
+        assert_eq!(
+            exchange.request.src,
+            "[fd00::1]:40000".parse::<SocketAddr>().unwrap()
+        );
+        assert_eq!(
+            exchange.request.timestamp,
+            UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789)
+        );
+
+        let json = capture.to_json_value();
+        assert_eq!(json[0]["request"]["i002"], "411111******1111");
+        assert_eq!(json[0]["request"]["Serno"], 1);
+        assert_eq!(json[0]["response"]["reason"], 8100);
+        assert_eq!(json[0]["client"], "[fd00::1]:40000");
+    }
+
+    /// Raw IPv4 packet of a TCP segment between 10.0.0.1 and 10.0.0.2.
+    fn raw_packet(from_client: bool, seq: u32, payload: &[u8]) -> Packet {
+        let (src, dst, sport, dport) = match from_client {
+            true => ([10, 0, 0, 1], [10, 0, 0, 2], 40000u16, 7000u16),
+            false => ([10, 0, 0, 2], [10, 0, 0, 1], 7000, 40000),
+        };
+        let mut data = vec![0x45, 0];
+        data.extend_from_slice(&((40 + payload.len()) as u16).to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 64, 6, 0, 0]);
+        data.extend_from_slice(&src);
+        data.extend_from_slice(&dst);
+        data.extend_from_slice(&sport.to_be_bytes());
+        data.extend_from_slice(&dport.to_be_bytes());
+        data.extend_from_slice(&seq.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+        data.extend_from_slice(payload);
+        Packet {
+            timestamp: UNIX_EPOCH,
+            link_type: LINKTYPE_RAW,
+            data,
+        }
+    }
+
+    #[test]
+    fn skips_corrupt_data() {
+        let req = |serno| {
+            SigmaRequest::new("N", "M", "0100", serno)
+                .unwrap()
+                .encode()
+                .unwrap()
+        };
+        let resp = |serno| {
+            SigmaResponse::new("0110", serno, 8100)
+                .unwrap()
+                .encode()
+                .unwrap()
+        };
+
+        let mut first = b"xx".to_vec();
+        first.extend_from_slice(&req(1));
+        let second = req(2);
+        let packets = vec![
+            raw_packet(true, 1, &first),
+            Packet {
+                timestamp: UNIX_EPOCH,
+                link_type: 147,
+                data: Vec::new(),
+            },
+            raw_packet(true, 1 + first.len() as u32, &second),
+            raw_packet(false, 1, &resp(1)),
+            raw_packet(false, 1 + resp(1).len() as u32, &resp(2)),
+        ];
+
+        let capture = Capture::from_packets(&packets).unwrap();
+        assert_eq!(
+            capture.errors,
+            vec![
+                "10.0.0.1:40000 -> 10.0.0.2:7000: 2 bytes skipped",
+                "Unsupported link type 147: 1 packets skipped",
+            ]
+        );
+        let sernos = capture
+            .exchanges
+            .iter()
+            .map(|e| {
+                let resp = e.response.as_ref().unwrap();
+                (e.request.message.auth_serno, resp.message.auth_serno)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(sernos, vec![(1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn incorrect() {
+        assert!(matches!(
+            read_packets(b"not a capture at all"),
+            Err(PcapError::Format(_))
+        ));
+        assert!(read_packets(&PCAP[..PCAP.len() - 1]).is_err());
+
+        // Timestamp in seconds (if_tsresol=0) beyond the range of SystemTime
+        #[rustfmt::skip]
+        let words: &[u32] = &[
+            // Section header, little endian, version 1.0, unknown section length
+            PCAPNG_SECTION_HEADER, 28, 0x1a2b_3c4d, 1, u32::MAX, u32::MAX, 28,
+            // Ethernet interface with if_tsresol=0 option
+            PCAPNG_INTERFACE_DESCRIPTION, 32, 1, 0, 0x0001_0009, 0, 0, 32,
+            // Empty packet with the largest timestamp
+            PCAPNG_ENHANCED_PACKET, 32, 0, u32::MAX, u32::MAX, 0, 0, 32,
+        ];
+        let data = words
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(data.len(), 92);
+        assert!(matches!(
+            read_packets(&data),
+            Err(PcapError::Format(s)) if s == "timestamp out of range"
+        ));
+
+        let packet = Packet {
+            timestamp: UNIX_EPOCH,
+            link_type: 147,
+            data: Vec::new(),
+        };
+        assert!(matches!(
+            tcp_segment(&packet),
+            Err(PcapError::UnsupportedLinkType(147))
+        ));
+    }
+}
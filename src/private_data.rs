@@ -0,0 +1,364 @@
+//! Delimited private data sub-elements, as carried in ISO field 48 or Sigma tags.
+//!
+//! E.g. `USRDT|2595100250` holds a single sub-element `USRDT` with value `2595100250`
+//! when parsed with the default [`PrivateDataFormat`].
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Error, SigmaRequest};
+
+/// ISO field which carries additional private data.
+pub const PRIVATE_DATA_FIELD: u16 = 48;
+
+/// How sub-elements are laid out between delimiters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Keys and values alternate: `KEY1|VALUE1|KEY2|VALUE2`.
+    #[default]
+    Pairs,
+    /// Every sub-element holds a key and a value: `KEY1=VALUE1|KEY2=VALUE2`.
+    KeyValue(char),
+    /// Sub-elements are identified by their 1-based position: `VALUE1|VALUE2`.
+    Positional,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrivateDataFormat {
+    pub delimiter: char,
+    pub layout: Layout,
+}
+
+impl Default for PrivateDataFormat {
+    fn default() -> Self {
+        Self {
+            delimiter: '|',
+            layout: Layout::default(),
+        }
+    }
+}
+
+impl PrivateDataFormat {
+    pub fn new(delimiter: char, layout: Layout) -> Self {
+        Self { delimiter, layout }
+    }
+}
+
+/// Ordered sub-elements of delimited private data.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrivateData {
+    format: PrivateDataFormat,
+    elements: Vec<(String, String)>,
+}
+
+impl PrivateData {
+    pub fn new(format: PrivateDataFormat) -> Self {
+        Self {
+            format,
+            elements: Vec::new(),
+        }
+    }
+
+    /// Parses data with the default format.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        Self::parse_with(s, PrivateDataFormat::default())
+    }
+
+    pub fn parse_with(s: &str, format: PrivateDataFormat) -> Result<Self, Error> {
+        let mut data = Self::new(format);
+        if s.is_empty() {
+            return Ok(data);
+        }
+
+        let mut parts = s.split(format.delimiter);
+        match format.layout {
+            Layout::Pairs => {
+                while let Some(key) = parts.next() {
+                    let value = parts.next().ok_or_else(|| {
+                        Error::incorrect_field_data("private data", "even number of sub-elements")
+                    })?;
+                    data.elements.push((key.into(), value.into()));
+                }
+            }
+            Layout::KeyValue(separator) => {
+                for part in parts {
+                    let (key, value) =
+                        part.split_once(separator)
+                            .ok_or_else(|| Error::IncorrectFieldData {
+                                field_name: "private data".into(),
+                                should_be: format!("sub-elements like KEY{}VALUE", separator),
+                            })?;
+                    data.elements.push((key.into(), value.into()));
+                }
+            }
+            Layout::Positional => {
+                data.elements = parts
+                    .enumerate()
+                    .map(|(i, v)| ((i + 1).to_string(), v.into()))
+                    .collect();
+            }
+        }
+        Ok(data)
+    }
+
+    pub fn format(&self) -> PrivateDataFormat {
+        self.format
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.elements.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.elements
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sub-element parsed into given type.
+    pub fn get_as<T: FromStr>(&self, key: &str) -> Result<Option<T>, Error> {
+        self.get(key)
+            .map(|v| {
+                v.parse::<T>().map_err(|_| Error::IncorrectFieldData {
+                    field_name: format!("private data {}", key),
+                    should_be: std::any::type_name::<T>().into(),
+                })
+            })
+            .transpose()
+    }
+
+    pub fn get_u64(&self, key: &str) -> Result<Option<u64>, Error> {
+        self.get_as(key)
+    }
+
+    /// Replaces value of existing sub-element in place or appends a new one,
+    /// returns the previous value.
+    ///
+    /// Positional sub-elements can be appended only right after the last one.
+    pub fn set(&mut self, key: &str, value: impl Into<String>) -> Result<Option<String>, Error> {
+        let value = value.into();
+        if value.contains(self.format.delimiter) {
+            return Err(Error::IncorrectFieldData {
+                field_name: format!("private data {}", key),
+                should_be: format!("value without '{}'", self.format.delimiter),
+            });
+        }
+        if let Some((_, v)) = self.elements.iter_mut().find(|(k, _)| k == key) {
+            return Ok(Some(std::mem::replace(v, value)));
+        }
+
+        match self.format.layout {
+            Layout::Pairs if key.contains(self.format.delimiter) => {
+                return Err(Error::IncorrectFieldData {
+                    field_name: format!("private data {}", key),
+                    should_be: format!("key without '{}'", self.format.delimiter),
+                })
+            }
+            Layout::KeyValue(separator)
+                if key.contains(self.format.delimiter) || key.contains(separator) =>
+            {
+                return Err(Error::IncorrectFieldData {
+                    field_name: format!("private data {}", key),
+                    should_be: format!(
+                        "key without '{}' and '{}'",
+                        self.format.delimiter, separator
+                    ),
+                })
+            }
+            Layout::Positional if key != (self.elements.len() + 1).to_string() => {
+                return Err(Error::IncorrectFieldData {
+                    field_name: format!("private data {}", key),
+                    should_be: format!("position {}", self.elements.len() + 1),
+                })
+            }
+            _ => {}
+        }
+        self.elements.push((key.into(), value));
+        Ok(None)
+    }
+
+    /// Removes sub-element, returns its value.
+    ///
+    /// Positional sub-elements are cleared rather than removed to keep positions of the rest.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let pos = self.elements.iter().position(|(k, _)| k == key)?;
+        match self.format.layout {
+            Layout::Positional => Some(std::mem::take(&mut self.elements[pos].1)),
+            _ => Some(self.elements.remove(pos).1),
+        }
+    }
+}
+
+impl fmt::Display for PrivateData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let delimiter = self.format.delimiter;
+        for (i, (key, value)) in self.elements.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", delimiter)?;
+            }
+            match self.format.layout {
+                Layout::Pairs => write!(f, "{}{}{}", key, delimiter, value)?,
+                Layout::KeyValue(separator) => write!(f, "{}{}{}", key, separator, value)?,
+                Layout::Positional => write!(f, "{}", value)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SigmaRequest {
+    /// Parsed private data (i048), if present.
+    pub fn private_data(&self, format: PrivateDataFormat) -> Result<Option<PrivateData>, Error> {
+        self.iso_fields
+            .get(&PRIVATE_DATA_FIELD)
+            .map(|v| PrivateData::parse_with(&v.to_cow_str_lossy(), format))
+            .transpose()
+    }
+
+    pub fn set_private_data(&mut self, data: &PrivateData) {
+        self.iso_fields
+            .insert(PRIVATE_DATA_FIELD, data.to_string().into());
+    }
+
+    pub fn private_data_element(
+        &self,
+        format: PrivateDataFormat,
+        key: &str,
+    ) -> Result<Option<String>, Error> {
+        Ok(self
+            .private_data(format)?
+            .and_then(|data| data.get(key).map(String::from)))
+    }
+
+    /// Sets single sub-element of private data (i048), creating the field if absent.
+    /// Returns the previous value.
+    pub fn set_private_data_element(
+        &mut self,
+        format: PrivateDataFormat,
+        key: &str,
+        value: impl Into<String>,
+    ) -> Result<Option<String>, Error> {
+        let mut data = self
+            .private_data(format)?
+            .unwrap_or_else(|| PrivateData::new(format));
+        let previous = data.set(key, value)?;
+        self.set_private_data(&data);
+        Ok(previous)
+    }
+
+    pub fn remove_private_data_element(
+        &mut self,
+        format: PrivateDataFormat,
+        key: &str,
+    ) -> Result<Option<String>, Error> {
+        let mut data = match self.private_data(format)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let removed = data.remove(key);
+        if removed.is_some() {
+            self.set_private_data(&data);
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs() {
+        let mut data = PrivateData::parse("USRDT|2595100250|CHN|WEB").unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data.get("USRDT"), Some("2595100250"));
+        assert_eq!(data.get_u64("USRDT").unwrap(), Some(2595100250));
+        assert!(data.get_u64("CHN").is_err());
+        assert_eq!(data.get("MISSING"), None);
+
+        assert_eq!(data.set("CHN", "POS").unwrap(), Some("WEB".into()));
+        assert_eq!(data.set("ECI", "05").unwrap(), None);
+        assert_eq!(data.to_string(), "USRDT|2595100250|CHN|POS|ECI|05");
+        assert!(data.set("ECI", "0|5").is_err());
+
+        assert_eq!(data.remove("USRDT"), Some("2595100250".into()));
+        assert_eq!(data.to_string(), "CHN|POS|ECI|05");
+
+        assert!(PrivateData::parse("USRDT").is_err());
+        assert!(PrivateData::parse("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn key_value() {
+        let format = PrivateDataFormat::new(';', Layout::KeyValue('='));
+        let mut data = PrivateData::parse_with("A=1;B=;C=x=y", format).unwrap();
+        assert_eq!(
+            data.iter().collect::<Vec<_>>(),
+            vec![("A", "1"), ("B", ""), ("C", "x=y")]
+        );
+        assert_eq!(data.get_as::<u8>("A").unwrap(), Some(1));
+
+        data.set("D", "4").unwrap();
+        assert!(data.set("E=", "5").is_err());
+        assert_eq!(data.to_string(), "A=1;B=;C=x=y;D=4");
+
+        assert!(PrivateData::parse_with("A=1;B", format).is_err());
+    }
+
+    #[test]
+    fn positional() {
+        let format = PrivateDataFormat::new('|', Layout::Positional);
+        let mut data = PrivateData::parse_with("first||third", format).unwrap();
+        assert_eq!(data.get("1"), Some("first"));
+        assert_eq!(data.get("2"), Some(""));
+        assert_eq!(data.get("3"), Some("third"));
+
+        assert_eq!(data.remove("1"), Some("first".into()));
+        data.set("2", "second").unwrap();
+        data.set("4", "fourth").unwrap();
+        assert!(data.set("6", "sixth").is_err());
+        assert_eq!(data.to_string(), "|second|third|fourth");
+    }
+
+    #[test]
+    fn request_private_data() {
+        let format = PrivateDataFormat::default();
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        assert_eq!(req.private_data(format).unwrap(), None);
+        assert_eq!(req.remove_private_data_element(format, "USRDT"), Ok(None));
+
+        req.set_private_data_element(format, "USRDT", "2595100250")
+            .unwrap();
+        assert_eq!(
+            req.iso_fields.get(&PRIVATE_DATA_FIELD).unwrap(),
+            "USRDT|2595100250"
+        );
+
+        req.set_private_data_element(format, "CHN", "WEB").unwrap();
+        assert_eq!(
+            req.private_data_element(format, "CHN").unwrap(),
+            Some("WEB".into())
+        );
+        assert_eq!(
+            req.remove_private_data_element(format, "USRDT").unwrap(),
+            Some("2595100250".into())
+        );
+        assert_eq!(req.iso_fields.get(&PRIVATE_DATA_FIELD).unwrap(), "CHN|WEB");
+
+        req.iso_fields.insert(PRIVATE_DATA_FIELD, "CHN".into());
+        assert!(req.private_data_element(format, "CHN").is_err());
+    }
+}
@@ -0,0 +1,364 @@
+//! Raw messages which keep fields in their wire order, including repeated ones.
+//!
+//! [`SigmaRequest`] and [`SigmaResponse`] group and sort fields, so re-encoding them is not
+//! byte-identical to the received data. [`RawMessage`] re-encodes byte for byte, which
+//! matters for MAC checks and comparison against captures.
+
+use std::convert::TryFrom;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::util::{bytes_split_to, decode_bcd_x4, encode_bcd_x4};
+use crate::{Error, SigmaRequest, SigmaResponse, Tag};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    /// Header is SAF (1), source (1), MTI (4) and serno (10).
+    Request,
+    /// Header is MTI (4) and serno (10).
+    Response,
+}
+
+impl MessageKind {
+    fn header_len(&self) -> usize {
+        match self {
+            Self::Request => 16,
+            Self::Response => 14,
+        }
+    }
+}
+
+/// What to do with repeated fields on decoding.
+///
+/// Fees (T0032) of responses are repeatable by design and are never treated as duplicates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    #[default]
+    Allow,
+    Reject,
+}
+
+/// Single field as it was on the wire.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawField {
+    tag: Tag,
+    pub data: Bytes,
+    /// Tag bytes as received, the subfield byte of T and I tags is not necessarily zero.
+    raw_tag: [u8; 4],
+}
+
+/// Longest field data the 4 digit length can express.
+const MAX_FIELD_LENGTH: usize = 9999;
+
+fn field_length_error(length: usize) -> Error {
+    Error::Bounds(format!(
+        "field length {} exceeds {}",
+        length, MAX_FIELD_LENGTH
+    ))
+}
+
+impl RawField {
+    pub fn new(tag: Tag, data: impl Into<Bytes>) -> Result<Self, Error> {
+        let data = data.into();
+        if data.len() > MAX_FIELD_LENGTH {
+            return Err(field_length_error(data.len()));
+        }
+        let mut buf = BytesMut::with_capacity(4);
+        tag.encode_to_buf(&mut buf)?;
+        let mut raw_tag = [0u8; 4];
+        raw_tag.copy_from_slice(&buf);
+        Ok(Self { tag, data, raw_tag })
+    }
+
+    /// Tag of the field; fields with another tag are created with [`RawField::new`].
+    pub fn tag(&self) -> &Tag {
+        &self.tag
+    }
+}
+
+/// Message with fields in wire order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawMessage {
+    kind: MessageKind,
+    header: Bytes,
+    pub fields: Vec<RawField>,
+}
+
+impl RawMessage {
+    pub fn decode(
+        kind: MessageKind,
+        mut data: Bytes,
+        duplicates: DuplicatePolicy,
+    ) -> Result<Self, Error> {
+        let msg_len = parse_ascii_bytes_lossy!(
+            &bytes_split_to(&mut data, 5)?,
+            usize,
+            Error::incorrect_field_data("message length", "valid integer")
+        )?;
+        let mut data = bytes_split_to(&mut data, msg_len)?;
+        let header = bytes_split_to(&mut data, kind.header_len())?;
+        let mut msg = Self {
+            kind,
+            header,
+            fields: Vec::new(),
+        };
+        // Validates the header
+        msg.mti()?;
+        msg.auth_serno()?;
+
+        while !data.is_empty() {
+            let tag_src = bytes_split_to(&mut data, 4)?;
+            let mut raw_tag = [0u8; 4];
+            raw_tag.copy_from_slice(&tag_src);
+            let tag = Tag::decode(tag_src)?;
+
+            let len_src = bytes_split_to(&mut data, 2)?;
+            let len = decode_bcd_x4(&[len_src[0], len_src[1]])?;
+            let field = RawField {
+                tag,
+                data: bytes_split_to(&mut data, len as usize)?,
+                raw_tag,
+            };
+
+            if duplicates == DuplicatePolicy::Reject
+                && !msg.is_repeatable(&field.tag)
+                && msg.fields.iter().any(|f| f.tag == field.tag)
+            {
+                return Err(Error::IncorrectData(format!(
+                    "Duplicate field {}",
+                    field.tag
+                )));
+            }
+            msg.fields.push(field);
+        }
+
+        Ok(msg)
+    }
+
+    pub fn decode_request(data: Bytes, duplicates: DuplicatePolicy) -> Result<Self, Error> {
+        Self::decode(MessageKind::Request, data, duplicates)
+    }
+
+    pub fn decode_response(data: Bytes, duplicates: DuplicatePolicy) -> Result<Self, Error> {
+        Self::decode(MessageKind::Response, data, duplicates)
+    }
+
+    fn is_repeatable(&self, tag: &Tag) -> bool {
+        self.kind == MessageKind::Response && *tag == Tag::Regular(32)
+    }
+
+    pub fn encode(&self) -> Result<Bytes, Error> {
+        let mut buf = BytesMut::with_capacity(8192);
+        buf.extend_from_slice(b"00000");
+        buf.extend_from_slice(&self.header);
+        for field in self.fields.iter() {
+            buf.extend_from_slice(&field.raw_tag);
+            let length = u16::try_from(field.data.len())
+                .map_err(|_| field_length_error(field.data.len()))?;
+            buf.extend_from_slice(&encode_bcd_x4(length)?);
+            buf.extend_from_slice(&field.data);
+        }
+
+        let msg_len = buf.len() - 5;
+        if msg_len > 99999 {
+            return Err(Error::Bounds(format!(
+                "message length {} exceeds 99999",
+                msg_len
+            )));
+        }
+        buf[0..5].copy_from_slice(format!("{:05}", msg_len).as_bytes());
+        Ok(buf.freeze())
+    }
+
+    pub fn kind(&self) -> MessageKind {
+        self.kind
+    }
+
+    /// Header bytes as received.
+    pub fn header(&self) -> &Bytes {
+        &self.header
+    }
+
+    pub fn mti(&self) -> Result<&str, Error> {
+        let offset = self.kind.header_len() - 14;
+        std::str::from_utf8(&self.header[offset..offset + 4])
+            .ok()
+            .filter(|v| v.bytes().all(|b| b.is_ascii_digit()))
+            .ok_or_else(|| Error::incorrect_field_data("MTI", "4 digit number (string)"))
+    }
+
+    pub fn auth_serno(&self) -> Result<u64, Error> {
+        let offset = self.kind.header_len() - 10;
+        String::from_utf8_lossy(&self.header[offset..])
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| Error::IncorrectFieldData {
+                field_name: "Serno".into(),
+                should_be: "u64".into(),
+            })
+    }
+
+    /// Data of all fields with given tag in wire order.
+    pub fn get_all<'a>(&'a self, tag: &Tag) -> impl Iterator<Item = &'a Bytes> + 'a {
+        let tag = tag.clone();
+        self.fields
+            .iter()
+            .filter(move |f| f.tag == tag)
+            .map(|f| &f.data)
+    }
+
+    /// Data of the first field with given tag.
+    pub fn get(&self, tag: &Tag) -> Option<&Bytes> {
+        self.get_all(tag).next()
+    }
+
+    /// Tags which occur more than once, in order of their first occurrence.
+    pub fn duplicates(&self) -> Vec<Tag> {
+        let mut duplicates = Vec::new();
+        for (i, field) in self.fields.iter().enumerate() {
+            if !self.is_repeatable(&field.tag)
+                && !duplicates.contains(&field.tag)
+                && self.fields[i + 1..].iter().any(|f| f.tag == field.tag)
+            {
+                duplicates.push(field.tag.clone());
+            }
+        }
+        duplicates
+    }
+
+    /// Converts into request; of repeated fields the last one wins, as in [`SigmaRequest::decode`].
+    pub fn to_request(&self) -> Result<SigmaRequest, Error> {
+        if self.kind != MessageKind::Request {
+            return Err(Error::IncorrectData("Not a request".into()));
+        }
+        SigmaRequest::decode(self.encode()?)
+    }
+
+    pub fn to_response(&self) -> Result<SigmaResponse, Error> {
+        if self.kind != MessageKind::Response {
+            return Err(Error::IncorrectData("Not a response".into()));
+        }
+        SigmaResponse::decode(self.encode()?)
+    }
+}
+
+impl SigmaRequest {
+    /// Raw message with fields in the order [`SigmaRequest::encode`] emits them.
+    pub fn to_raw(&self) -> Result<RawMessage, Error> {
+        RawMessage::decode_request(self.encode()?, DuplicatePolicy::Reject)
+    }
+}
+
+impl SigmaResponse {
+    pub fn to_raw(&self) -> Result<RawMessage, Error> {
+        RawMessage::decode_response(self.encode()?, DuplicatePolicy::Reject)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// I000 before T0000, repeated T0018 and T subfield byte 01.
+    const REQUEST: &[u8] = b"00070NM01000000000000\
+        I\x00\x00\x00\x00\x040100\
+        T\x00\x00\x00\x00\x132371492071643\
+        T\x00\x18\x00\x00\x01Y\
+        S\x00\x48\x01\x00\x05USRDT\
+        T\x00\x18\x01\x00\x01N";
+
+    #[test]
+    fn byte_identical() {
+        let data = Bytes::from_static(REQUEST);
+        let msg = RawMessage::decode_request(data.clone(), DuplicatePolicy::Allow).unwrap();
+        assert_eq!(msg.kind(), MessageKind::Request);
+        assert_eq!(msg.mti().unwrap(), "0100");
+        assert_eq!(msg.auth_serno().unwrap(), 0);
+        assert_eq!(
+            msg.fields
+                .iter()
+                .map(|f| f.tag.to_string())
+                .collect::<Vec<_>>(),
+            vec!["i000", "T0000", "T0018", "s004801", "T0018"]
+        );
+        assert_eq!(
+            msg.get_all(&Tag::Regular(18)).collect::<Vec<_>>(),
+            vec![&Bytes::from_static(b"Y"), &Bytes::from_static(b"N")]
+        );
+        assert_eq!(msg.get(&Tag::Iso(0)).unwrap(), &b"0100"[..]);
+        assert_eq!(msg.duplicates(), vec![Tag::Regular(18)]);
+
+        assert_eq!(msg.encode().unwrap(), data);
+
+        // Re-encoding of the request groups and sorts fields and loses the repeated tag
+        let req = msg.to_request().unwrap();
+        assert_eq!(req.tags.get(&18).unwrap(), "N");
+        assert_ne!(req.encode().unwrap(), data);
+    }
+
+    #[test]
+    fn reject_duplicates() {
+        let data = Bytes::from_static(REQUEST);
+        assert_eq!(
+            RawMessage::decode_request(data, DuplicatePolicy::Reject),
+            Err(Error::IncorrectData("Duplicate field T0018".into()))
+        );
+    }
+
+    #[test]
+    fn response_fees_are_not_duplicates() {
+        let mut resp = SigmaResponse::new("0110", 1, 8100).unwrap();
+        for amount in [100, 200] {
+            resp.fees.push(crate::FeeData {
+                reason: 8116,
+                currency: 978,
+                amount,
+            });
+        }
+
+        let msg =
+            RawMessage::decode_response(resp.encode().unwrap(), DuplicatePolicy::Reject).unwrap();
+        assert!(msg.duplicates().is_empty());
+        assert_eq!(msg.get_all(&Tag::Regular(32)).count(), 2);
+        assert_eq!(msg.to_response().unwrap().fees, resp.fees);
+        assert!(msg.to_request().is_err());
+    }
+
+    #[test]
+    fn build() {
+        let mut msg = SigmaRequest::new("N", "M", "0100", 1)
+            .unwrap()
+            .to_raw()
+            .unwrap();
+        assert!(msg.fields.is_empty());
+
+        msg.fields
+            .push(RawField::new(Tag::Regular(18), &b"Y"[..]).unwrap());
+        msg.fields
+            .push(RawField::new(Tag::Iso(2), &b"4111111111111111"[..]).unwrap());
+        msg.fields
+            .push(RawField::new(Tag::Regular(18), &b"N"[..]).unwrap());
+
+        let encoded = msg.encode().unwrap();
+        assert_eq!(
+            RawMessage::decode_request(encoded, DuplicatePolicy::Allow).unwrap(),
+            msg
+        );
+        assert_eq!(
+            RawField::new(Tag::Regular(18), vec![b'Y'; 10000]),
+            Err(Error::Bounds("field length 10000 exceeds 9999".into()))
+        );
+        // Data is public, so its length is checked on encoding as well
+        for length in [10000, 65541] {
+            let mut msg = msg.clone();
+            msg.fields[0].data = Bytes::from(vec![b'Y'; length]);
+            assert!(matches!(msg.encode(), Err(Error::Bounds(_))));
+        }
+
+        assert!(RawMessage::decode_request(
+            Bytes::from_static(b"00003NM0"),
+            DuplicatePolicy::Allow
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,350 @@
+//! Recording of traffic passing through [`SigmaClientProtocol`].
+//!
+//! Recording is an append-only sequence of records, each one consists of:
+//! * direction, `>` for outgoing (requests) and `<` for incoming (responses) data;
+//! * 20-digit timestamp, microseconds since UNIX epoch;
+//! * 10-digit length of the data;
+//! * the data, normally a single frame including its length header.
+//!
+//! A partially written trailing record (e.g. after a crash) is ignored on reading.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::codec::{ClientProtocolError, SigmaClientProtocol};
+use crate::util::bytes_split_to;
+use crate::{Error, SigmaRequest, SigmaResponse};
+
+const OUTGOING: u8 = b'>';
+const INCOMING: u8 = b'<';
+const TIMESTAMP_LENGTH: usize = 20;
+const DATA_LENGTH_LENGTH: usize = 10;
+const HEADER_LENGTH: usize = 1 + TIMESTAMP_LENGTH + DATA_LENGTH_LENGTH;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent to the host.
+    Outgoing,
+    /// Received from the host.
+    Incoming,
+}
+
+/// Data passed in one direction at some moment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    pub data: Bytes,
+}
+
+impl Record {
+    pub fn new(direction: Direction, data: impl Into<Bytes>) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            direction,
+            data: data.into(),
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let micros = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros();
+        let mut buf = BytesMut::with_capacity(HEADER_LENGTH + self.data.len());
+        buf.extend_from_slice(&[match self.direction {
+            Direction::Outgoing => OUTGOING,
+            Direction::Incoming => INCOMING,
+        }]);
+        buf.extend_from_slice(format!("{:020}{:010}", micros, self.data.len()).as_bytes());
+        buf.extend_from_slice(&self.data);
+        buf.freeze()
+    }
+
+    /// Decodes the next record, `None` if the data ends before the record does.
+    pub fn decode(data: &mut Bytes) -> Result<Option<Self>, Error> {
+        if data.len() < HEADER_LENGTH {
+            return Ok(None);
+        }
+        let direction = match data[0] {
+            OUTGOING => Direction::Outgoing,
+            INCOMING => Direction::Incoming,
+            _ => {
+                return Err(Error::incorrect_field_data(
+                    "record direction",
+                    "'>' or '<'",
+                ))
+            }
+        };
+        let micros = parse_ascii_bytes_lossy!(
+            &data[1..1 + TIMESTAMP_LENGTH],
+            u64,
+            Error::incorrect_field_data("record timestamp", "20 digits")
+        )?;
+        let length = parse_ascii_bytes_lossy!(
+            &data[1 + TIMESTAMP_LENGTH..HEADER_LENGTH],
+            usize,
+            Error::incorrect_field_data("record length", "10 digits")
+        )?;
+        if data.len() < HEADER_LENGTH + length {
+            return Ok(None);
+        }
+
+        let _ = data.split_to(HEADER_LENGTH);
+        Ok(Some(Self {
+            timestamp: UNIX_EPOCH + Duration::from_micros(micros),
+            direction,
+            data: bytes_split_to(data, length)?,
+        }))
+    }
+
+    /// Request of an outgoing record.
+    pub fn request(&self) -> Result<SigmaRequest, Error> {
+        match self.direction {
+            Direction::Outgoing => SigmaRequest::decode(self.data.clone()),
+            Direction::Incoming => Err(Error::IncorrectData("Not an outgoing record".into())),
+        }
+    }
+
+    /// Response of an incoming record.
+    pub fn response(&self) -> Result<SigmaResponse, Error> {
+        match self.direction {
+            Direction::Incoming => SigmaResponse::decode(self.data.clone()),
+            Direction::Outgoing => Err(Error::IncorrectData("Not an incoming record".into())),
+        }
+    }
+}
+
+/// Reads all complete records.
+pub fn read_records<R: Read>(mut reader: R) -> Result<Vec<Record>, ClientProtocolError> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let mut data = Bytes::from(data);
+
+    let mut records = Vec::new();
+    while let Some(record) = Record::decode(&mut data)? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+pub fn read_records_from_path<P: AsRef<Path>>(path: P) -> Result<Vec<Record>, ClientProtocolError> {
+    read_records(File::open(path)?)
+}
+
+/// Writer of records, flushing each one.
+#[derive(Debug)]
+pub struct RecordWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        self.writer.write_all(&record.encode())?;
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl RecordWriter<File> {
+    /// Opens recording at given path for appending, creating it if necessary.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Self::new(file))
+    }
+}
+
+/// [`SigmaClientProtocol`] which records every encoded and decoded frame.
+///
+/// Clones share the writer. Incoming frames are recorded whole, including corrupt ones, while
+/// bytes of incorrect headers skipped by
+/// [`ResyncStrategy::SkipToNextHeader`](crate::codec::ResyncStrategy) are not recorded.
+#[derive(Debug)]
+pub struct RecordingCodec<W> {
+    inner: SigmaClientProtocol,
+    writer: Arc<Mutex<RecordWriter<W>>>,
+}
+
+impl<W> Clone for RecordingCodec<W> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            writer: self.writer.clone(),
+        }
+    }
+}
+
+impl<W: Write> RecordingCodec<W> {
+    pub fn new(inner: SigmaClientProtocol, writer: RecordWriter<W>) -> Self {
+        Self {
+            inner,
+            writer: Arc::new(Mutex::new(writer)),
+        }
+    }
+
+    pub fn inner(&self) -> &SigmaClientProtocol {
+        &self.inner
+    }
+
+    pub fn writer(&self) -> &Arc<Mutex<RecordWriter<W>>> {
+        &self.writer
+    }
+
+    fn record(&self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        self.writer
+            .lock()
+            .unwrap()
+            .write(&Record::new(direction, Bytes::copy_from_slice(data)))
+    }
+}
+
+impl<W: Write> Decoder for RecordingCodec<W> {
+    type Item = SigmaResponse;
+    type Error = ClientProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            let frame = match self.inner.next_frame(src)? {
+                Some(v) => v,
+                None => return Ok(None),
+            };
+            self.record(Direction::Incoming, &frame)?;
+            if let Some(resp) = self.inner.decode_frame(frame)? {
+                return Ok(Some(resp));
+            }
+        }
+    }
+}
+
+impl<W: Write> Encoder<SigmaRequest> for RecordingCodec<W> {
+    type Error = ClientProtocolError;
+
+    fn encode(&mut self, item: SigmaRequest, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let start = dst.len();
+        self.inner.encode(item, dst)?;
+        self.record(Direction::Outgoing, &dst[start..])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BufMut;
+
+    use super::*;
+    use crate::codec::ResyncStrategy;
+
+    #[test]
+    fn encode_decode() {
+        let record = Record {
+            timestamp: UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456),
+            direction: Direction::Outgoing,
+            data: Bytes::from_static(b"00016NM01000000000001"),
+        };
+        let encoded = record.encode();
+        assert_eq!(
+            encoded,
+            &b">00001700000000123456000000002100016NM01000000000001"[..]
+        );
+
+        let mut data = encoded.clone();
+        assert_eq!(Record::decode(&mut data).unwrap(), Some(record.clone()));
+        assert!(data.is_empty());
+        assert_eq!(record.request().unwrap().auth_serno, 1);
+        assert!(record.response().is_err());
+
+        // Partially written record
+        let mut data = encoded.slice(..encoded.len() - 1);
+        assert_eq!(Record::decode(&mut data).unwrap(), None);
+        assert!(Record::decode(&mut Bytes::from_static(&[b'x'; HEADER_LENGTH])).is_err());
+    }
+
+    #[test]
+    fn recording_codec() {
+        let mut codec =
+            RecordingCodec::new(SigmaClientProtocol::new(), RecordWriter::new(Vec::new()));
+
+        let mut dst = BytesMut::new();
+        codec
+            .encode(
+                SigmaRequest::new("N", "M", "0100", 4007040978).unwrap(),
+                &mut dst,
+            )
+            .unwrap();
+
+        let mut src = BytesMut::new();
+        src.put(&b"0002401104007040978T\x00\x31\x00\x00\x048100000"[..]);
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap().reason, 8100);
+        assert_eq!(src, b"000"[..]);
+        assert!(codec.clone().decode(&mut src).unwrap().is_none());
+
+        let writer = codec.writer().lock().unwrap();
+        let records = read_records(&writer.writer[..]).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::Outgoing);
+        assert_eq!(records[0].data, dst);
+        assert_eq!(records[0].request().unwrap().auth_serno, 4007040978);
+        assert_eq!(records[1].direction, Direction::Incoming);
+        assert_eq!(records[1].response().unwrap().reason, 8100);
+    }
+
+    #[test]
+    fn recording_codec_resync() {
+        let mut codec = RecordingCodec::new(
+            SigmaClientProtocol::new().with_resync(ResyncStrategy::SkipToNextHeader),
+            RecordWriter::new(Vec::new()),
+        );
+
+        let mut src = BytesMut::new();
+        src.put(&b"abc000140110400704097x"[..]);
+        src.put(&b"0002401104007040978T\x00\x31\x00\x00\x048100"[..]);
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap().reason, 8100);
+        assert!(src.is_empty());
+        assert_eq!(codec.inner().stats().frames_skipped, 2);
+
+        let writer = codec.writer().lock().unwrap();
+        let records = read_records(&writer.writer[..]).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].data, b"000140110400704097x"[..]);
+        assert!(records[0].response().is_err());
+        assert_eq!(records[1].response().unwrap().reason, 8100);
+    }
+
+    #[test]
+    fn append_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("traffic.rec");
+        for serno in [1, 2] {
+            let data = SigmaRequest::new("N", "M", "0100", serno)
+                .unwrap()
+                .encode()
+                .unwrap();
+            RecordWriter::open(&path)
+                .unwrap()
+                .write(&Record::new(Direction::Outgoing, data))
+                .unwrap();
+        }
+        let records = read_records_from_path(&path).unwrap();
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| r.request().unwrap().auth_serno)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+}
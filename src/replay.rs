@@ -0,0 +1,305 @@
+//! Replay of recorded traffic against a host and comparison of responses.
+//!
+//! Outgoing records of a recording are decoded as requests and paired with the incoming
+//! records of the same authorization serno. Requests are sent again with [`SigmaClient`] and
+//! the responses are compared with the recorded ones.
+
+use std::time::{Duration, SystemTime};
+
+use tokio::time::{sleep_until, Instant};
+
+use crate::client::SigmaClient;
+use crate::record::{Direction, Record};
+use crate::{Error, SigmaRequest, SigmaResponse};
+
+/// Pace of sending requests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timing {
+    /// Each request is sent after the response to the previous one.
+    AsFastAsPossible,
+    /// Requests are sent at recorded intervals divided by given factor, without waiting
+    /// for responses to previous requests. Factor 1.0 keeps the original timing.
+    Scaled(f64),
+}
+
+impl Timing {
+    pub fn original() -> Self {
+        Self::Scaled(1.0)
+    }
+}
+
+/// Recorded request with the recorded response to it.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    /// Time since the first recorded request.
+    pub offset: Duration,
+    pub request: SigmaRequest,
+    pub response: Option<SigmaResponse>,
+}
+
+/// Pairs recorded requests with recorded responses by authorization serno, in request order.
+///
+/// Incoming records which do not decode or do not answer any request (e.g. echo responses of
+/// keepalive) are ignored.
+pub fn exchanges(records: &[Record]) -> Result<Vec<Exchange>, Error> {
+    let mut exchanges: Vec<Exchange> = Vec::new();
+    let mut start: Option<SystemTime> = None;
+
+    for record in records {
+        match record.direction {
+            Direction::Outgoing => {
+                let start = *start.get_or_insert(record.timestamp);
+                exchanges.push(Exchange {
+                    offset: record.timestamp.duration_since(start).unwrap_or_default(),
+                    request: record.request()?,
+                    response: None,
+                });
+            }
+            Direction::Incoming => {
+                let resp = match record.response() {
+                    Ok(v) => v,
+                    Err(_) => continue,
+                };
+                if let Some(exchange) = exchanges
+                    .iter_mut()
+                    .find(|e| e.response.is_none() && e.request.auth_serno == resp.auth_serno)
+                {
+                    exchange.response = Some(resp);
+                }
+            }
+        }
+    }
+    Ok(exchanges)
+}
+
+/// Response field which differs between the recorded and the replayed response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub field: &'static str,
+    pub recorded: String,
+    pub replayed: String,
+}
+
+/// Differences between responses, authorization serno is not compared.
+pub fn diff(recorded: &SigmaResponse, replayed: &SigmaResponse) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    let mut compare = |field, recorded: String, replayed: String| {
+        if recorded != replayed {
+            differences.push(Difference {
+                field,
+                recorded,
+                replayed,
+            });
+        }
+    };
+    compare("MTI", recorded.mti().into(), replayed.mti().into());
+    compare(
+        "T0031",
+        recorded.reason.to_string(),
+        replayed.reason.to_string(),
+    );
+    compare(
+        "T0032",
+        format!("{:?}", recorded.fees),
+        format!("{:?}", replayed.fees),
+    );
+    compare(
+        "T0033",
+        format!("{:?}", recorded.xri),
+        format!("{:?}", replayed.xri),
+    );
+    compare(
+        "T0048",
+        format!("{:?}", recorded.adata),
+        format!("{:?}", replayed.adata),
+    );
+    compare(
+        "T0050",
+        format!("{:?}", recorded.supdata),
+        format!("{:?}", replayed.supdata),
+    );
+    differences
+}
+
+/// Outcome of a single replayed request.
+#[derive(Debug)]
+pub struct ReplayResult {
+    pub exchange: Exchange,
+    /// Replayed response or the error of sending the request.
+    pub replayed: Result<SigmaResponse, String>,
+    /// Differences from the recorded response, empty if any of the responses is absent.
+    pub differences: Vec<Difference>,
+}
+
+impl ReplayResult {
+    fn new(exchange: Exchange, replayed: Result<SigmaResponse, String>) -> Self {
+        let differences = match (&exchange.response, &replayed) {
+            (Some(recorded), Ok(replayed)) => diff(recorded, replayed),
+            _ => Vec::new(),
+        };
+        Self {
+            exchange,
+            replayed,
+            differences,
+        }
+    }
+
+    /// Whether the replayed response matches the recorded one.
+    pub fn matches(&self) -> bool {
+        match (&self.exchange.response, &self.replayed) {
+            (Some(_), Ok(_)) => self.differences.is_empty(),
+            (None, Err(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Replays recorded requests with given client, results are in request order.
+pub async fn replay(
+    client: &SigmaClient,
+    exchanges: Vec<Exchange>,
+    timing: Timing,
+) -> Vec<ReplayResult> {
+    let mut results = Vec::with_capacity(exchanges.len());
+
+    match timing {
+        Timing::AsFastAsPossible => {
+            for exchange in exchanges {
+                let replayed = client.send(exchange.request.clone()).await;
+                results.push(ReplayResult::new(
+                    exchange,
+                    replayed.map_err(|err| err.to_string()),
+                ));
+            }
+        }
+        Timing::Scaled(factor) => {
+            let start = Instant::now();
+            let mut handles = Vec::with_capacity(exchanges.len());
+            for exchange in exchanges {
+                sleep_until(start + exchange.offset.div_f64(factor)).await;
+                let client = client.clone();
+                let req = exchange.request.clone();
+                handles.push((
+                    exchange,
+                    tokio::spawn(async move { client.send(req).await }),
+                ));
+            }
+            for (exchange, handle) in handles {
+                let replayed = match handle.await {
+                    Ok(v) => v.map_err(|err| err.to_string()),
+                    Err(err) => Err(err.to_string()),
+                };
+                results.push(ReplayResult::new(exchange, replayed));
+            }
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::client::ClientConfig;
+
+    fn record(offset_ms: u64, direction: Direction, data: bytes::Bytes) -> Record {
+        Record {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1000 + offset_ms),
+            direction,
+            data,
+        }
+    }
+
+    fn recording() -> Vec<Record> {
+        let req = |serno| {
+            SigmaRequest::new("N", "M", "0100", serno)
+                .unwrap()
+                .encode()
+                .unwrap()
+        };
+        let resp = |serno, reason| {
+            SigmaResponse::new("0110", serno, reason)
+                .unwrap()
+                .encode()
+                .unwrap()
+        };
+        vec![
+            record(0, Direction::Outgoing, req(1)),
+            record(5, Direction::Outgoing, req(2)),
+            record(7, Direction::Incoming, resp(2, 8100)),
+            record(8, Direction::Incoming, resp(1, 8495)),
+            record(9, Direction::Incoming, resp(3, 8100)),
+            record(10, Direction::Outgoing, req(3)),
+        ]
+    }
+
+    #[test]
+    fn pair_exchanges() {
+        let exchanges = exchanges(&recording()).unwrap();
+        assert_eq!(exchanges.len(), 3);
+        assert_eq!(exchanges[0].response.as_ref().unwrap().reason, 8495);
+        assert_eq!(exchanges[1].offset, Duration::from_millis(5));
+        assert_eq!(exchanges[1].response.as_ref().unwrap().reason, 8100);
+        // Response preceding the request is not paired
+        assert!(exchanges[2].response.is_none());
+    }
+
+    #[test]
+    fn differences() {
+        let recorded = SigmaResponse::new("0110", 1, 8100).unwrap();
+        let mut replayed = SigmaResponse::new("0110", 2, 8100).unwrap();
+        assert!(diff(&recorded, &replayed).is_empty());
+
+        replayed.reason = 8495;
+        replayed.adata = Some("X".into());
+        assert_eq!(
+            diff(&recorded, &replayed),
+            vec![
+                Difference {
+                    field: "T0031",
+                    recorded: "8100".into(),
+                    replayed: "8495".into(),
+                },
+                Difference {
+                    field: "T0048",
+                    recorded: "None".into(),
+                    replayed: "Some(\"X\")".into(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_against_mock() {
+        let (client_io, mut server_io) = duplex(4096);
+        let client = SigmaClient::new(client_io, ClientConfig::default());
+
+        // Mock host approves everything
+        tokio::spawn(async move {
+            loop {
+                let mut len = [0u8; 5];
+                if server_io.read_exact(&mut len).await.is_err() {
+                    break;
+                }
+                let len = std::str::from_utf8(&len).unwrap().parse::<usize>().unwrap();
+                let mut data = vec![0u8; len];
+                server_io.read_exact(&mut data).await.unwrap();
+                let serno = std::str::from_utf8(&data[6..16]).unwrap().parse().unwrap();
+                let resp = SigmaResponse::new("0110", serno, 8100).unwrap();
+                server_io.write_all(&resp.encode().unwrap()).await.unwrap();
+            }
+        });
+
+        let exchanges = exchanges(&recording()).unwrap();
+        for timing in [Timing::AsFastAsPossible, Timing::Scaled(10.0)] {
+            let results = replay(&client, exchanges.clone(), timing).await;
+            assert_eq!(results.len(), 3);
+            assert!(!results[0].matches());
+            assert_eq!(results[0].differences[0].field, "T0031");
+            assert!(results[1].matches());
+            assert!(results[2].replayed.is_ok());
+            assert!(!results[2].matches());
+        }
+    }
+}
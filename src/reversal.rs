@@ -0,0 +1,179 @@
+//! Reversals (04xx) of authorization and financial requests.
+
+use crate::util::gen_random_short_auth_serno;
+use crate::{Error, IsoFieldData, SigmaRequest};
+
+/// ISO field which carries original data elements.
+pub const ORIGINAL_DATA_FIELD: u16 = 90;
+
+/// ISO fields copied from the original request into its reversal.
+pub const REVERSAL_ISO_FIELDS: &[u16] = &[
+    2, 3, 4, 5, 6, 7, 11, 12, 13, 14, 18, 22, 23, 32, 33, 35, 37, 41, 42, 43, 49, 50, 51, 102, 103,
+];
+
+/// Reversal MTI for given authorization (x1xx) or financial (x2xx) request MTI:
+/// x400 for the former and x420 for the latter.
+pub fn reversal_mti(mti: &str) -> Result<String, Error> {
+    let b = mti.as_bytes();
+    if b.len() != 4 || !b.iter().all(u8::is_ascii_digit) {
+        return Err(Error::incorrect_field_data(
+            "MTI",
+            "4 digit number (string)",
+        ));
+    }
+    match (b[1], b[2]) {
+        (b'1', b'0') => Ok(format!("{}400", b[0] as char)),
+        (b'2', b'0') => Ok(format!("{}420", b[0] as char)),
+        _ => Err(Error::incorrect_field_data(
+            "MTI",
+            "authorization or financial request",
+        )),
+    }
+}
+
+/// Right-justifies value in the field of given length, zero-padded or cut from the left.
+fn fixed_digits(v: &str, len: usize) -> String {
+    let chars = v.trim().chars().collect::<Vec<_>>();
+    let skip = chars.len().saturating_sub(len);
+    format!(
+        "{:0>width$}",
+        chars[skip..].iter().collect::<String>(),
+        width = len
+    )
+}
+
+fn field_digits(v: Option<&IsoFieldData>, len: usize) -> String {
+    fixed_digits(&v.map(|v| v.to_cow_str_lossy()).unwrap_or_default(), len)
+}
+
+impl SigmaRequest {
+    /// Whether the request has to be reversed if no response arrives in time.
+    pub fn needs_reversal(&self) -> bool {
+        reversal_mti(self.mti()).is_ok()
+    }
+
+    /// Builds reversal of the request.
+    ///
+    /// Reversal gets new authorization serno, is marked SAF and contains all the Sigma tags,
+    /// the ISO fields from [`REVERSAL_ISO_FIELDS`] with their subfields and original data
+    /// elements (i090): original MTI, STAN (i011), transmission date and time (i007),
+    /// acquiring (i032) and forwarding (i033) institution IDs.
+    pub fn to_reversal(&self) -> Result<SigmaRequest, Error> {
+        let mti = reversal_mti(self.mti())?;
+        let mut reversal = Self::new("Y", self.source(), &mti, gen_random_short_auth_serno())?;
+
+        reversal.tags = self.tags.clone();
+        for i in REVERSAL_ISO_FIELDS {
+            if let Some(v) = self.iso_fields.get(i) {
+                reversal.iso_fields.insert(*i, v.clone());
+            }
+        }
+        reversal.iso_subfields = self
+            .iso_subfields
+            .iter()
+            .filter(|((i, _), _)| REVERSAL_ISO_FIELDS.contains(i))
+            .map(|(k, v)| (*k, v.clone()))
+            .collect();
+
+        // The ISO MTI may differ from the Sigma one
+        let original_mti = match self.iso_fields.get(&0) {
+            Some(v) => {
+                let v = v.to_cow_str_lossy().into_owned();
+                if let Ok(iso_mti) = reversal_mti(&v) {
+                    reversal.iso_fields.insert(0, iso_mti.into());
+                }
+                v
+            }
+            None => self.mti().to_string(),
+        };
+
+        let original_data = format!(
+            "{}{}{}{}{}",
+            fixed_digits(&original_mti, 4),
+            field_digits(self.iso_fields.get(&11), 6),
+            field_digits(self.iso_fields.get(&7), 10),
+            field_digits(self.iso_fields.get(&32), 11),
+            field_digits(self.iso_fields.get(&33), 11),
+        );
+        reversal
+            .iso_fields
+            .insert(ORIGINAL_DATA_FIELD, original_data.into());
+
+        Ok(reversal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reversal_mtis() {
+        assert_eq!(reversal_mti("0100").unwrap(), "0400");
+        assert_eq!(reversal_mti("0101").unwrap(), "0400");
+        assert_eq!(reversal_mti("0200").unwrap(), "0420");
+        assert_eq!(reversal_mti("1200").unwrap(), "1420");
+
+        assert!(reversal_mti("0120").is_err());
+        assert!(reversal_mti("0400").is_err());
+        assert!(reversal_mti("0800").is_err());
+        assert!(reversal_mti("01OO").is_err());
+    }
+
+    #[test]
+    fn reversal_of_financial_request() {
+        let mut req = SigmaRequest::new("N", "M", "0200", 6007040979).unwrap();
+        req.tags.insert(0, "2371492071643".into());
+        req.tags.insert(3, "000100000000".into());
+        req.iso_fields.insert(0, "0100".into());
+        req.iso_fields.insert(2, "555544******1111".into());
+        req.iso_fields.insert(4, "000100000000".into());
+        req.iso_fields.insert(7, "0629151748".into());
+        req.iso_fields.insert(11, "100250".into());
+        req.iso_fields.insert(32, "010455".into());
+        req.iso_fields.insert(39, "00".into());
+        req.iso_fields.insert(48, "USRDT|2595100250".into());
+        req.iso_subfields.insert((48, 1), "USRDT".into());
+        req.iso_subfields.insert((3, 1), "50".into());
+
+        let reversal = req.to_reversal().unwrap();
+        assert_eq!(reversal.mti(), "0420");
+        assert_eq!(reversal.saf(), "Y");
+        assert_eq!(reversal.source(), "M");
+        assert_ne!(reversal.auth_serno, req.auth_serno);
+        assert!(reversal.auth_serno <= 9999999999);
+        assert_eq!(reversal.tags, req.tags);
+
+        assert_eq!(reversal.iso_fields.get(&0).unwrap(), "0400");
+        assert_eq!(reversal.iso_fields.get(&2).unwrap(), "555544******1111");
+        assert_eq!(reversal.iso_fields.get(&4).unwrap(), "000100000000");
+        assert!(!reversal.iso_fields.contains_key(&39));
+        assert!(!reversal.iso_fields.contains_key(&48));
+        assert_eq!(
+            reversal.iso_fields.get(&90).unwrap(),
+            "010010025006291517480000001045500000000000"
+        );
+        assert_eq!(reversal.iso_subfields.len(), 1);
+        assert_eq!(reversal.iso_subfields.get(&(3, 1)).unwrap(), "50");
+    }
+
+    #[test]
+    fn reversal_without_iso_mti() {
+        let req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+
+        let reversal = req.to_reversal().unwrap();
+        assert_eq!(reversal.mti(), "0400");
+        assert!(!reversal.iso_fields.contains_key(&0));
+        assert_eq!(
+            reversal.iso_fields.get(&90).unwrap(),
+            &format!("0100{}", "0".repeat(38))
+        );
+    }
+
+    #[test]
+    fn no_reversal_of_advice() {
+        let req = SigmaRequest::new("Y", "M", "0120", 1).unwrap();
+        assert!(!req.needs_reversal());
+        assert!(req.to_reversal().is_err());
+    }
+}
@@ -0,0 +1,541 @@
+//! Store-and-forward queue for requests marked SAF.
+//!
+//! [`SafQueue`] keeps encoded requests in an append-only journal file, so they survive
+//! restarts, and delivers them with any [`RequestSender`] until a response arrives.
+//!
+//! Journal consists of records of two kinds:
+//! * `A` followed by an encoded request (self-delimited by its length header) — request added;
+//! * `D` followed by 20-digit authorization serno — request delivered or purged.
+//!
+//! A partially written trailing record (e.g. after a crash) is dropped, any other corrupt record
+//! fails opening and leaves the journal untouched. The journal is compacted once it contains
+//! more removed requests than queued ones.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::{Error, SigmaRequest, SigmaResponse};
+
+const ADD_RECORD: u8 = b'A';
+const DELETE_RECORD: u8 = b'D';
+const SERNO_LENGTH: usize = 20;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SafError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    ExtfgSigma(#[from] Error),
+    #[error("Request with auth serno {0} is not marked SAF")]
+    NotSaf(u64),
+}
+
+/// Anything able to deliver a request and return the response to it.
+pub trait RequestSender {
+    type Error: std::fmt::Display;
+
+    fn send(
+        &self,
+        req: SigmaRequest,
+    ) -> impl Future<Output = Result<SigmaResponse, Self::Error>> + Send;
+}
+
+#[cfg(feature = "client")]
+mod senders {
+    use std::future::Future;
+
+    use super::RequestSender;
+    use crate::client::pool::SigmaPool;
+    use crate::client::reconnect::ReconnectingClient;
+    use crate::client::{ClientError, SigmaClient};
+    use crate::{SigmaRequest, SigmaResponse};
+
+    impl RequestSender for SigmaClient {
+        type Error = ClientError;
+
+        fn send(
+            &self,
+            req: SigmaRequest,
+        ) -> impl Future<Output = Result<SigmaResponse, Self::Error>> + Send {
+            SigmaClient::send(self, req)
+        }
+    }
+
+    impl RequestSender for ReconnectingClient {
+        type Error = ClientError;
+
+        fn send(
+            &self,
+            req: SigmaRequest,
+        ) -> impl Future<Output = Result<SigmaResponse, Self::Error>> + Send {
+            ReconnectingClient::send(self, req)
+        }
+    }
+
+    impl RequestSender for SigmaPool {
+        type Error = ClientError;
+
+        fn send(
+            &self,
+            req: SigmaRequest,
+        ) -> impl Future<Output = Result<SigmaResponse, Self::Error>> + Send {
+            SigmaPool::send(self, req)
+        }
+    }
+}
+
+/// Queued request with its delivery statistics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafEntry {
+    pub request: SigmaRequest,
+    /// Delivery attempts made since the queue was opened.
+    pub attempts: u32,
+    /// Error of the last failed delivery attempt.
+    pub last_error: Option<String>,
+}
+
+/// Outcome of a single [`SafQueue::deliver_pending`] pass.
+#[derive(Debug, Clone, Default)]
+pub struct DeliveryReport {
+    pub delivered: Vec<(u64, SigmaResponse)>,
+    pub failed: Vec<u64>,
+}
+
+struct Inner {
+    file: File,
+    entries: BTreeMap<u64, SafEntry>,
+    removed: usize,
+}
+
+/// Durable queue of requests marked SAF, deduplicated by authorization serno.
+pub struct SafQueue {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+impl std::fmt::Debug for SafQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SafQueue")
+            .field("path", &self.path)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl SafQueue {
+    /// Opens queue journal at given path, creating it if necessary.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SafError> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let length = data.len();
+        let (entries, removed, complete) = replay_journal(Bytes::from(data))?;
+        if complete < length {
+            // Drop torn trailing record, otherwise appended records would be swallowed by it
+            file.set_len(complete as u64)?;
+            file.sync_data()?;
+        }
+
+        let queue = Self {
+            path,
+            inner: Mutex::new(Inner {
+                file,
+                entries,
+                removed,
+            }),
+        };
+        queue.compact_if_needed()?;
+        Ok(queue)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Adds request to the queue. Returns `false` if request with the same authorization serno
+    /// is already queued.
+    pub fn enqueue(&self, req: SigmaRequest) -> Result<bool, SafError> {
+        if req.saf() != "Y" {
+            return Err(SafError::NotSaf(req.auth_serno));
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.contains_key(&req.auth_serno) {
+            return Ok(false);
+        }
+
+        let encoded = req.encode()?;
+        let mut record = BytesMut::with_capacity(encoded.len() + 1);
+        record.extend_from_slice(&[ADD_RECORD]);
+        record.extend_from_slice(&encoded);
+        append(&mut inner.file, &record)?;
+
+        inner.entries.insert(
+            req.auth_serno,
+            SafEntry {
+                request: req,
+                attempts: 0,
+                last_error: None,
+            },
+        );
+        Ok(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, auth_serno: u64) -> bool {
+        self.inner.lock().unwrap().entries.contains_key(&auth_serno)
+    }
+
+    pub fn get(&self, auth_serno: u64) -> Option<SafEntry> {
+        self.inner.lock().unwrap().entries.get(&auth_serno).cloned()
+    }
+
+    /// Snapshot of all queued entries ordered by authorization serno.
+    pub fn entries(&self) -> Vec<SafEntry> {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Removes request from the queue without delivering it.
+    pub fn purge(&self, auth_serno: u64) -> Result<bool, SafError> {
+        let removed = self.remove(auth_serno)?;
+        self.compact_if_needed()?;
+        Ok(removed)
+    }
+
+    /// Removes all requests from the queue without delivering them.
+    pub fn purge_all(&self) -> Result<usize, SafError> {
+        let mut inner = self.inner.lock().unwrap();
+        let count = inner.entries.len();
+        inner.entries.clear();
+        rewrite_journal(&self.path, &mut inner)?;
+        Ok(count)
+    }
+
+    /// Tries to deliver every queued request once. Delivered requests are removed from the queue.
+    pub async fn deliver_pending<S: RequestSender>(
+        &self,
+        sender: &S,
+    ) -> Result<DeliveryReport, SafError> {
+        let mut report = DeliveryReport::default();
+
+        for entry in self.entries() {
+            let auth_serno = entry.request.auth_serno;
+            let result = sender.send(entry.request).await;
+
+            match result {
+                Ok(resp) => {
+                    self.remove(auth_serno)?;
+                    report.delivered.push((auth_serno, resp));
+                }
+                Err(err) => {
+                    let mut inner = self.inner.lock().unwrap();
+                    if let Some(entry) = inner.entries.get_mut(&auth_serno) {
+                        entry.attempts += 1;
+                        entry.last_error = Some(err.to_string());
+                    }
+                    report.failed.push(auth_serno);
+                }
+            }
+        }
+
+        self.compact_if_needed()?;
+        Ok(report)
+    }
+
+    /// Keeps delivering queued requests, pausing for given interval between passes.
+    /// Returns only on a journal error.
+    pub async fn run<S: RequestSender>(
+        &self,
+        sender: &S,
+        retry_interval: Duration,
+    ) -> Result<(), SafError> {
+        loop {
+            self.deliver_pending(sender).await?;
+            tokio::time::sleep(retry_interval).await;
+        }
+    }
+
+    fn remove(&self, auth_serno: u64) -> Result<bool, SafError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.remove(&auth_serno).is_none() {
+            return Ok(false);
+        }
+
+        let mut record = Vec::with_capacity(SERNO_LENGTH + 1);
+        record.push(DELETE_RECORD);
+        record.extend_from_slice(format!("{:020}", auth_serno).as_bytes());
+        append(&mut inner.file, &record)?;
+        inner.removed += 1;
+        Ok(true)
+    }
+
+    fn compact_if_needed(&self) -> Result<(), SafError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.removed > inner.entries.len() {
+            rewrite_journal(&self.path, &mut inner)?;
+        }
+        Ok(())
+    }
+}
+
+fn append(file: &mut File, record: &[u8]) -> Result<(), SafError> {
+    file.write_all(record)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+fn rewrite_journal(path: &Path, inner: &mut Inner) -> Result<(), SafError> {
+    let tmp_path = path.with_extension("tmp");
+    let mut tmp = File::create(&tmp_path)?;
+    for entry in inner.entries.values() {
+        tmp.write_all(&[ADD_RECORD])?;
+        tmp.write_all(&entry.request.encode()?)?;
+    }
+    tmp.sync_all()?;
+    drop(tmp);
+    fs::rename(&tmp_path, path)?;
+
+    inner.file = OpenOptions::new().read(true).append(true).open(path)?;
+    inner.removed = 0;
+    Ok(())
+}
+
+/// Replays journal records, returning entries, amount of removals and length of the complete
+/// records; a partially written trailing record is ignored, corrupt records are errors.
+fn replay_journal(mut data: Bytes) -> Result<(BTreeMap<u64, SafEntry>, usize, usize), SafError> {
+    let length = data.len();
+    let mut entries = BTreeMap::new();
+    let mut removed = 0;
+
+    while !data.is_empty() {
+        match data[0] {
+            ADD_RECORD => {
+                if data.len() < 6 {
+                    break;
+                }
+                // Complete length of a record which is not the last one can only be corrupt
+                let msg_len = parse_ascii_bytes_lossy!(
+                    &data[1..6],
+                    usize,
+                    Error::incorrect_field_data("SAF journal message length", "valid integer")
+                )?;
+                if data.len() < msg_len + 6 {
+                    break;
+                }
+                let _ = data.split_to(1);
+                let req = SigmaRequest::decode(data.split_to(msg_len + 5))?;
+                entries.insert(
+                    req.auth_serno,
+                    SafEntry {
+                        request: req,
+                        attempts: 0,
+                        last_error: None,
+                    },
+                );
+            }
+            DELETE_RECORD => {
+                if data.len() < SERNO_LENGTH + 1 {
+                    break;
+                }
+                let _ = data.split_to(1);
+                let serno = parse_ascii_bytes_lossy!(
+                    &data.split_to(SERNO_LENGTH),
+                    u64,
+                    Error::incorrect_field_data("SAF journal serno", "valid integer")
+                )?;
+                entries.remove(&serno);
+                removed += 1;
+            }
+            _ => {
+                return Err(Error::IncorrectData("Corrupt SAF journal record".into()).into());
+            }
+        }
+    }
+
+    Ok((entries, removed, length - data.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    struct FlakySender {
+        failures_left: AtomicUsize,
+    }
+
+    impl RequestSender for FlakySender {
+        type Error = String;
+
+        fn send(
+            &self,
+            req: SigmaRequest,
+        ) -> impl Future<Output = Result<SigmaResponse, Self::Error>> + Send {
+            let fail = self
+                .failures_left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| v.checked_sub(1))
+                .is_ok();
+            async move {
+                match fail {
+                    true => Err("host unreachable".to_string()),
+                    false => Ok(SigmaResponse::new("0130", req.auth_serno, 8100).unwrap()),
+                }
+            }
+        }
+    }
+
+    fn advice(serno: u64) -> SigmaRequest {
+        let mut req = SigmaRequest::new("Y", "M", "0120", serno).unwrap();
+        req.tags.insert(0, "2371492071643".into());
+        req.iso_fields.insert(2, "555544******1111".into());
+        req
+    }
+
+    #[test]
+    fn enqueue_deduplicate_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("saf.journal");
+
+        let queue = SafQueue::open(&path).unwrap();
+        assert!(queue.enqueue(advice(1)).unwrap());
+        assert!(queue.enqueue(advice(2)).unwrap());
+        assert!(!queue.enqueue(advice(1)).unwrap());
+        assert!(queue.purge(2).unwrap());
+        assert!(!queue.purge(2).unwrap());
+        drop(queue);
+
+        let queue = SafQueue::open(&path).unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.get(1).unwrap().request, advice(1));
+    }
+
+    #[test]
+    fn reject_not_saf() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = SafQueue::open(dir.path().join("saf.journal")).unwrap();
+
+        let req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        assert!(matches!(queue.enqueue(req), Err(SafError::NotSaf(1))));
+    }
+
+    #[test]
+    fn ignore_partial_trailing_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("saf.journal");
+
+        let queue = SafQueue::open(&path).unwrap();
+        queue.enqueue(advice(1)).unwrap();
+        drop(queue);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"A00050YM0120").unwrap();
+        drop(file);
+
+        let queue = SafQueue::open(&path).unwrap();
+        assert_eq!(queue.len(), 1);
+        queue.enqueue(advice(2)).unwrap();
+        drop(queue);
+
+        let queue = SafQueue::open(&path).unwrap();
+        assert_eq!(queue.len(), 2);
+        assert!(queue.contains(1) && queue.contains(2));
+    }
+
+    #[test]
+    fn corrupt_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("saf.journal");
+        fs::write(&path, b"X").unwrap();
+
+        assert!(SafQueue::open(&path).is_err());
+
+        // Corrupt record followed by valid ones is not truncated
+        let record = |serno| {
+            let mut v = vec![ADD_RECORD];
+            v.extend_from_slice(&advice(serno).encode().unwrap());
+            v
+        };
+        let mut data = record(1);
+        data.extend_from_slice(b"A0x050YM0120");
+        data.extend_from_slice(&record(2));
+        data.extend_from_slice(&record(3));
+        fs::write(&path, &data).unwrap();
+
+        assert!(matches!(
+            SafQueue::open(&path),
+            Err(SafError::ExtfgSigma(Error::IncorrectFieldData { .. }))
+        ));
+        assert_eq!(fs::read(&path).unwrap(), data);
+    }
+
+    #[test]
+    fn compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("saf.journal");
+
+        let queue = SafQueue::open(&path).unwrap();
+        for serno in 0..4 {
+            queue.enqueue(advice(serno)).unwrap();
+        }
+        for serno in 0..3 {
+            queue.purge(serno).unwrap();
+        }
+        let compacted = fs::read(&path).unwrap();
+        let mut expected = vec![ADD_RECORD];
+        expected.extend_from_slice(&advice(3).encode().unwrap());
+        assert_eq!(compacted, expected);
+
+        assert_eq!(queue.purge_all().unwrap(), 1);
+        assert!(fs::read(&path).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deliver_with_retries() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = SafQueue::open(dir.path().join("saf.journal")).unwrap();
+        queue.enqueue(advice(1)).unwrap();
+        queue.enqueue(advice(2)).unwrap();
+
+        let sender = FlakySender {
+            failures_left: AtomicUsize::new(1),
+        };
+
+        let report = queue.deliver_pending(&sender).await.unwrap();
+        assert_eq!(report.failed, vec![1]);
+        assert_eq!(report.delivered.len(), 1);
+        let entry = queue.get(1).unwrap();
+        assert_eq!(entry.attempts, 1);
+        assert_eq!(entry.last_error.as_deref(), Some("host unreachable"));
+
+        let report = queue.deliver_pending(&sender).await.unwrap();
+        assert_eq!(report.delivered[0].0, 1);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,460 @@
+//! Message specifications loaded at runtime and validation of messages against them.
+//!
+//! A specification lists, per MTI, the tags and ISO fields a message may contain,
+//! which of them are required and their format and length rules:
+//!
+//! ```toml
+//! [messages.0100]
+//! allow_unknown = false
+//!
+//! [messages.0100.tags.0]
+//! required = true
+//! format = "numeric"
+//! max_length = 19
+//!
+//! [messages.0100.iso_fields.2]
+//! format = "numeric"
+//! min_length = 12
+//! max_length = 19
+//! ```
+//!
+//! Responses are validated by their tags: reason (T0031), fees (T0032), XRI (T0033),
+//! additional data (T0048) and supplementary data (T0050).
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{SigmaRequest, SigmaResponse};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpecError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse spec: {0}")]
+    Parse(String),
+    #[error("Unsupported spec file extension '{0}', should be toml, yaml, yml or json")]
+    UnsupportedFormat(String),
+    #[error("Incorrect MTI '{0}', should be 4 digit number")]
+    IncorrectMti(String),
+}
+
+/// Format of element data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Any,
+    #[serde(alias = "n")]
+    Numeric,
+    #[serde(alias = "a")]
+    Alpha,
+    #[serde(alias = "an")]
+    Alphanumeric,
+    Hex,
+    /// Printable ASCII characters.
+    #[serde(alias = "ans")]
+    Printable,
+}
+
+impl Format {
+    pub fn matches(&self, v: &str) -> bool {
+        let check: fn(&char) -> bool = match self {
+            Self::Any => return true,
+            Self::Numeric => char::is_ascii_digit,
+            Self::Alpha => char::is_ascii_alphabetic,
+            Self::Alphanumeric => char::is_ascii_alphanumeric,
+            Self::Hex => char::is_ascii_hexdigit,
+            Self::Printable => |c| matches!(c, ' '..='~'),
+        };
+        v.chars().all(|c| check(&c))
+    }
+}
+
+/// Rules of a single tag or ISO field.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ElementSpec {
+    pub required: bool,
+    pub format: Format,
+    /// Exact length.
+    pub length: Option<usize>,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    /// Allowed values.
+    pub values: Option<Vec<String>>,
+}
+
+fn u16_keys<'de, D, V>(deserializer: D) -> Result<BTreeMap<u16, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    // TOML keys are always strings
+    BTreeMap::<String, V>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(k, v)| match k.parse::<u16>() {
+            Ok(k) => Ok((k, v)),
+            Err(_) => Err(serde::de::Error::custom(format!(
+                "incorrect tag or field number '{}'",
+                k
+            ))),
+        })
+        .collect()
+}
+
+/// Rules of messages with a single MTI.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MtiSpec {
+    /// Whether tags and fields not listed in the spec are allowed.
+    pub allow_unknown: bool,
+    #[serde(deserialize_with = "u16_keys")]
+    pub tags: BTreeMap<u16, ElementSpec>,
+    #[serde(deserialize_with = "u16_keys")]
+    pub iso_fields: BTreeMap<u16, ElementSpec>,
+}
+
+impl Default for MtiSpec {
+    fn default() -> Self {
+        Self {
+            allow_unknown: true,
+            tags: BTreeMap::new(),
+            iso_fields: BTreeMap::new(),
+        }
+    }
+}
+
+/// Tag or ISO field of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Element {
+    Tag(u16),
+    IsoField(u16),
+}
+
+impl fmt::Display for Element {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tag(i) => write!(f, "T{:04}", i),
+            Self::IsoField(i) => write!(f, "i{:03}", i),
+        }
+    }
+}
+
+/// Single violation of the spec.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+pub enum Violation {
+    #[error("MTI {0} is not described by the spec")]
+    UnknownMti(String),
+    #[error("Missing required {0}")]
+    Missing(Element),
+    #[error("Unexpected {0}")]
+    Unexpected(Element),
+    #[error("{element} should be of format {format:?}")]
+    Format { element: Element, format: Format },
+    #[error("{element} has length {length}, which is out of the spec")]
+    Length { element: Element, length: usize },
+    #[error("{element} has value which is not allowed")]
+    Value { element: Element },
+}
+
+/// Message specification of a Sigma installation.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessageSpec {
+    /// Rules per MTI.
+    pub messages: BTreeMap<String, MtiSpec>,
+}
+
+impl MessageSpec {
+    fn checked(self) -> Result<Self, SpecError> {
+        for mti in self.messages.keys() {
+            if mti.len() != 4 || !mti.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(SpecError::IncorrectMti(mti.clone()));
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn from_toml_str(s: &str) -> Result<Self, SpecError> {
+        toml::from_str::<Self>(s)
+            .map_err(|e| SpecError::Parse(e.to_string()))?
+            .checked()
+    }
+
+    pub fn from_yaml_str(s: &str) -> Result<Self, SpecError> {
+        serde_yaml::from_str::<Self>(s)
+            .map_err(|e| SpecError::Parse(e.to_string()))?
+            .checked()
+    }
+
+    pub fn from_json_str(s: &str) -> Result<Self, SpecError> {
+        serde_json::from_str::<Self>(s)
+            .map_err(|e| SpecError::Parse(e.to_string()))?
+            .checked()
+    }
+
+    /// Loads spec from a file, the format is chosen by its extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, SpecError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .map(|v| v.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let parse = match extension.as_str() {
+            "toml" => Self::from_toml_str,
+            "yaml" | "yml" => Self::from_yaml_str,
+            "json" => Self::from_json_str,
+            _ => return Err(SpecError::UnsupportedFormat(extension)),
+        };
+        parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn validate_request(&self, req: &SigmaRequest) -> Result<(), Vec<Violation>> {
+        let elements = req
+            .tags
+            .iter()
+            .map(|(k, v)| (Element::Tag(*k), v.clone()))
+            .chain(
+                req.iso_fields
+                    .iter()
+                    .map(|(k, v)| (Element::IsoField(*k), v.to_cow_str_lossy().into_owned())),
+            )
+            .collect::<Vec<_>>();
+        self.validate(req.mti(), &elements)
+    }
+
+    pub fn validate_response(&self, resp: &SigmaResponse) -> Result<(), Vec<Violation>> {
+        let mut elements = vec![(Element::Tag(31), resp.reason.to_string())];
+        for fee in resp.fees.iter() {
+            let fee = fee
+                .encode()
+                .map(|v| String::from_utf8_lossy(&v).into_owned())
+                .unwrap_or_default();
+            elements.push((Element::Tag(32), fee));
+        }
+        let optional = [(33, &resp.xri), (48, &resp.adata), (50, &resp.supdata)];
+        for (tag, v) in optional.iter() {
+            if let Some(v) = v {
+                elements.push((Element::Tag(*tag), v.clone()));
+            }
+        }
+        self.validate(resp.mti(), &elements)
+    }
+
+    fn validate(&self, mti: &str, elements: &[(Element, String)]) -> Result<(), Vec<Violation>> {
+        let spec = self
+            .messages
+            .get(mti)
+            .ok_or_else(|| vec![Violation::UnknownMti(mti.into())])?;
+
+        let specs = || {
+            let tags = spec.tags.iter().map(|(k, v)| (Element::Tag(*k), v));
+            let fields = spec
+                .iso_fields
+                .iter()
+                .map(|(k, v)| (Element::IsoField(*k), v));
+            tags.chain(fields)
+        };
+
+        let mut violations = Vec::new();
+        for (element, rules) in specs() {
+            if rules.required && !elements.iter().any(|(e, _)| *e == element) {
+                violations.push(Violation::Missing(element));
+            }
+        }
+        for (element, v) in elements.iter() {
+            let element = *element;
+            let rules = match specs().find(|(e, _)| *e == element) {
+                Some((_, rules)) => rules,
+                None if spec.allow_unknown => continue,
+                None => {
+                    violations.push(Violation::Unexpected(element));
+                    continue;
+                }
+            };
+
+            if !rules.format.matches(v) {
+                let format = rules.format;
+                violations.push(Violation::Format { element, format });
+            }
+            let length = v.chars().count();
+            if rules.length.is_some_and(|l| length != l)
+                || rules.min_length.is_some_and(|l| length < l)
+                || rules.max_length.is_some_and(|l| length > l)
+            {
+                violations.push(Violation::Length { element, length });
+            }
+            if rules
+                .values
+                .as_ref()
+                .is_some_and(|values| !values.contains(v))
+            {
+                violations.push(Violation::Value { element });
+            }
+        }
+
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(violations),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const TOML: &str = r#"
+[messages.0100]
+allow_unknown = false
+
+[messages.0100.tags.0]
+required = true
+format = "n"
+max_length = 19
+
+[messages.0100.tags.18]
+values = ["Y", "N"]
+
+[messages.0100.iso_fields.2]
+required = true
+format = "numeric"
+min_length = 12
+max_length = 19
+
+[messages.0100.iso_fields.4]
+length = 12
+
+[messages.0110.tags.31]
+required = true
+format = "numeric"
+length = 4
+"#;
+
+    const YAML: &str = r#"
+messages:
+  "0110":
+    tags:
+      31: { required: true, format: numeric, length: 4 }
+      48: { format: ans }
+"#;
+
+    fn request() -> SigmaRequest {
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        req.tags.insert(0, "2371492071643".into());
+        req.tags.insert(18, "Y".into());
+        req.iso_fields.insert(2, "4111111111111111".into());
+        req.iso_fields.insert(4, "000100000000".into());
+        req
+    }
+
+    #[test]
+    fn valid_request() {
+        let spec = MessageSpec::from_toml_str(TOML).unwrap();
+        assert_eq!(spec.messages.len(), 2);
+        assert_eq!(spec.validate_request(&request()), Ok(()));
+    }
+
+    #[test]
+    fn invalid_request() {
+        let spec = MessageSpec::from_toml_str(TOML).unwrap();
+
+        let mut req = request();
+        req.tags.remove(&0);
+        req.tags.insert(18, "X".into());
+        req.tags.insert(99, "?".into());
+        req.iso_fields.insert(2, "41111111111A".into());
+        req.iso_fields.insert(4, "100".into());
+
+        assert_eq!(
+            spec.validate_request(&req),
+            Err(vec![
+                Violation::Missing(Element::Tag(0)),
+                Violation::Value {
+                    element: Element::Tag(18)
+                },
+                Violation::Unexpected(Element::Tag(99)),
+                Violation::Format {
+                    element: Element::IsoField(2),
+                    format: Format::Numeric
+                },
+                Violation::Length {
+                    element: Element::IsoField(4),
+                    length: 3
+                },
+            ])
+        );
+
+        req.set_mti("0200".into()).unwrap();
+        assert_eq!(
+            spec.validate_request(&req),
+            Err(vec![Violation::UnknownMti("0200".into())])
+        );
+    }
+
+    #[test]
+    fn response() {
+        let spec = MessageSpec::from_yaml_str(YAML).unwrap();
+
+        let mut resp = SigmaResponse::new("0110", 1, 8100).unwrap();
+        resp.adata = Some("USRDT|2595100250".into());
+        assert_eq!(spec.validate_response(&resp), Ok(()));
+
+        resp.reason = 100;
+        resp.adata = Some("\x01".into());
+        assert_eq!(
+            spec.validate_response(&resp),
+            Err(vec![
+                Violation::Length {
+                    element: Element::Tag(31),
+                    length: 3
+                },
+                Violation::Format {
+                    element: Element::Tag(48),
+                    format: Format::Printable
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn load() {
+        let json = r#"{"messages": {"0100": {"iso_fields": {"2": {"required": true}}}}}"#;
+        let spec = MessageSpec::from_json_str(json).unwrap();
+        assert!(spec.messages["0100"].iso_fields[&2].required);
+        assert!(spec.messages["0100"].allow_unknown);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spec.yml");
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(YAML.as_bytes())
+            .unwrap();
+        assert_eq!(
+            MessageSpec::from_path(&path).unwrap(),
+            MessageSpec::from_yaml_str(YAML).unwrap()
+        );
+
+        assert!(matches!(
+            MessageSpec::from_path(dir.path().join("spec.ini")),
+            Err(SpecError::UnsupportedFormat(_))
+        ));
+        assert!(matches!(
+            MessageSpec::from_toml_str("[messages.100]"),
+            Err(SpecError::IncorrectMti(_))
+        ));
+        assert!(matches!(
+            MessageSpec::from_toml_str("[messages.0100.tags.T1]"),
+            Err(SpecError::Parse(_))
+        ));
+        assert!(matches!(
+            MessageSpec::from_json_str(r#"{"messages": {"0100": {"tag": {}}}}"#),
+            Err(SpecError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,471 @@
+//! Stand-in authorization for the time Sigma host is unreachable.
+//!
+//! [`StandIn`] approves or declines requests locally by evaluating configured [`Rule`]s and
+//! records every decision, so it can later be replayed to Sigma as an advice.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::pan::PAN_FIELD;
+use crate::{Error, SigmaRequest, SigmaResponse};
+
+/// Sigma tag of the advice which carries the stand-in reason code.
+pub const STANDIN_REASON_TAG: u16 = 31;
+
+/// Single stand-in rule. Rules are evaluated in order, the first violated one declines
+/// the request with its own reason or the default one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// Maximum amount of a single transaction in given currency.
+    AmountLimit {
+        currency: u16,
+        max: u64,
+        reason: Option<u32>,
+    },
+    /// Only listed merchant category codes (i018) are allowed.
+    MccAllow {
+        mccs: BTreeSet<String>,
+        reason: Option<u32>,
+    },
+    /// Listed merchant category codes (i018) are declined.
+    MccDeny {
+        mccs: BTreeSet<String>,
+        reason: Option<u32>,
+    },
+    /// Limits of approved transactions per card (by masked PAN) within sliding window.
+    Velocity {
+        window: Duration,
+        max_count: usize,
+        max_amount: Option<u64>,
+        reason: Option<u32>,
+    },
+}
+
+impl Rule {
+    fn reason(&self) -> Option<u32> {
+        match self {
+            Self::AmountLimit { reason, .. }
+            | Self::MccAllow { reason, .. }
+            | Self::MccDeny { reason, .. }
+            | Self::Velocity { reason, .. } => *reason,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StandInConfig {
+    pub rules: Vec<Rule>,
+    /// Sigma tag with transaction amount.
+    pub amount_tag: u16,
+    /// Sigma tag with transaction currency code.
+    pub currency_tag: u16,
+    /// Reason code of approved requests.
+    pub approve_reason: u32,
+    /// Reason code of declined requests for rules without their own one.
+    pub default_reason: u32,
+}
+
+impl Default for StandInConfig {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            amount_tag: 3,
+            currency_tag: 2,
+            approve_reason: 8100,
+            default_reason: 8495,
+        }
+    }
+}
+
+/// Recorded stand-in decision.
+#[derive(Debug, Clone)]
+pub struct StandInRecord {
+    pub request: SigmaRequest,
+    pub response: SigmaResponse,
+    pub approved: bool,
+    /// Index of the rule which declined the request.
+    pub rule: Option<usize>,
+    pub decided_at: SystemTime,
+}
+
+impl StandInRecord {
+    /// Builds advice (x120/x220) informing Sigma about the decision, marked SAF.
+    /// The reason code is put into [`STANDIN_REASON_TAG`].
+    pub fn to_advice(&self) -> Result<SigmaRequest, Error> {
+        let mti = self.request.mti().as_bytes();
+        let mut advice = self.request.clone();
+        advice.set_mti(format!("{}{}20", mti[0] as char, mti[1] as char))?;
+        advice.set_saf("Y".into())?;
+        advice
+            .tags
+            .insert(STANDIN_REASON_TAG, format!("{}", self.response.reason));
+        Ok(advice)
+    }
+}
+
+/// Velocity counters key: PAN masked except the first 6 and the last 4 digits.
+fn velocity_key(req: &SigmaRequest) -> Option<String> {
+    let pan = req
+        .iso_fields
+        .get(&PAN_FIELD)?
+        .to_cow_str_lossy()
+        .into_owned();
+    if pan.len() < 10 || !pan.is_ascii() {
+        return Some(pan);
+    }
+    Some(format!(
+        "{}{}{}",
+        &pan[..6],
+        "*".repeat(pan.len() - 10),
+        &pan[pan.len() - 4..]
+    ))
+}
+
+/// Response MTI for given request MTI: x100 → x110, x120 → x130 and so on.
+fn response_mti(mti: &str) -> Result<String, Error> {
+    let b = mti.as_bytes();
+    match b.get(2) {
+        Some(function @ b'0'..=b'8') => Ok(format!(
+            "{}{}{}{}",
+            b[0] as char,
+            b[1] as char,
+            (function + 1) as char,
+            b[3] as char
+        )),
+        _ => Err(Error::incorrect_field_data("MTI", "request MTI")),
+    }
+}
+
+/// Rule-driven local authorization engine.
+#[derive(Debug)]
+pub struct StandIn {
+    config: StandInConfig,
+    velocity: Mutex<HashMap<String, VecDeque<(Instant, u64)>>>,
+    records: Mutex<Vec<StandInRecord>>,
+}
+
+impl StandIn {
+    pub fn new(config: StandInConfig) -> Self {
+        Self {
+            config,
+            velocity: Mutex::new(HashMap::new()),
+            records: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn config(&self) -> &StandInConfig {
+        &self.config
+    }
+
+    /// Makes and records a decision on the request.
+    pub fn authorize(&self, req: &SigmaRequest) -> Result<SigmaResponse, Error> {
+        self.authorize_at(req, Instant::now())
+    }
+
+    /// Same as [`StandIn::authorize`] with explicit current time for velocity checks.
+    pub fn authorize_at(&self, req: &SigmaRequest, now: Instant) -> Result<SigmaResponse, Error> {
+        let mti = response_mti(req.mti())?;
+        let amount = self.amount(req)?;
+        let key = velocity_key(req);
+
+        let mut velocity = self.velocity.lock().unwrap();
+        let history = key.as_ref().map(|key| {
+            let history = velocity.entry(key.clone()).or_default();
+            let max_window = self
+                .config
+                .rules
+                .iter()
+                .filter_map(|rule| match rule {
+                    Rule::Velocity { window, .. } => Some(*window),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or_default();
+            while matches!(history.front(), Some((at, _)) if now.duration_since(*at) > max_window) {
+                history.pop_front();
+            }
+            history
+        });
+
+        let mut declined_by = None;
+        for (i, rule) in self.config.rules.iter().enumerate() {
+            let violated = match rule {
+                Rule::AmountLimit { currency, max, .. } => {
+                    self.currency(req)? == *currency && amount > *max
+                }
+                Rule::MccAllow { mccs, .. } => !mccs.contains(&mcc(req)),
+                Rule::MccDeny { mccs, .. } => mccs.contains(&mcc(req)),
+                Rule::Velocity {
+                    window,
+                    max_count,
+                    max_amount,
+                    ..
+                } => match history.as_ref() {
+                    Some(history) => {
+                        let recent = history
+                            .iter()
+                            .filter(|(at, _)| now.duration_since(*at) <= *window);
+                        let (count, sum) = recent.fold((0usize, 0u64), |(c, s), (_, a)| {
+                            (c + 1, s.saturating_add(*a))
+                        });
+                        count + 1 > *max_count
+                            || max_amount.is_some_and(|max| sum.saturating_add(amount) > max)
+                    }
+                    None => false,
+                },
+            };
+            if violated {
+                declined_by = Some(i);
+                break;
+            }
+        }
+
+        let reason = match declined_by {
+            Some(i) => self.config.rules[i]
+                .reason()
+                .unwrap_or(self.config.default_reason),
+            None => {
+                if let Some(history) = history {
+                    history.push_back((now, amount));
+                }
+                self.config.approve_reason
+            }
+        };
+        drop(velocity);
+
+        let response = SigmaResponse::new(&mti, req.auth_serno, reason)?;
+        self.records.lock().unwrap().push(StandInRecord {
+            request: req.clone(),
+            response: response.clone(),
+            approved: declined_by.is_none(),
+            rule: declined_by,
+            decided_at: SystemTime::now(),
+        });
+        Ok(response)
+    }
+
+    /// Snapshot of recorded decisions.
+    pub fn records(&self) -> Vec<StandInRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Takes recorded decisions out, e.g. to replay them to Sigma.
+    pub fn take_records(&self) -> Vec<StandInRecord> {
+        std::mem::take(&mut *self.records.lock().unwrap())
+    }
+
+    /// Takes recorded decisions out as advices.
+    pub fn take_advices(&self) -> Result<Vec<SigmaRequest>, Error> {
+        self.take_records()
+            .iter()
+            .map(StandInRecord::to_advice)
+            .collect()
+    }
+
+    fn amount(&self, req: &SigmaRequest) -> Result<u64, Error> {
+        match req.tags.get(&self.config.amount_tag) {
+            Some(v) => v
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| Error::incorrect_field_data("amount", "valid integer")),
+            None => Ok(0),
+        }
+    }
+
+    fn currency(&self, req: &SigmaRequest) -> Result<u16, Error> {
+        let tag = self.config.currency_tag;
+        req.tags
+            .get(&tag)
+            .ok_or_else(|| Error::MissingField(format!("T{:04}", tag)))?
+            .trim()
+            .parse::<u16>()
+            .map_err(|_| Error::incorrect_field_data("currency", "valid integer"))
+    }
+}
+
+fn mcc(req: &SigmaRequest) -> String {
+    req.iso_fields
+        .get(&18)
+        .map(|v| v.to_cow_str_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(serno: u64, amount: u64, mcc: &str) -> SigmaRequest {
+        let mut req = SigmaRequest::new("N", "M", "0100", serno).unwrap();
+        req.tags.insert(2, "643".into());
+        req.tags.insert(3, format!("{:012}", amount));
+        req.iso_fields.insert(2, "5555441234561111".into());
+        req.iso_fields.insert(18, mcc.into());
+        req
+    }
+
+    #[test]
+    fn response_mtis() {
+        assert_eq!(response_mti("0100").unwrap(), "0110");
+        assert_eq!(response_mti("0120").unwrap(), "0130");
+        assert_eq!(response_mti("0200").unwrap(), "0210");
+        assert!(response_mti("0190").is_err());
+    }
+
+    #[test]
+    fn approve_without_rules() {
+        let standin = StandIn::new(StandInConfig::default());
+
+        let resp = standin.authorize(&request(1, 100, "5411")).unwrap();
+        assert_eq!(resp.mti(), "0110");
+        assert_eq!(resp.auth_serno, 1);
+        assert_eq!(resp.reason, 8100);
+        assert!(standin.records()[0].approved);
+    }
+
+    #[test]
+    fn amount_limit() {
+        let standin = StandIn::new(StandInConfig {
+            rules: vec![
+                Rule::AmountLimit {
+                    currency: 643,
+                    max: 1000,
+                    reason: Some(8116),
+                },
+                Rule::AmountLimit {
+                    currency: 978,
+                    max: 10,
+                    reason: None,
+                },
+            ],
+            ..Default::default()
+        });
+
+        assert_eq!(
+            standin.authorize(&request(1, 1000, "5411")).unwrap().reason,
+            8100
+        );
+        assert_eq!(
+            standin.authorize(&request(2, 1001, "5411")).unwrap().reason,
+            8116
+        );
+
+        let mut req = request(3, 11, "5411");
+        req.tags.insert(2, "978".into());
+        assert_eq!(standin.authorize(&req).unwrap().reason, 8495);
+        assert_eq!(standin.records()[2].rule, Some(1));
+    }
+
+    #[test]
+    fn mcc_lists() {
+        let standin = StandIn::new(StandInConfig {
+            rules: vec![
+                Rule::MccDeny {
+                    mccs: vec!["7995".to_string()].into_iter().collect(),
+                    reason: None,
+                },
+                Rule::MccAllow {
+                    mccs: vec!["5411".to_string(), "7995".to_string()]
+                        .into_iter()
+                        .collect(),
+                    reason: Some(8120),
+                },
+            ],
+            ..Default::default()
+        });
+
+        assert_eq!(
+            standin.authorize(&request(1, 1, "5411")).unwrap().reason,
+            8100
+        );
+        assert_eq!(
+            standin.authorize(&request(2, 1, "7995")).unwrap().reason,
+            8495
+        );
+        assert_eq!(
+            standin.authorize(&request(3, 1, "6011")).unwrap().reason,
+            8120
+        );
+    }
+
+    #[test]
+    fn velocity() {
+        let standin = StandIn::new(StandInConfig {
+            rules: vec![Rule::Velocity {
+                window: Duration::from_secs(60),
+                max_count: 2,
+                max_amount: Some(250),
+                reason: Some(8165),
+            }],
+            ..Default::default()
+        });
+        let start = Instant::now();
+
+        let decide = |serno, amount, secs| {
+            standin
+                .authorize_at(
+                    &request(serno, amount, "5411"),
+                    start + Duration::from_secs(secs),
+                )
+                .unwrap()
+                .reason
+        };
+
+        assert_eq!(decide(1, 100, 0), 8100);
+        // Amount limit is exceeded, declines are not counted
+        assert_eq!(decide(2, 200, 1), 8165);
+        assert_eq!(decide(3, 100, 2), 8100);
+        // Count limit is exceeded
+        assert_eq!(decide(4, 1, 3), 8165);
+        // The first approval is out of the window
+        assert_eq!(decide(5, 1, 61), 8100);
+    }
+
+    #[test]
+    fn velocity_of_short_pans() {
+        let standin = StandIn::new(StandInConfig {
+            rules: vec![Rule::Velocity {
+                window: Duration::from_secs(60),
+                max_count: 1,
+                max_amount: None,
+                reason: Some(8165),
+            }],
+            ..Default::default()
+        });
+        let decide = |serno, pan: &str| {
+            let mut req = request(serno, 100, "5411");
+            req.iso_fields.insert(PAN_FIELD, pan.into());
+            standin.authorize(&req).unwrap().reason
+        };
+
+        // Counters of different 10 and 11 digit PANs are kept apart
+        assert_eq!(decide(1, "5555441111"), 8100);
+        assert_eq!(decide(2, "5555442222"), 8100);
+        assert_eq!(decide(3, "55554412222"), 8100);
+        assert_eq!(decide(4, "5555441111"), 8165);
+    }
+
+    #[test]
+    fn advices_for_replay() {
+        let standin = StandIn::new(StandInConfig::default());
+        standin.authorize(&request(1, 100, "5411")).unwrap();
+
+        let advices = standin.take_advices().unwrap();
+        assert!(standin.records().is_empty());
+        assert_eq!(advices.len(), 1);
+        assert_eq!(advices[0].mti(), "0120");
+        assert_eq!(advices[0].saf(), "Y");
+        assert_eq!(advices[0].auth_serno, 1);
+        assert_eq!(advices[0].tags.get(&STANDIN_REASON_TAG).unwrap(), "8100");
+    }
+
+    #[test]
+    fn incorrect_amount() {
+        let standin = StandIn::new(StandInConfig::default());
+        let mut req = request(1, 1, "5411");
+        req.tags.insert(3, "ABC".into());
+
+        assert!(standin.authorize(&req).is_err());
+        assert!(standin.records().is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+//! Events of the `tracing` feature.
+//!
+//! Only MTI, serno, lengths, errors and warnings are logged, never field data: errors and
+//! warnings name fields but do not carry their values.
+
+use bytes::Bytes;
+use tracing::{debug, warn, Span};
+
+use crate::{DecodeWarning, Error};
+
+pub(crate) fn encoded(result: &Result<Bytes, Error>) {
+    match result {
+        Ok(data) => debug!(length = data.len(), "Encoded"),
+        Err(err) => warn!(error = %err, "Encoding failed"),
+    }
+}
+
+/// Records MTI and serno into the current span, which has to declare them.
+pub(crate) fn decoded(mti: &str, auth_serno: u64, warnings: &[DecodeWarning]) {
+    let span = Span::current();
+    span.record("mti", mti);
+    span.record("auth_serno", auth_serno);
+    for warning in warnings {
+        warn!(%warning, "Decoded leniently");
+    }
+    debug!(warnings = warnings.len(), "Decoded");
+}
+
+pub(crate) fn decode_failed(err: &Error) {
+    warn!(error = %err, "Decoding failed");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use crate::{SigmaRequest, SigmaResponse};
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture(f: impl FnOnce()) -> String {
+        let output = Output::default();
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+        let v = output.0.lock().unwrap().clone();
+        String::from_utf8(v).unwrap()
+    }
+
+    #[test]
+    fn no_sensitive_data() {
+        let mut req = SigmaRequest::new("N", "M", "0100", 4007040978).unwrap();
+        req.iso_fields.insert(2, "4111111111111111".into());
+        req.tags.insert(18, "SECRET".into());
+
+        let logs = capture(|| {
+            let data = req.encode().unwrap();
+            SigmaRequest::decode(data).unwrap();
+        });
+        assert!(logs.contains("sigma_request_encode"));
+        assert!(logs.contains("sigma_request_decode"));
+        assert!(logs.contains("mti=0100"));
+        assert!(logs.contains("auth_serno=4007040978"));
+        assert!(logs.contains("length=55"));
+        assert!(!logs.contains("4111111111111111"));
+        assert!(!logs.contains("SECRET"));
+
+        let logs = capture(|| {
+            assert!(
+                SigmaResponse::decode(bytes::Bytes::from_static(b"000140110400704097x")).is_err()
+            );
+        });
+        assert!(logs.contains("Decoding failed"));
+        assert!(logs.contains("Serno"));
+    }
+}
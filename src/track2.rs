@@ -0,0 +1,361 @@
+//! Track 2 equivalent data (ISO field 35).
+
+use std::fmt;
+
+use crate::pan::{mask_pan, Pan, MASK_CHAR};
+use crate::{Error, SigmaRequest};
+
+/// ISO field which carries track 2 data.
+pub const TRACK2_FIELD: u16 = 35;
+
+pub const MAX_TRACK2_LENGTH: usize = 37;
+
+fn incorrect(should_be: &str) -> Error {
+    Error::incorrect_field_data("Track 2", should_be)
+}
+
+/// Card expiry date, YYMM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Expiry {
+    year: u8,
+    month: u8,
+}
+
+impl Expiry {
+    /// Expiry date of 2-digit year and month.
+    pub fn new(year: u8, month: u8) -> Result<Self, Error> {
+        if year > 99 || !(1..=12).contains(&month) {
+            return Err(incorrect("expiry date YYMM"));
+        }
+        Ok(Self { year, month })
+    }
+
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let b = s.as_bytes();
+        if b.len() != 4 || !b.iter().all(u8::is_ascii_digit) {
+            return Err(incorrect("expiry date YYMM"));
+        }
+        Self::new(
+            (b[0] - b'0') * 10 + b[1] - b'0',
+            (b[2] - b'0') * 10 + b[3] - b'0',
+        )
+    }
+
+    /// 2-digit year.
+    pub fn year(&self) -> u8 {
+        self.year
+    }
+
+    /// Full year, assuming the 21st century.
+    pub fn full_year(&self) -> u16 {
+        2000 + self.year as u16
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    /// Whether the card is expired at given full year and month.
+    /// A card is valid through the whole month of its expiry.
+    pub fn is_expired_at(&self, year: u16, month: u8) -> bool {
+        (self.full_year(), self.month) < (year, month)
+    }
+}
+
+impl fmt::Display for Expiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}{:02}", self.year, self.month)
+    }
+}
+
+/// 3-digit service code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ServiceCode([u8; 3]);
+
+impl ServiceCode {
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let b = s.as_bytes();
+        if b.len() != 3 || !b.iter().all(u8::is_ascii_digit) {
+            return Err(incorrect("3 digit service code"));
+        }
+        Ok(Self([b[0] - b'0', b[1] - b'0', b[2] - b'0']))
+    }
+
+    /// Digits of the service code.
+    pub fn digits(&self) -> [u8; 3] {
+        self.0
+    }
+
+    /// Whether the card has a chip (the first digit is 2 or 6).
+    pub fn has_chip(&self) -> bool {
+        matches!(self.0[0], 2 | 6)
+    }
+
+    /// Whether the card is for international use (the first digit is 1 or 2).
+    pub fn is_international(&self) -> bool {
+        matches!(self.0[0], 1 | 2)
+    }
+
+    /// Whether transactions have to be authorized online (the second digit is 2).
+    pub fn requires_online_authorization(&self) -> bool {
+        self.0[1] == 2
+    }
+
+    /// Whether PIN is required (the third digit is 0, 3 or 5).
+    pub fn requires_pin(&self) -> bool {
+        matches!(self.0[2], 0 | 3 | 5)
+    }
+}
+
+impl fmt::Display for ServiceCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+/// Separator between PAN and the rest of track 2 data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Separator {
+    /// `=`, used on magnetic stripe.
+    #[default]
+    Equals,
+    /// `D`, used in hexadecimal representation of track 2 equivalent data.
+    D,
+}
+
+impl Separator {
+    pub fn as_char(&self) -> char {
+        match self {
+            Self::Equals => '=',
+            Self::D => 'D',
+        }
+    }
+}
+
+/// Parsed track 2 data.
+///
+/// `Debug` and `Display` implementations show the masked form only, use
+/// [`Track2::encode`] to get the data itself.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Track2 {
+    pub pan: Pan,
+    pub expiry: Expiry,
+    pub service_code: ServiceCode,
+    pub discretionary_data: String,
+    pub separator: Separator,
+}
+
+impl Track2 {
+    pub fn new(pan: Pan, expiry: Expiry, service_code: ServiceCode) -> Self {
+        Self {
+            pan,
+            expiry,
+            service_code,
+            discretionary_data: String::new(),
+            separator: Separator::default(),
+        }
+    }
+
+    pub fn with_discretionary_data(mut self, v: impl Into<String>) -> Self {
+        self.discretionary_data = v.into();
+        self
+    }
+
+    pub fn with_separator(mut self, v: Separator) -> Self {
+        self.separator = v;
+        self
+    }
+
+    /// Parses track 2 data, optionally surrounded by `;` and `?` sentinels.
+    /// Trailing `F` padding of the discretionary data is kept as is.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let s = s.trim();
+        let s = s.strip_prefix(';').unwrap_or(s);
+        let s = s.strip_suffix('?').unwrap_or(s);
+        if s.len() > MAX_TRACK2_LENGTH {
+            return Err(incorrect("at most 37 characters"));
+        }
+
+        let (pos, separator) = s
+            .char_indices()
+            .find_map(|(i, c)| match c {
+                '=' => Some((i, Separator::Equals)),
+                'D' | 'd' => Some((i, Separator::D)),
+                _ => None,
+            })
+            .ok_or_else(|| incorrect("PAN followed by '=' or 'D' separator"))?;
+
+        let pan = Pan::parse(&s[..pos])?;
+        let rest = &s[pos + 1..];
+        if rest.len() < 7 || !rest.is_char_boundary(7) {
+            return Err(incorrect("expiry date and service code after separator"));
+        }
+        let expiry = Expiry::parse(&rest[..4])?;
+        let service_code = ServiceCode::parse(&rest[4..7])?;
+
+        let discretionary_data = &rest[7..];
+        let digits = discretionary_data.trim_end_matches(['F', 'f']);
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(incorrect("digits of discretionary data"));
+        }
+
+        Ok(Self {
+            pan,
+            expiry,
+            service_code,
+            discretionary_data: discretionary_data.into(),
+            separator,
+        })
+    }
+
+    /// Track 2 data without sentinels.
+    pub fn encode(&self) -> String {
+        format!(
+            "{}{}{}{}{}",
+            self.pan.as_str(),
+            self.separator.as_char(),
+            self.expiry,
+            self.service_code,
+            self.discretionary_data
+        )
+    }
+
+    /// Track 2 data with PAN masked and everything after the separator masked completely.
+    pub fn masked(&self) -> String {
+        mask_track2(&self.encode())
+    }
+}
+
+/// Masks track 2 like string: PAN as in [`mask_pan`] and everything after
+/// the separator completely. Strings without separator are masked completely.
+pub fn mask_track2(s: &str) -> String {
+    match s.find(['=', 'D', 'd']) {
+        Some(pos) => format!(
+            "{}{}{}",
+            mask_pan(&s[..pos]),
+            &s[pos..pos + 1],
+            MASK_CHAR.to_string().repeat(s[pos + 1..].chars().count())
+        ),
+        None => MASK_CHAR.to_string().repeat(s.chars().count()),
+    }
+}
+
+impl fmt::Debug for Track2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Track2").field(&self.masked()).finish()
+    }
+}
+
+impl fmt::Display for Track2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.masked())
+    }
+}
+
+impl std::str::FromStr for Track2 {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl SigmaRequest {
+    /// Track 2 data (i035), if present.
+    pub fn track2(&self) -> Result<Option<Track2>, Error> {
+        self.iso_fields
+            .get(&TRACK2_FIELD)
+            .map(|v| Track2::parse(&v.to_cow_str_lossy()))
+            .transpose()
+    }
+
+    pub fn set_track2(&mut self, v: &Track2) {
+        self.iso_fields.insert(TRACK2_FIELD, v.encode().into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let track2 = Track2::parse("4111111111111111=25122011234500000").unwrap();
+        assert_eq!(track2.pan.as_str(), "4111111111111111");
+        assert_eq!(track2.expiry, Expiry::new(25, 12).unwrap());
+        assert_eq!(track2.expiry.full_year(), 2025);
+        assert_eq!(track2.service_code.to_string(), "201");
+        assert!(track2.service_code.has_chip());
+        assert!(track2.service_code.is_international());
+        assert!(!track2.service_code.requires_online_authorization());
+        assert!(!track2.service_code.requires_pin());
+        assert_eq!(track2.discretionary_data, "1234500000");
+        assert_eq!(track2.separator, Separator::Equals);
+        assert_eq!(track2.encode(), "4111111111111111=25122011234500000");
+
+        let track2 = Track2::parse(";5555441234561111D2612101F?").unwrap();
+        assert_eq!(track2.separator, Separator::D);
+        assert_eq!(track2.service_code.digits(), [1, 0, 1]);
+        assert_eq!(track2.discretionary_data, "F");
+        assert_eq!(track2.encode(), "5555441234561111D2612101F");
+
+        let track2 = Track2::parse("555544******1111=2612101").unwrap();
+        assert!(track2.pan.is_masked());
+    }
+
+    #[test]
+    fn incorrect() {
+        assert!(Track2::parse("4111111111111111").is_err());
+        assert!(Track2::parse("41111=2512201").is_err());
+        assert!(Track2::parse("4111111111111111=251").is_err());
+        assert!(Track2::parse("4111111111111111=2513201").is_err());
+        assert!(Track2::parse("4111111111111111=25122A1").is_err());
+        assert!(Track2::parse("4111111111111111=2512201X").is_err());
+        assert!(Track2::parse("4111111111111111=2512201123456789012345").is_err());
+    }
+
+    #[test]
+    fn build_and_mask() {
+        let track2 = Track2::new(
+            "4111111111111111".parse().unwrap(),
+            Expiry::parse("2512").unwrap(),
+            ServiceCode::parse("101").unwrap(),
+        )
+        .with_discretionary_data("123")
+        .with_separator(Separator::D);
+        assert_eq!(track2.encode(), "4111111111111111D2512101123");
+        assert_eq!(track2.masked(), "411111******1111D**********");
+        assert_eq!(
+            format!("{:?}", track2),
+            "Track2(\"411111******1111D**********\")"
+        );
+
+        assert_eq!(mask_track2("4111111111111111"), "****************");
+        assert_eq!(mask_track2("41111=25"), "*****=**");
+    }
+
+    #[test]
+    fn expiry() {
+        let expiry = Expiry::parse("2512").unwrap();
+        assert!(!expiry.is_expired_at(2025, 12));
+        assert!(expiry.is_expired_at(2026, 1));
+        assert_eq!(expiry.to_string(), "2512");
+        assert!(Expiry::parse("2500").is_err());
+    }
+
+    #[test]
+    fn request_track2() {
+        let mut req = SigmaRequest::new("N", "M", "0100", 1).unwrap();
+        assert_eq!(req.track2().unwrap(), None);
+
+        req.set_track2(&"4111111111111111=2512201123".parse().unwrap());
+        assert_eq!(
+            req.track2().unwrap().unwrap().expiry,
+            Expiry::new(25, 12).unwrap()
+        );
+        assert_eq!(
+            req.masked().iso_fields.get(&35).unwrap(),
+            "411111******1111=**********"
+        );
+    }
+}
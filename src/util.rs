@@ -31,6 +31,11 @@ pub fn gen_random_auth_serno() -> u64 {
     rrn
 }
 
+/// Generate Authorization Serno which is not trimmed on encoding
+pub(crate) fn gen_random_short_auth_serno() -> u64 {
+    gen_random_auth_serno() % 10_000_000_000
+}
+
 pub(crate) fn decode_bcd_x2(v: u8) -> Result<u8, Error> {
     let left = v >> 4;
     if !matches!(left, 0..=9) {
@@ -118,7 +123,9 @@ impl Tag {
 
     pub fn decode(data: Bytes) -> Result<Self, Error> {
         if data.len() < 4 {
-            return Err(Error::IncorrectTag("Should be 5 bytes long".into()));
+            return Err(Error::IncorrectTag(
+                "Should be at least 4 bytes long".into(),
+            ));
         }
         let i = decode_bcd_x4(&[data[1], data[2]])?;
         let si = decode_bcd_x2(data[3])?;
@@ -130,6 +137,7 @@ impl Tag {
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<Self, Error> {
         let bytes = s.as_bytes();
         match (bytes.first(), s.len()) {
@@ -171,6 +179,14 @@ impl Tag {
     }
 }
 
+impl std::str::FromStr for Tag {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Tag::from_str(s)
+    }
+}
+
 impl Display for Tag {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         match self {
@@ -188,15 +204,20 @@ pub fn encode_field_to_buf(tag: Tag, data: &[u8], buf: &mut BytesMut) -> Result<
     Ok(())
 }
 
-pub fn decode_field_from_cursor(buf: &mut Bytes) -> Result<(Tag, Bytes), Error> {
+/// Splits tag bytes and data of the next field off the buffer.
+pub fn split_field_from_cursor(buf: &mut Bytes) -> Result<(Bytes, Bytes), Error> {
     let tag_src = bytes_split_to(buf, 4)?;
-    let tag = Tag::decode(tag_src)?;
 
     let len_src = bytes_split_to(buf, 2)?;
     let len = decode_bcd_x4(&[len_src[0], len_src[1]])?;
 
     let data = bytes_split_to(buf, len as usize)?;
-    Ok((tag, data))
+    Ok((tag_src, data))
+}
+
+pub fn decode_field_from_cursor(buf: &mut Bytes) -> Result<(Tag, Bytes), Error> {
+    let (tag_src, data) = split_field_from_cursor(buf)?;
+    Ok((Tag::decode(tag_src)?, data))
 }
 
 #[cfg(test)]